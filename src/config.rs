@@ -1,6 +1,6 @@
-use std::time::Duration;
+use std::{collections::BTreeMap, num::NonZeroU64, time::Duration};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::{
     crate_authors, crate_description, crate_license, crate_name, crate_version, App, AppSettings,
     Arg, ValueHint,
@@ -8,28 +8,39 @@ use clap::{
 use config::File;
 use serde::Deserialize;
 
-use crate::exporters::database;
-#[cfg(feature = "http")]
-use crate::exporters::http;
-#[cfg(feature = "twitter")]
-use crate::exporters::twitter;
+use crate::{
+    exporters::{self, database, Instance},
+    monitor::ShutdownConfig,
+    probe::{self, DEFAULT_NAME},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    #[serde(with = "humantime_serde", alias = "Period")]
-    pub(crate) period: Duration,
-    #[serde(alias = "Measurer")]
-    pub(crate) measurer: Option<String>,
-    #[serde(default, alias = "StdOut", alias = "STDOUT")]
-    pub(crate) stdout: bool,
-    #[cfg(feature = "twitter")]
-    #[serde(rename = "twitter", alias = "Twitter")]
-    pub(crate) twitter_config: Option<twitter::Config>,
-    #[cfg(feature = "http")]
-    #[serde(rename = "http", alias = "HTTP")]
-    pub(crate) http_config: Option<http::Config>,
+    /// The default probe, configured at the top level; only used if no named probes are
+    /// configured.
+    #[serde(flatten)]
+    pub(crate) probe_config: probe::Config,
+    /// Named probes, each with its own schedule, measurer and exporter routing.
+    #[serde(default, alias = "Probes")]
+    pub(crate) probes: BTreeMap<String, probe::Config>,
+    /// The minimum time since the beginning of the latest round for an on-demand one to begin.
+    #[serde(default, with = "humantime_serde", alias = "MinTriggerGap")]
+    pub(crate) min_trigger_gap: Option<Duration>,
+    /// Named exporters, each configured as `[exporter.<name>]`.
+    #[serde(default, alias = "Exporter")]
+    pub(crate) exporter: BTreeMap<String, serde_json::Value>,
     #[serde(rename = "database", alias = "db", alias = "Database")]
     pub(crate) db_config: Option<database::Config>,
+    #[serde(default, rename = "shutdown", alias = "Shutdown")]
+    pub(crate) shutdown_config: ShutdownConfig,
+    /// The number of scheduled rounds to run before exiting (configured through the command
+    /// line); unbounded if absent.
+    #[serde(skip)]
+    pub(crate) rounds: Option<NonZeroU64>,
+    /// All other top-level keys; those named after a kind of exporter, case-insensitively (e.g.,
+    /// `[http]` or `[HTTP]`), configure an exporter of that kind by the same (lowercase) name.
+    #[serde(flatten)]
+    pub(crate) sections: BTreeMap<String, serde_json::Value>,
 }
 
 impl Config {
@@ -52,11 +63,35 @@ impl Config {
                     .value_hint(ValueHint::FilePath)
                     .long_about("Path to configuration file. Examples are available in the examples directory."), // TODO
             )
+            .arg(
+                Arg::new("once")
+                    .long("once")
+                    .about("Run a single round of measuring and reporting, and then exit")
+                    .conflicts_with("count"),
+            )
+            .arg(
+                Arg::new("count")
+                    .long("count")
+                    .about("Run N rounds of measuring and reporting, and then exit")
+                    .takes_value(true)
+                    .value_name("N")
+                    .validator(|n| n.parse::<NonZeroU64>()),
+            )
             .long_about(crate_description!()) // TODO
             .get_matches();
 
         let config_path = matches.value_of("config").unwrap(); // SAFETY checked by clap
+        let mut config = Self::from_file(config_path)?;
+        config.rounds = if matches.is_present("once") {
+            NonZeroU64::new(1)
+        } else {
+            matches.value_of("count").map(str::parse).transpose()? // SAFETY validated by clap
+        };
+        Ok(config)
+    }
 
+    /// Reads the configuration out of the file at the given path.
+    fn from_file(config_path: &str) -> Result<Self> {
         let mut c = ::config::Config::default();
         c.merge(File::with_name(config_path).required(true))
             .with_context(|| format!("failed to merge config file '{}'", config_path))?;
@@ -65,4 +100,70 @@ impl Config {
             "failed to convert '::config::Config' to 'netspeedmon::config::Config'"
         })
     }
+
+    /// Returns the configuration of all probes to run, by name.
+    pub(crate) fn probes(&self) -> Result<Vec<(String, probe::Config)>> {
+        if self.probes.is_empty() {
+            return Ok(vec![(DEFAULT_NAME.to_owned(), self.probe_config.clone())]);
+        }
+        if self.probe_config.schedule_config.is_configured() {
+            bail!(
+                "a top-level 'period' or 'schedule' may not be configured along with named probes"
+            );
+        }
+        Ok(self
+            .probes
+            .iter()
+            .map(|(name, config)| (name.clone(), config.clone()))
+            .collect())
+    }
+
+    /// Returns the configuration of all exporters to run (apart from the Database), by name.
+    pub(crate) fn exporters(&self) -> Result<BTreeMap<String, Instance>> {
+        exporters::instances(&self.sections, &self.exporter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exporters(path: &str) -> Result<BTreeMap<String, Instance>> {
+        Config::from_file(path).unwrap().exporters()
+    }
+
+    /// All example configurations are valid, apart from those that configure exporters whose
+    /// Cargo feature is not enabled.
+    #[test]
+    fn examples() {
+        for entry in std::fs::read_dir("conf").unwrap() {
+            let path = entry.unwrap().path();
+            let path = path.to_str().unwrap();
+            Config::from_file(path).unwrap().probes().unwrap();
+            if let Err(e) = exporters(path) {
+                assert!(
+                    format!("{}", e).contains("Cargo feature"),
+                    "{}: {}",
+                    path,
+                    e
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sections() {
+        assert!(exporters("conf/stdout.json")
+            .unwrap()
+            .contains_key("stdout"));
+        #[cfg(feature = "http")]
+        assert!(exporters("conf/default.json").unwrap().contains_key("http"));
+        #[cfg(all(feature = "http", feature = "twitter"))]
+        {
+            let all = exporters("conf/all.toml").unwrap();
+            assert!(all.contains_key("http") && all.contains_key("twitter"));
+        }
+        #[cfg(not(feature = "twitter"))]
+        assert!(exporters("conf/all.toml").is_err());
+    }
 }
@@ -0,0 +1,131 @@
+use anyhow::{Context as _, Result};
+use async_trait::async_trait;
+use reqwest::{
+    multipart::{Form, Part},
+    Client, Url,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, trace};
+
+use super::{Chat, Message, Platform, Secret, PLOT_ATTACHMENT, TIMEOUT};
+use crate::exporters::Context;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The URL of the webhook to post messages to.
+    #[serde(alias = "WebhookUrl")]
+    webhook_url: Secret,
+    /// The name to post messages as, instead of the webhook's default one.
+    username: Option<String>,
+    #[serde(flatten)]
+    chat: super::Config,
+}
+
+/// Posts messages through a Discord webhook.
+#[derive(Debug)]
+pub(crate) struct Discord {
+    client: Client,
+    webhook_url: Secret,
+    username: Option<String>,
+}
+
+impl Discord {
+    #[tracing::instrument(skip(ctx))]
+    pub(crate) fn new(config: Config, ctx: &Context) -> Result<Chat> {
+        trace!("Creating new '{}'...", std::any::type_name::<Self>());
+        Url::parse(&config.webhook_url.0).with_context(|| "invalid 'webhook_url'")?;
+        let client = Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .with_context(|| "failed to build the HTTP client")?;
+        let discord = Self {
+            client,
+            webhook_url: config.webhook_url,
+            username: config.username,
+        };
+        Ok(Chat::new(config.chat, Box::new(discord), ctx))
+    }
+
+    /// The payload of the webhook request for the given message: a coloured embed, with the
+    /// values as inline fields, and the plot as its image, if attached.
+    fn payload(&self, message: &Message, plot: bool) -> Value {
+        let mut embed = json!({
+            "title": format!("{} {}", message.health.emoji(), message.title),
+            "color": message.health.colour(),
+            "fields": message
+                .fields
+                .iter()
+                .map(|(name, value)| json!({ "name": name, "value": value, "inline": true }))
+                .collect::<Vec<_>>(),
+            "timestamp": chrono::Local::now().to_rfc3339(),
+        });
+        if !message.reasons.is_empty() {
+            embed["description"] = message.reasons.join("\n").into();
+        }
+        if plot {
+            embed["image"] = json!({ "url": format!("attachment://{}", PLOT_ATTACHMENT) });
+        }
+        let mut payload = json!({ "embeds": [embed] });
+        if let Some(ref username) = self.username {
+            payload["username"] = username.as_str().into();
+        }
+        payload
+    }
+}
+
+#[async_trait]
+impl Platform for Discord {
+    async fn post(&self, message: &Message, plot: Option<Vec<u8>>) -> Result<()> {
+        let payload = self.payload(message, plot.is_some());
+        let request = self.client.post(&self.webhook_url.0);
+        // Files can only be attached to multipart requests
+        let request = match plot {
+            Some(plot) => {
+                let plot = Part::bytes(plot)
+                    .file_name(PLOT_ATTACHMENT)
+                    .mime_str("image/png")?;
+                let form = Form::new()
+                    .text("payload_json", payload.to_string())
+                    .part("files[0]", plot);
+                request.multipart(form)
+            }
+            None => request.json(&payload),
+        };
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| "failed to post to the Discord webhook")?;
+        debug!("Posted message to Discord");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::chat::Health;
+
+    #[test]
+    fn payload() {
+        let discord = Discord {
+            client: Client::new(),
+            webhook_url: Secret("https://discord.com/api/webhooks/1/x".to_owned()),
+            username: Some("netspeedmon".to_owned()),
+        };
+        let message = Message {
+            title: "netspeedmon: wan is ok".to_owned(),
+            health: Health::Ok,
+            reasons: Vec::new(),
+            fields: vec![("Download", "94.25 Mbit/s".to_owned())],
+        };
+        let payload = discord.payload(&message, true);
+        let embed = &payload["embeds"][0];
+        assert_eq!(embed["color"], 0x2e_b6_7d);
+        assert_eq!(embed["fields"][0]["value"], "94.25 Mbit/s");
+        assert_eq!(embed["image"]["url"], "attachment://netspeedmon.png");
+        assert!(embed.get("description").is_none());
+        assert_eq!(payload["username"], "netspeedmon");
+    }
+}
@@ -0,0 +1,224 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{bail, Context as _, Result};
+use async_trait::async_trait;
+use reqwest::{header, Client, Url};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, trace};
+
+use super::{Chat, Message, Platform, Secret, PLOT_ATTACHMENT, TIMEOUT};
+use crate::exporters::Context;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The base URL of the homeserver (e.g., `https://matrix.example.com`).
+    homeserver: String,
+    /// The access token of the user to post messages as.
+    #[serde(alias = "AccessToken")]
+    access_token: Secret,
+    /// The ID (not an alias) of the room to post messages to (e.g., `!abcdef:example.com`).
+    #[serde(alias = "RoomId")]
+    room_id: String,
+    #[serde(flatten)]
+    chat: super::Config,
+}
+
+/// Posts messages to a room, through the Matrix client-server API.
+#[derive(Debug)]
+pub(crate) struct Matrix {
+    client: Client,
+    homeserver: Url,
+    access_token: Secret,
+    room_id: String,
+    /// The prefix of the transaction IDs of events, which must be unique per access token.
+    txn_prefix: i64,
+    /// The number of events sent so far, which makes up the rest of their transaction IDs.
+    txn_count: AtomicU64,
+}
+
+impl Matrix {
+    #[tracing::instrument(skip(ctx))]
+    pub(crate) fn new(config: Config, ctx: &Context) -> Result<Chat> {
+        trace!("Creating new '{}'...", std::any::type_name::<Self>());
+        let homeserver = Url::parse(&config.homeserver)
+            .with_context(|| format!("invalid 'homeserver' '{}'", config.homeserver))?;
+        if homeserver.cannot_be_a_base() {
+            bail!("the homeserver URL cannot be a base URL");
+        }
+        if !config.room_id.starts_with('!') {
+            bail!("'room_id' must be a room ID (i.e., start with '!'), rather than an alias");
+        }
+        let client = Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .with_context(|| "failed to build the HTTP client")?;
+        let matrix = Self {
+            client,
+            homeserver,
+            access_token: config.access_token,
+            room_id: config.room_id,
+            txn_prefix: chrono::Local::now().timestamp_millis(),
+            txn_count: AtomicU64::new(0),
+        };
+        Ok(Chat::new(config.chat, Box::new(matrix), ctx))
+    }
+
+    /// The URL of the given endpoint (as path segments) of the homeserver.
+    fn url(&self, path: &[&str]) -> Url {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .expect("the homeserver URL cannot be a base URL")
+            .pop_if_empty()
+            .extend(path);
+        url
+    }
+
+    /// The content of the `m.room.message` event for the given message: its text, along with its
+    /// HTML rendering, with the title coloured by health and the values as a list.
+    fn content(message: &Message) -> Value {
+        let mut html = format!(
+            "<p><strong><font color=\"#{:06x}\" data-mx-color=\"#{:06x}\">{} {}</font></strong></p>",
+            message.health.colour(),
+            message.health.colour(),
+            message.health.emoji(),
+            escape(&message.title)
+        );
+        for reason in message.reasons.iter() {
+            html.push_str(&format!("<p><em>{}</em></p>", escape(reason)));
+        }
+        if !message.fields.is_empty() {
+            html.push_str("<ul>");
+            for (name, value) in message.fields.iter() {
+                html.push_str(&format!(
+                    "<li><strong>{}:</strong> {}</li>",
+                    name,
+                    escape(value)
+                ));
+            }
+            html.push_str("</ul>");
+        }
+        json!({
+            "msgtype": "m.text",
+            "body": message.text(),
+            "format": "org.matrix.custom.html",
+            "formatted_body": html,
+        })
+    }
+
+    /// Sends an `m.room.message` event with the given content to the room.
+    async fn send(&self, content: &Value) -> Result<()> {
+        let txn_id = format!(
+            "netspeedmon.{}.{}",
+            self.txn_prefix,
+            self.txn_count.fetch_add(1, Ordering::Relaxed)
+        );
+        let url = self.url(&[
+            "_matrix",
+            "client",
+            "v3",
+            "rooms",
+            &self.room_id,
+            "send",
+            "m.room.message",
+            &txn_id,
+        ]);
+        self.client
+            .put(url)
+            .bearer_auth(&self.access_token.0)
+            .json(content)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())?;
+        Ok(())
+    }
+
+    /// Uploads the given plot to the content repository, returning its `mxc://` URI.
+    async fn upload(&self, plot: Vec<u8>) -> Result<String> {
+        let mut url = self.url(&["_matrix", "media", "v3", "upload"]);
+        url.query_pairs_mut()
+            .append_pair("filename", PLOT_ATTACHMENT);
+        let response: Value = self
+            .client
+            .post(url)
+            .bearer_auth(&self.access_token.0)
+            .header(header::CONTENT_TYPE, "image/png")
+            .body(plot)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())?
+            .json()
+            .await?;
+        match response["content_uri"].as_str() {
+            Some(uri) => Ok(uri.to_owned()),
+            None => bail!("unexpected response: {}", response),
+        }
+    }
+}
+
+#[async_trait]
+impl Platform for Matrix {
+    async fn post(&self, message: &Message, plot: Option<Vec<u8>>) -> Result<()> {
+        self.send(&Self::content(message))
+            .await
+            .with_context(|| "failed to send message to the Matrix room")?;
+        debug!("Posted message to Matrix");
+        if let Some(plot) = plot {
+            let size = plot.len();
+            let uri = self
+                .upload(plot)
+                .await
+                .with_context(|| "failed to upload the plot to Matrix")?;
+            let content = json!({
+                "msgtype": "m.image",
+                "body": PLOT_ATTACHMENT,
+                "url": uri,
+                "info": { "mimetype": "image/png", "size": size },
+            });
+            self.send(&content)
+                .await
+                .with_context(|| "failed to send the plot to the Matrix room")?;
+        }
+        Ok(())
+    }
+}
+
+/// Escapes the characters that are special in HTML.
+fn escape(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::chat::Health;
+
+    #[test]
+    fn content() {
+        let message = Message {
+            title: "netspeedmon: wan is degraded".to_owned(),
+            health: Health::Degraded,
+            reasons: vec!["Suspect: <1 Mbit/s".to_owned()],
+            fields: vec![("Upload", "0.50 Mbit/s".to_owned())],
+        };
+        let content = Matrix::content(&message);
+        assert_eq!(
+            content["body"],
+            "⚠️ netspeedmon: wan is degraded\nSuspect: <1 Mbit/s\nUpload: 0.50 Mbit/s"
+        );
+        assert_eq!(
+            content["formatted_body"],
+            "<p><strong><font color=\"#ecb22e\" data-mx-color=\"#ecb22e\">⚠️ netspeedmon: wan is degraded</font></strong></p><p><em>Suspect: &lt;1 Mbit/s</em></p><ul><li><strong>Upload:</strong> 0.50 Mbit/s</li></ul>"
+        );
+    }
+}
@@ -0,0 +1,331 @@
+pub(super) mod discord;
+pub(super) mod matrix;
+pub(super) mod slack;
+
+use std::{collections::BTreeMap, fmt::Debug, path::PathBuf, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use tracing::{debug, trace, warn};
+
+use super::{Context, Exporter};
+use crate::{measure::Measurement, probe::DEFAULT_NAME};
+
+/// How long to wait for each request to a chat service to be responded to.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// The file name that the latest plot is attached as.
+const PLOT_ATTACHMENT: &str = "netspeedmon.png";
+
+/// The configuration shared by all chat exporters, flattened into each one's section.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// Which rounds to post about; every one of them, if absent.
+    notify: Option<Notify>,
+    /// The download speed (in Mbit/s) below which a round is degraded, if any.
+    #[serde(alias = "MinDownload")]
+    min_download: Option<f64>,
+    /// The upload speed (in Mbit/s) below which a round is degraded, if any.
+    #[serde(alias = "MinUpload")]
+    min_upload: Option<f64>,
+    /// The ping latency (in ms) above which a round is degraded, if any.
+    #[serde(alias = "MaxLatency")]
+    max_latency: Option<f64>,
+    /// Whether to attach the latest plot, if a Database is configured; true, if absent.
+    plot: Option<bool>,
+}
+
+/// A secret (e.g., a webhook URL, or an access token), which is kept out of logs.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+struct Secret(String);
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Which rounds to post about.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Notify {
+    /// Every round.
+    Always,
+    /// Each round whose health differs from that of the previous round of the same probe.
+    Change,
+    /// Each degraded or failed round, and the first healthy round after them.
+    Alerts,
+}
+
+/// How healthy a round is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Health {
+    Ok,
+    /// The round is suspect, or below the configured thresholds.
+    Degraded,
+    Failed,
+}
+
+impl Health {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Degraded => "degraded",
+            Self::Failed => "failed",
+        }
+    }
+
+    /// The colour to highlight messages about rounds of this health with.
+    fn colour(self) -> u32 {
+        match self {
+            Self::Ok => 0x2e_b6_7d,
+            Self::Degraded => 0xec_b2_2e,
+            Self::Failed => 0xe0_1e_5a,
+        }
+    }
+
+    fn emoji(self) -> &'static str {
+        match self {
+            Self::Ok => "✅",
+            Self::Degraded => "⚠️",
+            Self::Failed => "❌",
+        }
+    }
+}
+
+/// A message about a round, independently of how each chat service formats it.
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    title: String,
+    health: Health,
+    /// Why the round is degraded or failed, if it is.
+    reasons: Vec<String>,
+    /// Labelled values (e.g., `("Download", "94.25 Mbit/s")`).
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Message {
+    /// The message as plain text.
+    fn text(&self) -> String {
+        let mut ret = format!("{} {}", self.health.emoji(), self.title);
+        for reason in self.reasons.iter() {
+            ret.push_str(&format!("\n{}", reason));
+        }
+        for (name, value) in self.fields.iter() {
+            ret.push_str(&format!("\n{}: {}", name, value));
+        }
+        ret
+    }
+}
+
+/// A chat service that messages can be posted to.
+#[async_trait]
+trait Platform: Debug + Send + Sync {
+    /// Posts the given message, along with the given plot (as a PNG image), if any.
+    async fn post(&self, message: &Message, plot: Option<Vec<u8>>) -> Result<()>;
+}
+
+/// Posts messages about rounds to a chat service, according to the configured policy.
+#[derive(Debug)]
+pub(crate) struct Chat {
+    platform: Box<dyn Platform>,
+    config: Config,
+    plot_path: Option<PathBuf>,
+    /// The health of the previous round of each probe.
+    previous: BTreeMap<String, Health>,
+}
+
+impl Chat {
+    fn new(config: Config, platform: Box<dyn Platform>, ctx: &Context) -> Self {
+        trace!("Creating new '{}'...", std::any::type_name::<Self>());
+        let plot_path = match config.plot.unwrap_or(true) {
+            true => ctx.plot_path.clone(),
+            false => None,
+        };
+        Self {
+            platform,
+            config,
+            plot_path,
+            previous: BTreeMap::new(),
+        }
+    }
+
+    /// The health of the given measurement, along with why it is not healthy, if it is not.
+    fn health(&self, measurement: &Measurement) -> (Health, Vec<String>) {
+        if measurement.is_failed() {
+            return (Health::Failed, vec!["The round failed".to_owned()]);
+        }
+        let mut reasons: Vec<_> = measurement
+            .suspect
+            .iter()
+            .map(|reason| format!("Suspect: {}", reason))
+            .collect();
+        if let Some(min) = self.config.min_download {
+            if measurement.download_speed < min {
+                reasons.push(format!("Download is below {} Mbit/s", min));
+            }
+        }
+        if let Some(min) = self.config.min_upload {
+            if measurement.upload_speed < min {
+                reasons.push(format!("Upload is below {} Mbit/s", min));
+            }
+        }
+        if let Some(max) = self.config.max_latency {
+            if measurement.ping_latency > max {
+                reasons.push(format!("Latency is above {} ms", max));
+            }
+        }
+        match reasons.is_empty() {
+            true => (Health::Ok, reasons),
+            false => (Health::Degraded, reasons),
+        }
+    }
+
+    /// Whether to post about a round of the given health, given that of the previous round.
+    fn should_post(&self, health: Health, previous: Option<Health>) -> bool {
+        match self.config.notify.unwrap_or(Notify::Always) {
+            Notify::Always => true,
+            Notify::Change => previous != Some(health),
+            Notify::Alerts => {
+                health != Health::Ok || matches!(previous, Some(previous) if previous != Health::Ok)
+            }
+        }
+    }
+
+    fn message(&self, probe: &str, measurement: &Measurement) -> Message {
+        let (health, reasons) = self.health(measurement);
+        let mut fields = Vec::new();
+        if !measurement.is_failed() {
+            fields.extend(vec![
+                (
+                    "Download",
+                    format!("{:.2} Mbit/s", measurement.download_speed),
+                ),
+                ("Upload", format!("{:.2} Mbit/s", measurement.upload_speed)),
+                ("Latency", format!("{:.2} ms", measurement.ping_latency)),
+            ]);
+            if let Some(jitter) = measurement.jitter {
+                fields.push(("Jitter", format!("{:.2} ms", jitter)));
+            }
+        }
+        let known = [
+            ("Server", &measurement.server),
+            ("Measurer", &measurement.measurer),
+            ("Interface", &measurement.interface),
+        ];
+        for (name, value) in known.iter() {
+            if let Some(value) = value {
+                fields.push((name, value.clone()));
+            }
+        }
+        Message {
+            title: format!("netspeedmon: {} is {}", probe, health.name()),
+            health,
+            reasons,
+            fields,
+        }
+    }
+
+    /// Reads the latest plot, if it is to be attached.
+    async fn plot(&self) -> Option<Vec<u8>> {
+        let _plot_path = self.plot_path.as_ref()?;
+        #[cfg(feature = "plot")]
+        match tokio::fs::read(_plot_path).await {
+            Ok(png) => return Some(png),
+            Err(e) => warn!("Failed to read the latest plot image: {}", e),
+        }
+        None
+    }
+}
+
+#[async_trait]
+impl Exporter for Chat {
+    async fn on_measurement(&mut self, measurement: Measurement) {
+        if measurement.skipped.is_some() {
+            return;
+        }
+        let probe = measurement.probe.as_deref().unwrap_or(DEFAULT_NAME);
+        let message = self.message(probe, &measurement);
+        let previous = self.previous.insert(probe.to_owned(), message.health);
+        if !self.should_post(message.health, previous) {
+            debug!(
+                "Not posting about a round that is {}",
+                message.health.name()
+            );
+            return;
+        }
+        let plot = self.plot().await;
+        if let Err(e) = self.platform.post(&message, plot).await {
+            warn!("Failed to post message: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Nowhere;
+
+    #[async_trait]
+    impl Platform for Nowhere {
+        async fn post(&self, _: &Message, _: Option<Vec<u8>>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn chat(notify: Notify) -> Chat {
+        let config = Config {
+            notify: Some(notify),
+            min_download: Some(50.),
+            min_upload: None,
+            max_latency: Some(20.),
+            plot: None,
+        };
+        let ctx = Context {
+            name: "chat".to_owned(),
+            plot_path: None,
+            probes: Default::default(),
+            health: Default::default(),
+        };
+        Chat::new(config, Box::new(Nowhere), &ctx)
+    }
+
+    #[test]
+    fn message() {
+        let chat = chat(Notify::Always);
+        let measurement = Measurement {
+            server: Some("Athens".to_owned()),
+            ..(25., 94.25, 9.5).into()
+        };
+        let message = chat.message("wan", &measurement);
+        assert_eq!(message.health, Health::Degraded);
+        assert_eq!(
+            message.text(),
+            "⚠️ netspeedmon: wan is degraded\nLatency is above 20 ms\nDownload: 94.25 Mbit/s\nUpload: 9.50 Mbit/s\nLatency: 25.00 ms\nServer: Athens"
+        );
+        let message = chat.message("wan", &Measurement::default());
+        assert_eq!(message.health, Health::Failed);
+        assert!(message.fields.is_empty());
+    }
+
+    #[test]
+    fn should_post() {
+        use Health::*;
+
+        let rounds = [None, Some(Ok), Some(Ok), Some(Degraded), Some(Failed)];
+        let posts = |notify| -> Vec<_> {
+            let chat = chat(notify);
+            rounds
+                .windows(2)
+                .map(|w| chat.should_post(w[1].unwrap(), w[0]))
+                .chain(std::iter::once(chat.should_post(Ok, Some(Failed))))
+                .collect()
+        };
+        assert_eq!(posts(Notify::Always), [true, true, true, true, true]);
+        assert_eq!(posts(Notify::Change), [true, false, true, true, true]);
+        assert_eq!(posts(Notify::Alerts), [false, false, true, true, true]);
+    }
+}
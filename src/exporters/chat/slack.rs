@@ -0,0 +1,185 @@
+use anyhow::{bail, Context as _, Result};
+use async_trait::async_trait;
+use reqwest::{header, Client, Url};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, trace};
+
+use super::{Chat, Message, Platform, Secret, PLOT_ATTACHMENT, TIMEOUT};
+use crate::exporters::Context;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The URL of the incoming webhook to post messages to.
+    #[serde(alias = "WebhookUrl")]
+    webhook_url: Secret,
+    /// The token of a bot (with the `files:write` scope) to upload the latest plot with, since
+    /// incoming webhooks cannot carry files; it is not uploaded, if absent.
+    token: Option<Secret>,
+    /// The ID of the channel to upload the latest plot to (i.e., that of the webhook).
+    channel: Option<String>,
+    #[serde(flatten)]
+    chat: super::Config,
+}
+
+/// Posts messages through a Slack incoming webhook.
+#[derive(Debug)]
+pub(crate) struct Slack {
+    client: Client,
+    webhook_url: Secret,
+    /// The bot token and channel to upload the latest plot with, if any.
+    upload: Option<(Secret, String)>,
+}
+
+impl Slack {
+    const API_URL: &'static str = "https://slack.com/api/";
+
+    #[tracing::instrument(skip(ctx))]
+    pub(crate) fn new(config: Config, ctx: &Context) -> Result<Chat> {
+        trace!("Creating new '{}'...", std::any::type_name::<Self>());
+        Url::parse(&config.webhook_url.0).with_context(|| "invalid 'webhook_url'")?;
+        let upload = match (config.token, config.channel) {
+            (Some(token), Some(channel)) => Some((token, channel)),
+            (None, None) => None,
+            _ => bail!("uploading the plot requires both a 'token' and a 'channel'"),
+        };
+        let client = Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .with_context(|| "failed to build the HTTP client")?;
+        let slack = Self {
+            client,
+            webhook_url: config.webhook_url,
+            upload,
+        };
+        Ok(Chat::new(config.chat, Box::new(slack), ctx))
+    }
+
+    /// The payload of the incoming webhook request for the given message: a coloured attachment,
+    /// with the values as fields.
+    fn payload(message: &Message) -> Value {
+        let mut blocks = vec![json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("{} *{}*", message.health.emoji(), message.title),
+            },
+        })];
+        if !message.reasons.is_empty() {
+            blocks.push(json!({
+                "type": "context",
+                "elements": message
+                    .reasons
+                    .iter()
+                    .map(|reason| json!({ "type": "plain_text", "text": reason }))
+                    .collect::<Vec<_>>(),
+            }));
+        }
+        // Sections may have up to 10 fields
+        for fields in message.fields.chunks(10) {
+            blocks.push(json!({
+                "type": "section",
+                "fields": fields
+                    .iter()
+                    .map(|(name, value)| {
+                        json!({ "type": "mrkdwn", "text": format!("*{}*\n{}", name, value) })
+                    })
+                    .collect::<Vec<_>>(),
+            }));
+        }
+        json!({
+            "text": message.title,
+            "attachments": [{
+                "color": format!("#{:06x}", message.health.colour()),
+                "blocks": blocks,
+            }],
+        })
+    }
+
+    /// Uploads the given plot to the configured channel, through the Web API.
+    async fn upload(&self, token: &Secret, channel: &str, plot: Vec<u8>) -> Result<()> {
+        let length = plot.len().to_string();
+        let response = self
+            .client
+            .post(format!("{}files.getUploadURLExternal", Self::API_URL))
+            .bearer_auth(&token.0)
+            .form(&[("filename", PLOT_ATTACHMENT), ("length", &length)])
+            .send()
+            .await?;
+        let response = ok(response).await?;
+        let (upload_url, file_id) = match (response["upload_url"].as_str(), &response["file_id"]) {
+            (Some(upload_url), Value::String(file_id)) => (upload_url.to_owned(), file_id.clone()),
+            _ => bail!("unexpected response: {}", response),
+        };
+        self.client
+            .post(upload_url)
+            .header(header::CONTENT_TYPE, "image/png")
+            .body(plot)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())?;
+        let response = self
+            .client
+            .post(format!("{}files.completeUploadExternal", Self::API_URL))
+            .bearer_auth(&token.0)
+            .json(&json!({
+                "files": [{ "id": file_id, "title": "netspeedmon measurements" }],
+                "channel_id": channel,
+            }))
+            .send()
+            .await?;
+        ok(response).await?;
+        debug!("Uploaded plot to Slack");
+        Ok(())
+    }
+}
+
+/// Parses the given response of the Web API, which reports errors with `"ok": false`.
+async fn ok(response: reqwest::Response) -> Result<Value> {
+    let response: Value = response.error_for_status()?.json().await?;
+    match response["ok"].as_bool() {
+        Some(true) => Ok(response),
+        _ => bail!("{}", response["error"].as_str().unwrap_or("unknown error")),
+    }
+}
+
+#[async_trait]
+impl Platform for Slack {
+    async fn post(&self, message: &Message, plot: Option<Vec<u8>>) -> Result<()> {
+        self.client
+            .post(&self.webhook_url.0)
+            .json(&Self::payload(message))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| "failed to post to the Slack webhook")?;
+        debug!("Posted message to Slack");
+        if let (Some((token, channel)), Some(plot)) = (&self.upload, plot) {
+            self.upload(token, channel, plot)
+                .await
+                .with_context(|| "failed to upload the plot to Slack")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::chat::Health;
+
+    #[test]
+    fn payload() {
+        let message = Message {
+            title: "netspeedmon: wan is failed".to_owned(),
+            health: Health::Failed,
+            reasons: vec!["The round failed".to_owned()],
+            fields: vec![("Server", "Athens".to_owned())],
+        };
+        let payload = Slack::payload(&message);
+        assert_eq!(payload["attachments"][0]["color"], "#e01e5a");
+        let blocks = &payload["attachments"][0]["blocks"];
+        assert_eq!(blocks[1]["elements"][0]["text"], "The round failed");
+        assert_eq!(blocks[2]["fields"][0]["text"], "*Server*\nAthens");
+    }
+}
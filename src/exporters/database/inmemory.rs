@@ -1,4 +1,5 @@
 use std::{
+    collections::VecDeque,
     fmt,
     sync::{Arc, Mutex},
 };
@@ -20,6 +21,9 @@ pub struct InMemory {
 struct Inner {
     buffer: Vec<(DateTime<Local>, Measurement)>,
     curr: usize,
+    /// Skipped rounds, which do not count towards `history_size`; they are kept as long as they
+    /// are not older than the oldest measurement in `buffer`, up to `history_size` of them.
+    skipped: VecDeque<(DateTime<Local>, Measurement)>,
 }
 
 impl InMemory {
@@ -30,6 +34,7 @@ impl InMemory {
             inner: Arc::new(Mutex::new(Inner {
                 buffer: Vec::with_capacity(history_size),
                 curr: usize::MAX,
+                skipped: VecDeque::new(),
             })),
             history_size,
         }
@@ -43,7 +48,14 @@ impl Store for InMemory {
         self.inner
             .lock()
             .map_err(|e| anyhow!("failed to acquire in-memory database lock: {}", e))
-            .map(|inner| inner.buffer.get(inner.curr).cloned())
+            .map(|inner| {
+                match (inner.buffer.get(inner.curr), inner.skipped.back()) {
+                    (Some(m), Some(s)) if s.0 > m.0 => Some(s),
+                    (Some(m), _) => Some(m),
+                    (None, s) => s,
+                }
+                .cloned()
+            })
     }
 
     #[tracing::instrument(skip(self))]
@@ -53,6 +65,7 @@ impl Store for InMemory {
             .map_err(|e| anyhow!("failed to acquire in-memory database lock: {}", e))
             .map(|inner| {
                 let mut ret = inner.buffer.clone();
+                ret.extend(inner.skipped.iter().cloned());
                 ret.sort_unstable_by_key(|(dt, _)| *dt);
                 ret
             })
@@ -65,6 +78,15 @@ impl Store for InMemory {
             .lock()
             .map_err(|e| anyhow!("failed to acquire in-memory database lock: {}", e))?;
 
+        if measurement.skipped.is_some() {
+            im.skipped.push_back((timestamp, measurement));
+            if im.skipped.len() > self.history_size {
+                trace!("Removing oldest skipped entry: '{:?}'", im.skipped.front());
+                im.skipped.pop_front();
+            }
+            return Ok(());
+        }
+
         if im.buffer.len() < self.history_size {
             debug_assert!(
                 (im.buffer.is_empty() && im.curr == usize::MAX)
@@ -78,6 +100,13 @@ impl Store for InMemory {
             let pos = im.curr;
             trace!("Removing oldest entry: '{:?}'", im.buffer[pos]);
             im.buffer[pos] = (timestamp, measurement);
+
+            // Forget about skipped rounds that precede the oldest measurement that is left
+            let oldest = im.buffer[(pos + 1) % self.history_size].0;
+            while matches!(im.skipped.front(), Some((dt, _)) if *dt < oldest) {
+                trace!("Removing oldest skipped entry: '{:?}'", im.skipped.front());
+                im.skipped.pop_front();
+            }
         }
 
         Ok(())
@@ -91,6 +120,7 @@ impl Default for InMemory {
             inner: Arc::new(Mutex::new(Inner {
                 buffer: Vec::with_capacity(DEFAULT_HISTORY_SIZE),
                 curr: usize::MAX,
+                skipped: VecDeque::new(),
             })),
             history_size: DEFAULT_HISTORY_SIZE,
         }
@@ -104,7 +134,7 @@ impl fmt::Debug for InMemory {
             .lock()
             .expect("failed to acquire in-memory database lock in std::fmt::Debug::fmt !");
         writeln!(f, "InMemory(history size = {}){{", self.history_size)?;
-        for (timestamp, measurement) in im.buffer.iter() {
+        for (timestamp, measurement) in im.buffer.iter().chain(im.skipped.iter()) {
             write!(f, "{:?}: {:?}", timestamp, measurement)?;
         }
         writeln!(f, "}}")
@@ -128,50 +158,50 @@ mod tests {
 
         // First fill it up
 
-        let m1 = (1., 1., 1.).into();
-        db.store(Local::now(), m1).await?;
+        let m1: Measurement = (1., 1., 1.).into();
+        db.store(Local::now(), m1.clone()).await?;
         assert!(matches!(db.retrieve_most_recent().await?, Some((_, m1))));
 
-        let m2 = (2., 2., 2.).into();
-        db.store(Local::now(), m2).await?;
+        let m2: Measurement = (2., 2., 2.).into();
+        db.store(Local::now(), m2.clone()).await?;
         assert!(matches!(db.retrieve_most_recent().await?, Some((_, m2))));
 
-        let m3 = (3., 3., 3.).into();
-        db.store(Local::now(), m3).await?;
+        let m3: Measurement = (3., 3., 3.).into();
+        db.store(Local::now(), m3.clone()).await?;
         assert!(matches!(db.retrieve_most_recent().await?, Some((_, m3))));
 
-        let m4 = (4., 4., 4.).into();
-        db.store(Local::now(), m4).await?;
+        let m4: Measurement = (4., 4., 4.).into();
+        db.store(Local::now(), m4.clone()).await?;
         assert!(matches!(db.retrieve_most_recent().await?, Some((_, m4))));
 
-        let m5 = (5., 5., 5.).into();
-        db.store(Local::now(), m5).await?;
+        let m5: Measurement = (5., 5., 5.).into();
+        db.store(Local::now(), m5.clone()).await?;
         assert!(matches!(db.retrieve_most_recent().await?, Some((_, m5))));
 
         // Now start overflowing it and make sure the ring buffer works as expected
 
-        let m9 = (9., 9., 9.).into();
-        db.store(Local::now(), m9).await?;
+        let m9: Measurement = (9., 9., 9.).into();
+        db.store(Local::now(), m9.clone()).await?;
         assert!(matches!(db.retrieve_most_recent().await?, Some((_, m9))));
 
-        let m8 = (8., 8., 8.).into();
-        db.store(Local::now(), m8).await?;
+        let m8: Measurement = (8., 8., 8.).into();
+        db.store(Local::now(), m8.clone()).await?;
         assert!(matches!(db.retrieve_most_recent().await?, Some((_, m8))));
 
         let history = db.retrieve_history().await?;
         let history = history.iter().map(|(_, m)| m).collect::<Vec<_>>();
         assert_eq!(history, vec![&m3, &m4, &m5, &m9, &m8]);
 
-        let m7 = (7., 7., 7.).into();
-        db.store(Local::now(), m7).await?;
+        let m7: Measurement = (7., 7., 7.).into();
+        db.store(Local::now(), m7.clone()).await?;
         assert!(matches!(db.retrieve_most_recent().await?, Some((_, m7))));
 
-        let m6 = (6., 6., 6.).into();
-        db.store(Local::now(), m6).await?;
+        let m6: Measurement = (6., 6., 6.).into();
+        db.store(Local::now(), m6.clone()).await?;
         assert!(matches!(db.retrieve_most_recent().await?, Some((_, m6))));
 
-        let m5 = (5., 5., 5.).into();
-        db.store(Local::now(), m5).await?;
+        let m5: Measurement = (5., 5., 5.).into();
+        db.store(Local::now(), m5.clone()).await?;
         assert!(matches!(db.retrieve_most_recent().await?, Some((_, m5))));
 
         let history = db.retrieve_history().await?;
@@ -180,4 +210,48 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn skipped() -> Result<()> {
+        let mut db = InMemory::new(2);
+        let t0 = Local::now();
+        let at = |secs| t0 + chrono::Duration::seconds(secs);
+
+        let m1: Measurement = (1., 1., 1.).into();
+        db.store(at(1), m1.clone()).await?;
+        let m2: Measurement = (2., 2., 2.).into();
+        db.store(at(2), m2.clone()).await?;
+
+        // Skipped rounds are recorded, without evicting any measurements
+        let s3 = Measurement::skipped("blackout".to_owned());
+        db.store(at(3), s3.clone()).await?;
+        let s4 = Measurement::skipped("busy link".to_owned());
+        db.store(at(4), s4.clone()).await?;
+        assert_eq!(
+            db.retrieve_most_recent().await?.map(|(_, m)| m),
+            Some(s4.clone())
+        );
+
+        let history = db.retrieve_history().await?;
+        let history = history.iter().map(|(_, m)| m).collect::<Vec<_>>();
+        assert_eq!(history, vec![&m1, &m2, &s3, &s4]);
+
+        // ...but they are capped, too
+        let s5 = Measurement::skipped("metered link".to_owned());
+        db.store(at(5), s5.clone()).await?;
+        let history = db.retrieve_history().await?;
+        let history = history.iter().map(|(_, m)| m).collect::<Vec<_>>();
+        assert_eq!(history, vec![&m1, &m2, &s4, &s5]);
+
+        // ...and they go along with the measurements that preceded them
+        let m6: Measurement = (6., 6., 6.).into();
+        db.store(at(6), m6.clone()).await?;
+        let m7: Measurement = (7., 7., 7.).into();
+        db.store(at(7), m7.clone()).await?;
+        let history = db.retrieve_history().await?;
+        let history = history.iter().map(|(_, m)| m).collect::<Vec<_>>();
+        assert_eq!(history, vec![&m6, &m7]);
+
+        Ok(())
+    }
 }
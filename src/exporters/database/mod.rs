@@ -22,7 +22,7 @@ use self::plotter::Plotter;
 const DEFAULT_HISTORY_SIZE: usize = 170;
 
 /// Configuration for the `Database` actor.
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub(crate) struct Config {
     /// A String that uniquely identifies the type of the underlying `Store` to be used by the
     /// `Database`.
@@ -34,11 +34,12 @@ pub(crate) struct Config {
     /// Path where netspeedmon's state may be stored. This includes storage required for the
     /// Database, as well as, optionally, for the Plotter.
     path: String,
-    /// The number of past measurements to store (and, optionally, plot).
+    /// The number of past measurements to store (and, optionally, plot); skipped rounds are
+    /// stored alongside them, without counting towards it.
     history_size: Option<usize>,
 }
 
-#[cfg(all(feature = "plot", any(feature = "http", feature = "twitter")))]
+#[cfg(feature = "plot")]
 impl Config {
     pub(crate) fn path(&self) -> &str {
         self.path.as_ref()
@@ -8,26 +8,26 @@ use std::{
 
 use anyhow::{anyhow, bail, Context, Result};
 use chrono::{DateTime, Local};
-#[cfg(feature = "twitter")]
+#[cfg(any(feature = "twitter", feature = "chat"))]
 use plotters::prelude::BitMapBackend;
-#[cfg(not(feature = "twitter"))]
+#[cfg(not(any(feature = "twitter", feature = "chat")))]
 use plotters::prelude::SVGBackend;
 use plotters::{
     prelude::{
-        ChartBuilder, Circle, IntoDrawingArea, LabelAreaPosition, LineSeries, PathElement,
-        RangedDateTime,
+        ChartBuilder, Circle, Cross, IntoDrawingArea, LabelAreaPosition, LineSeries, PathElement,
+        Polygon, RangedDateTime, Rectangle,
     },
     style::{Color, BLACK, BLUE, GREEN, RED, WHITE},
 };
 use tracing::{info, trace};
 
-use crate::measure::Measurement;
+use crate::measure::{LatencyStats, Measurement};
 
 /// Static name for the file where the latest plot is stored, to make sure that a new plot
 /// always overwrites the older, thus avoiding the need for large storage capacity over time.
-#[cfg(feature = "twitter")]
+#[cfg(any(feature = "twitter", feature = "chat"))]
 pub(crate) const PLOT_FILE_NAME: &str = "latest_plot.png";
-#[cfg(not(feature = "twitter"))]
+#[cfg(not(any(feature = "twitter", feature = "chat")))]
 pub(crate) const PLOT_FILE_NAME: &str = "latest_plot.svg";
 
 #[derive(Debug)]
@@ -94,29 +94,75 @@ impl Plotter {
     fn ping_range(&self, data: &[(DateTime<Local>, Measurement)]) -> Range<f64> {
         let mut max = f64::MIN;
         for (_, m) in data {
-            max = max.max(m.ping_latency);
+            max = max.max(m.ping_latency.max(Self::latency(m, |s| s.p99)));
         }
         0f64..(max * 1.2)
     }
 
+    /// Picks a statistic out of the latency distribution summary of the given `Measurement`,
+    /// falling back to its single ping latency value if no such summary is available.
+    fn latency(m: &Measurement, pick: fn(&LatencyStats) -> f64) -> f64 {
+        m.latency_stats.as_ref().map_or(m.ping_latency, pick)
+    }
+
+    /// Returns the outline of a band spanning between two latency statistics over time.
+    fn latency_band(
+        data: &[(DateTime<Local>, Measurement)],
+        lower: fn(&LatencyStats) -> f64,
+        upper: fn(&LatencyStats) -> f64,
+    ) -> Vec<(DateTime<Local>, f64)> {
+        data.iter()
+            .map(|(ts, m)| (*ts, Self::latency(m, upper)))
+            .chain(
+                data.iter()
+                    .rev()
+                    .map(|(ts, m)| (*ts, Self::latency(m, lower))),
+            )
+            .collect()
+    }
+
     #[tracing::instrument(skip(self, data))]
     pub(super) async fn plot(&self, data: Vec<(DateTime<Local>, Measurement)>) -> Result<()> {
+        // Skipped rounds are only recorded in history, there is nothing to plot for them.
+        let data: Vec<_> = data
+            .into_iter()
+            .filter(|(_, m)| m.skipped.is_none())
+            .collect();
         if data.len() < 2 {
             info!("Skipping plot since # measurements = {}", data.len());
             return Ok(());
         }
 
+        let datetime_range = self.datetime_range(&data).with_context(|| {
+            format!(
+                "Data points == {}, but failed to calculate datetime range",
+                data.len()
+            )
+        })?;
+
+        // Suspect measurements are kept out of the autoscaled ranges and the time series; they
+        // are only marked on the plot.
+        let (suspect, data): (Vec<_>, Vec<_>) =
+            data.into_iter().partition(|(_, m)| m.suspect.is_some());
+        if data.is_empty() {
+            info!(
+                "Skipping plot since all {} measurements are suspect",
+                suspect.len()
+            );
+            return Ok(());
+        }
+
         let plot_file_name = self.out_dir.join(PLOT_FILE_NAME);
         let backend = {
-            #[cfg(feature = "twitter")]
+            #[cfg(any(feature = "twitter", feature = "chat"))]
             {
-                // If the "twitter" Cargo feature is enabled, plots are PNG which are supported by
-                // the Twitter API.
+                // If the "twitter" or "chat" Cargo features are enabled, plots are PNG, which are
+                // supported by the Twitter API and by chat services.
                 BitMapBackend::new(&plot_file_name, Self::PLOT_IMAGE_RESOLUTION).into_drawing_area()
             }
-            #[cfg(not(feature = "twitter"))]
+            #[cfg(not(any(feature = "twitter", feature = "chat")))]
             {
-                // If the "twitter" Cargo feature is disabled, plots are SVG, which are better.
+                // Otherwise, plots are SVG, which are better.
                 SVGBackend::new(&plot_file_name, Self::PLOT_IMAGE_RESOLUTION).into_drawing_area()
             }
         };
@@ -124,13 +170,8 @@ impl Plotter {
             .fill(&WHITE)
             .with_context(|| "failed to fill backend with WHITE")?;
 
-        let datetime_range = self.datetime_range(&data).with_context(|| {
-            format!(
-                "Data points == {}, but failed to calculate datetime range",
-                data.len()
-            )
-        })?;
         let mbps_range = self.mbps_range(&data);
+        let mbps_max = mbps_range.end;
         let ping_range = self.ping_range(&data);
 
         let mut chart = ChartBuilder::on(&backend)
@@ -189,22 +230,68 @@ impl Plotter {
             )
             .with_context(|| "failed to draw upload speed points on primary axes")?;
 
-        // Draw points & time series for ping latency on secondary axes
+        // If latency distribution summaries are available, draw percentile bands for ping
+        // latency on secondary axes
+        let has_latency_stats = data.iter().any(|(_, m)| m.latency_stats.is_some());
+        if has_latency_stats {
+            let outer = RED.mix(0.15);
+            chart
+                .draw_secondary_series(std::iter::once(Polygon::new(
+                    Self::latency_band(&data, |s| s.min, |s| s.p99),
+                    outer.filled(),
+                )))
+                .with_context(|| "failed to draw min-p99 ping latency band on secondary axes")?
+                .label("Ping Latency min-p99 (ms)")
+                .legend(move |(x, y)| {
+                    Rectangle::new([(x, y - 5), (x + 20, y + 5)], outer.filled())
+                });
+            let inner = RED.mix(0.35);
+            chart
+                .draw_secondary_series(std::iter::once(Polygon::new(
+                    Self::latency_band(&data, |s| s.p50, |s| s.p90),
+                    inner.filled(),
+                )))
+                .with_context(|| "failed to draw p50-p90 ping latency band on secondary axes")?
+                .label("Ping Latency p50-p90 (ms)")
+                .legend(move |(x, y)| {
+                    Rectangle::new([(x, y - 5), (x + 20, y + 5)], inner.filled())
+                });
+        }
+
+        // Draw points & time series for (median) ping latency on secondary axes
         chart
             .draw_secondary_series(LineSeries::new(
-                data.iter().map(|(ts, m)| (*ts, m.ping_latency)),
+                data.iter()
+                    .map(|(ts, m)| (*ts, Self::latency(m, |s| s.p50))),
                 &RED,
             ))
             .with_context(|| "failed to draw ping latency series on seconday axes")?
-            .label("Ping Latency (ms)")
+            .label(if has_latency_stats {
+                "Ping Latency p50 (ms)"
+            } else {
+                "Ping Latency (ms)"
+            })
             .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &RED));
         chart
             .draw_secondary_series(
-                data.iter()
-                    .map(|(ts, m)| Circle::new((*ts, m.ping_latency), 3, RED.filled())),
+                data.iter().map(|(ts, m)| {
+                    Circle::new((*ts, Self::latency(m, |s| s.p50)), 3, RED.filled())
+                }),
             )
             .with_context(|| "failed to draw ping latency points on secondary axes")?;
 
+        // Mark suspect measurements at their download speed, clamped within the primary axes
+        if !suspect.is_empty() {
+            chart
+                .draw_series(suspect.iter().map(|(ts, m)| {
+                    let download_speed = m.download_speed.max(0.).min(mbps_max);
+                    Cross::new((*ts, download_speed), 6, BLACK.stroke_width(2))
+                }))
+                .with_context(|| "failed to draw suspect measurements on primary axes")?
+                .label("Suspect measurement")
+                .legend(|(x, y)| Cross::new((x + 10, y), 6, BLACK.stroke_width(2)));
+        }
+
         // Draw labels/legend
         chart
             .configure_series_labels()
@@ -0,0 +1,433 @@
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context as _, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use lettre::{
+    message::{header::ContentType, Attachment, Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::{debug, info, trace, warn};
+
+use super::{Context, Exporter};
+use crate::{measure::Measurement, probe::DEFAULT_NAME};
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The host of the SMTP relay.
+    host: String,
+    /// The port of the SMTP relay; 587 (STARTTLS), 465 (TLS) or 25 (plaintext), if absent.
+    port: Option<u16>,
+    /// How to secure the connection (`"starttls"`, `"tls"` or `"none"`); `"starttls"`, if absent.
+    security: Option<Security>,
+    /// The credentials to authenticate to the relay with, if any.
+    username: Option<String>,
+    password: Option<Secret>,
+    /// The mailbox to send emails from (e.g., `"netspeedmon <netspeedmon@example.com>"`).
+    from: String,
+    /// The mailboxes to send emails to.
+    to: Vec<String>,
+    /// What the subject of each email starts with; `"[netspeedmon]"`, if absent.
+    #[serde(alias = "SubjectPrefix")]
+    subject_prefix: Option<String>,
+    /// Whether to only email about rounds that failed or are suspect; false, if absent.
+    #[serde(default, alias = "AlertsOnly")]
+    alerts_only: bool,
+    /// The least time between consecutive emails; rounds received meanwhile are sent together, as
+    /// a digest, once it is over. 1 hour, if absent.
+    #[serde(default, with = "humantime_serde", alias = "MinInterval")]
+    min_interval: Option<Duration>,
+    /// Whether to attach the latest plot, if a Database is configured; true, if absent.
+    plot: Option<bool>,
+    /// How long to wait for the relay to respond; 30 seconds, if absent.
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Security {
+    /// Upgrades a plaintext connection to TLS, which is required.
+    StartTls,
+    /// Connects over TLS from the start.
+    Tls,
+    /// Sends everything (including credentials) in plaintext; only meant for local relays (or
+    /// sinks, such as MailHog).
+    None,
+}
+
+/// A secret, which is kept out of logs.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+struct Secret(String);
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Emails rounds through an SMTP relay, at most once every `min_interval`, sending those received
+/// meanwhile together as a digest.
+#[derive(Debug)]
+pub(crate) struct Email {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    subject_prefix: String,
+    alerts_only: bool,
+    min_interval: Duration,
+    plot_path: Option<PathBuf>,
+    /// The rounds yet to be emailed, oldest first, along with when each was received.
+    pending: VecDeque<(DateTime<Local>, Measurement)>,
+    /// When the next email may be sent, if not right away.
+    next_at: Option<Instant>,
+}
+
+impl Email {
+    const DEFAULT_SUBJECT_PREFIX: &'static str = "[netspeedmon]";
+    const DEFAULT_MIN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+    /// How long to wait before retrying to send an email that failed.
+    const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+    /// How many rounds to keep for the next email, dropping the oldest ones beyond that.
+    const PENDING_LIMIT: usize = 1000;
+
+    #[tracing::instrument(skip(ctx))]
+    pub(crate) fn new(config: Config, ctx: &Context) -> Result<Self> {
+        trace!("Creating new '{}'...", std::any::type_name::<Self>());
+        let from = config
+            .from
+            .parse()
+            .with_context(|| format!("invalid 'from' mailbox '{}'", config.from))?;
+        let to = config
+            .to
+            .iter()
+            .map(|to| {
+                to.parse()
+                    .with_context(|| format!("invalid 'to' mailbox '{}'", to))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if to.is_empty() {
+            bail!("at least one 'to' mailbox must be configured");
+        }
+        let security = config.security.unwrap_or(Security::StartTls);
+        let (builder, port) = match security {
+            Security::StartTls => (
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?,
+                587,
+            ),
+            Security::Tls => (
+                AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+                465,
+            ),
+            Security::None => (
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+                25,
+            ),
+        };
+        let mut builder = builder
+            .port(config.port.unwrap_or(port))
+            .timeout(Some(config.timeout.unwrap_or(Self::DEFAULT_TIMEOUT)));
+        match (config.username, config.password) {
+            (Some(username), Some(password)) => {
+                builder = builder.credentials(Credentials::new(username, password.0));
+            }
+            (None, None) => {}
+            _ => bail!("a 'username' requires a 'password', and vice versa"),
+        }
+        let plot_path = match config.plot.unwrap_or(true) {
+            true => ctx.plot_path.clone(),
+            false => None,
+        };
+        Ok(Self {
+            transport: builder.build(),
+            from,
+            to,
+            subject_prefix: config
+                .subject_prefix
+                .unwrap_or_else(|| Self::DEFAULT_SUBJECT_PREFIX.to_owned()),
+            alerts_only: config.alerts_only,
+            min_interval: config.min_interval.unwrap_or(Self::DEFAULT_MIN_INTERVAL),
+            plot_path,
+            pending: VecDeque::new(),
+            next_at: None,
+        })
+    }
+
+    /// The subject of the email about the pending rounds.
+    fn subject(&self) -> String {
+        let subject = match self.pending.len() {
+            1 => {
+                let (_, measurement) = &self.pending[0];
+                let probe = measurement.probe.as_deref().unwrap_or(DEFAULT_NAME);
+                match measurement.skipped.is_some() || measurement.is_failed() {
+                    true => format!("{}: {}", probe, measurement.status()),
+                    false => format!(
+                        "{}: {} ({:.2}/{:.2} Mbit/s, {:.2} ms)",
+                        probe,
+                        measurement.status(),
+                        measurement.download_speed,
+                        measurement.upload_speed,
+                        measurement.ping_latency
+                    ),
+                }
+            }
+            n => {
+                let alerts = self
+                    .pending
+                    .iter()
+                    .filter(|(_, measurement)| matches!(measurement.status(), "failed" | "suspect"))
+                    .count();
+                format!("Digest of {} rounds ({} failed or suspect)", n, alerts)
+            }
+        };
+        format!("{} {}", self.subject_prefix, subject)
+    }
+
+    /// The plain-text and HTML bodies of the email about the pending rounds, which list each of
+    /// them.
+    fn bodies(&self) -> (String, String) {
+        let mut text = String::new();
+        let mut html = String::from(concat!(
+            "<table>",
+            "<tr><th>Time</th><th>Probe</th><th>Status</th><th>Download (Mbit/s)</th>",
+            "<th>Upload (Mbit/s)</th><th>Latency (ms)</th><th>Server</th><th>Notes</th></tr>",
+        ));
+        for (at, measurement) in self.pending.iter() {
+            let probe = measurement.probe.as_deref().unwrap_or(DEFAULT_NAME);
+            let notes: Vec<_> = [
+                ("skipped", &measurement.skipped),
+                ("suspect", &measurement.suspect),
+                ("downgraded", &measurement.downgraded),
+                ("deferred", &measurement.deferred),
+            ]
+            .iter()
+            .filter_map(|(name, reason)| {
+                reason
+                    .as_ref()
+                    .map(|reason| format!("{}: {}", name, reason))
+            })
+            .collect();
+            let server = measurement.server.as_deref().unwrap_or("-");
+            let at = at.format("%Y-%m-%d %H:%M:%S %Z");
+            text.push_str(&format!(
+                "{} {} {}: {:.2} Mbit/s down, {:.2} Mbit/s up, {:.2} ms (server: {})",
+                at,
+                probe,
+                measurement.status(),
+                measurement.download_speed,
+                measurement.upload_speed,
+                measurement.ping_latency,
+                server
+            ));
+            for note in notes.iter() {
+                text.push_str(&format!("\n    {}", note));
+            }
+            text.push('\n');
+            let colour = match measurement.status() {
+                "ok" => "#2eb67d",
+                "failed" => "#e01e5a",
+                _ => "#ecb22e",
+            };
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td style=\"color: {}\"><b>{}</b></td><td>{:.2}</td><td>{:.2}</td><td>{:.2}</td><td>{}</td><td>{}</td></tr>",
+                at,
+                escape(probe),
+                colour,
+                measurement.status(),
+                measurement.download_speed,
+                measurement.upload_speed,
+                measurement.ping_latency,
+                escape(server),
+                escape(&notes.join("; "))
+            ));
+        }
+        html.push_str("</table>");
+        (text, html)
+    }
+
+    /// Reads the latest plot, if it is to be attached.
+    async fn attachment(&self) -> Option<(Vec<u8>, &Path)> {
+        let _plot_path = self.plot_path.as_deref()?;
+        #[cfg(feature = "plot")]
+        match tokio::fs::read(_plot_path).await {
+            Ok(plot) => return Some((plot, _plot_path)),
+            Err(e) => warn!("Failed to read the latest plot image: {}", e),
+        }
+        None
+    }
+
+    async fn message(&self) -> Result<Message> {
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .subject(self.subject());
+        for to in self.to.iter() {
+            builder = builder.to(to.clone());
+        }
+        let (text, html) = self.bodies();
+        let body = MultiPart::alternative_plain_html(text, html);
+        let message = match self.attachment().await {
+            Some((plot, path)) => {
+                let content_type = match path.extension().and_then(|ext| ext.to_str()) {
+                    Some("png") => "image/png",
+                    _ => "image/svg+xml",
+                };
+                let file_name = path
+                    .file_name()
+                    .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+                let plot = Attachment::new(file_name).body(plot, ContentType::parse(content_type)?);
+                builder.multipart(MultiPart::mixed().multipart(body).singlepart(plot))?
+            }
+            None => builder.multipart(body)?,
+        };
+        Ok(message)
+    }
+
+    /// Emails the pending rounds, keeping them to retry later if that fails.
+    async fn send(&mut self) {
+        let result = match self.message().await {
+            Ok(message) => self.transport.send(message).await.map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => {
+                debug!("Emailed {} rounds", self.pending.len());
+                self.pending.clear();
+                self.next_at = Some(Instant::now() + self.min_interval);
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to email {} rounds (retrying in {}): {:#}",
+                    self.pending.len(),
+                    humantime::format_duration(Self::RETRY_INTERVAL),
+                    e
+                );
+                self.next_at = Some(Instant::now() + Self::RETRY_INTERVAL);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Exporter for Email {
+    async fn init(&mut self) -> Result<()> {
+        match self
+            .transport
+            .test_connection()
+            .await
+            .with_context(|| "failed to connect to the SMTP relay")?
+        {
+            true => info!("Connected to the SMTP relay"),
+            false => bail!("the SMTP relay did not respond as expected"),
+        }
+        Ok(())
+    }
+
+    async fn on_measurement(&mut self, measurement: Measurement) {
+        if self.alerts_only && matches!(measurement.status(), "ok" | "skipped") {
+            return;
+        }
+        self.pending.push_back((Local::now(), measurement));
+        if self.pending.len() > Self::PENDING_LIMIT {
+            warn!("Dropping the oldest round, which exceeds the limit of the next email");
+            self.pending.pop_front();
+        }
+        match self.next_at {
+            Some(next_at) if Instant::now() < next_at => {
+                trace!("Rate-limited; {} rounds pending", self.pending.len())
+            }
+            _ => self.send().await,
+        }
+    }
+
+    fn wake_at(&self) -> Option<Instant> {
+        match self.pending.is_empty() {
+            true => None,
+            false => self.next_at,
+        }
+    }
+
+    async fn on_wake(&mut self) {
+        self.send().await
+    }
+
+    async fn on_shutdown(&mut self) {
+        if !self.pending.is_empty() {
+            info!("Emailing {} pending rounds", self.pending.len());
+            self.send().await;
+        }
+    }
+}
+
+/// Escapes the characters that are special in HTML.
+fn escape(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn message() {
+        let config: Config = serde_json::from_value(json!({
+            "host": "localhost",
+            "security": "none",
+            "from": "netspeedmon <netspeedmon@example.com>",
+            "to": ["ops@example.com"],
+        }))
+        .unwrap();
+        let ctx = Context {
+            name: "email".to_owned(),
+            plot_path: None,
+            probes: Default::default(),
+            health: Default::default(),
+        };
+        let mut email = Email::new(config, &ctx).unwrap();
+        let at = Local.timestamp(1_600_000_000, 0);
+        email.pending.push_back((
+            at,
+            Measurement {
+                probe: Some("wan".to_owned()),
+                suspect: Some("<1% of the baseline".to_owned()),
+                ..(3.5, 0.25, 9.5).into()
+            },
+        ));
+        assert_eq!(
+            email.subject(),
+            "[netspeedmon] wan: suspect (0.25/9.50 Mbit/s, 3.50 ms)"
+        );
+        email.pending.push_back((at, Measurement::default()));
+        assert_eq!(
+            email.subject(),
+            "[netspeedmon] Digest of 2 rounds (2 failed or suspect)"
+        );
+        let (text, html) = email.bodies();
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.contains("    suspect: <1% of the baseline\n"));
+        assert!(html.contains("<td>suspect: &lt;1% of the baseline</td>"));
+        assert!(html.contains("<td>default</td><td style=\"color: #e01e5a\"><b>failed</b></td>"));
+    }
+}
@@ -1,126 +1,223 @@
 use std::{
+    collections::BTreeMap,
+    convert::Infallible,
     fmt::Debug,
     net::SocketAddr,
-    path::{Path, PathBuf},
+    path::PathBuf,
     sync::{Arc, Mutex},
     time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
+use async_trait::async_trait;
 use serde::Deserialize;
-use tokio::sync::{broadcast, oneshot, watch};
+use tokio::{sync::oneshot, task::JoinHandle};
 use tracing::{debug, error, info, trace, warn};
-use warp::{hyper::StatusCode, Filter};
+use warp::{hyper::StatusCode, Filter, Reply};
 
-use crate::measure::Measurement;
+use super::{metrics::Metrics, Context, Exporter};
+use crate::{
+    measure::Measurement,
+    probe::{self, Control, ControlError, Trigger, TriggerError},
+    supervisor::{self, State},
+};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub(crate) struct Config {
     bind_addr: Option<String>,
+    /// A token that requests to mutating endpoints (e.g., `/measure`, `/admin/*`) must carry as
+    /// `Authorization: Bearer <token>`; if absent, see `unauthenticated`.
+    #[serde(alias = "AuthToken")]
+    auth_token: Option<AuthToken>,
+    /// Whether to serve the mutating endpoints to anyone, if no `auth_token` is configured; they
+    /// are not served at all otherwise. False, if absent.
+    unauthenticated: Option<bool>,
+}
+
+/// A secret, which is kept out of logs.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+struct AuthToken(String);
+
+impl Debug for AuthToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AuthToken(<redacted>)")
+    }
 }
 
+/// Who requests to mutating endpoints are served to.
+#[derive(Debug, Clone)]
+enum Access {
+    /// Those that carry the configured token.
+    Token(AuthToken),
+    /// Anyone, since no token is configured and unauthenticated access has been allowed.
+    Anyone,
+    /// No one, since no token is configured.
+    NoOne,
+}
+
+/// The rejection of a request to a mutating endpoint that lacks the configured token.
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
 #[derive(Debug)]
 pub(crate) struct Http {
     bind_addr: SocketAddr,
     plot_path: Option<PathBuf>,
-    period: Duration,
-    rx: broadcast::Receiver<Measurement>,
-    quit: watch::Receiver<bool>,
+    access: Access,
+    probes: probe::Registry,
+    health: supervisor::Registry,
+    latest: Arc<Mutex<Latest>>,
+    metrics: Arc<Mutex<Metrics>>,
+    /// The sending end of a `oneshot` channel to signal the HTTP server task to gracefully
+    /// terminate, along with its handle, once it has been spawned.
+    server: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MeasureQuery {
+    /// The probe to trigger an on-demand round of; all of them, if omitted.
+    probe: Option<String>,
+    /// Whether to wait for the on-demand round to be over, to respond with its outcome.
+    #[serde(default)]
+    wait: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct AdminQuery {
+    /// The probe to apply the change to; all of them, if omitted.
+    probe: Option<String>,
+    /// The period to schedule rounds on (for `/admin/period`).
+    #[serde(default, with = "humantime_serde")]
+    period: Option<Duration>,
+}
+
+/// The latest measurements received, overall and per probe.
+#[derive(Debug, Default)]
+struct Latest {
+    overall: Measurement,
+    per_probe: BTreeMap<String, Measurement>,
 }
 
 impl Http {
     const DEFAULT_ADDRESS: &'static str = "0.0.0.0:54242";
 
-    #[tracing::instrument(skip(rx, quit))]
-    pub(crate) fn new<P: AsRef<Path> + Debug>(
-        config: &Config,
-        plot_path: Option<P>,
-        period: Duration,
-        rx: broadcast::Receiver<Measurement>,
-        quit: watch::Receiver<bool>,
-    ) -> Result<Self> {
+    #[tracing::instrument(skip(ctx))]
+    pub(crate) fn new(config: Config, ctx: &Context) -> Result<Self> {
         trace!("Creating new '{}'...", std::any::type_name::<Self>());
         let bind_addr = config
             .bind_addr
             .as_ref()
             .map_or_else(|| Self::DEFAULT_ADDRESS.parse(), |addr| addr.parse())?;
+        #[cfg(feature = "plot")]
+        if ctx.plot_path.is_none() {
+            anyhow::bail!("a Database must be configured to serve the plot");
+        }
+        let access = match (config.auth_token, config.unauthenticated.unwrap_or(false)) {
+            (Some(token), _) => Access::Token(token),
+            (None, true) => Access::Anyone,
+            (None, false) => Access::NoOne,
+        };
         Ok(Self {
             bind_addr,
-            plot_path: plot_path.map(|p| p.as_ref().to_owned()),
-            period,
-            rx,
-            quit,
+            plot_path: ctx.plot_path.clone(),
+            access,
+            probes: ctx.probes.clone(),
+            health: ctx.health.clone(),
+            latest: Arc::default(),
+            metrics: Arc::default(),
+            server: None,
         })
     }
 
-    #[tracing::instrument(skip(self))]
-    pub(crate) async fn run(mut self) {
-        // Setup and spawn the HTTP server as a separate task
-        let latest_measurement = Arc::new(Mutex::new(Default::default()));
-
-        // Endpoints
-        let period = Self::endpoint_period(self.period);
-        let latest = Self::endpoint_latest(latest_measurement.clone());
-        let plot = Self::endpoint_plot(self.plot_path);
-        let routes = period.or(latest).or(plot);
+    // Matches an optional trailing path segment naming a probe, at the end of the path.
+    fn probe_name() -> warp::filters::BoxedFilter<(Option<String>,)> {
+        warp::path::param::<String>()
+            .map(Some)
+            .or(warp::any().map(|| None))
+            .unify()
+            .and(warp::path::end())
+            .boxed()
+    }
 
-        // We are using a `oneshot` channel to notify the server to gracefully terminate upon
-        // receival of a quit signal from the `watch` channel by the Monitor.
-        let (sqtx, sqrx) = oneshot::channel();
-        let (addr, server) =
-            warp::serve(routes).bind_with_graceful_shutdown(self.bind_addr, async {
-                sqrx.await.ok();
-            });
-        debug!("Binding to {} and serving...", addr);
-        let server_handle = tokio::spawn(server);
-
-        // Block waiting for either a quit signal or the latest measurement
-        loop {
-            let recv = self.rx.recv();
-            tokio::pin!(recv);
-
-            debug!("Now blocking, waiting for either a quit signal or a new measurement...");
-            tokio::select! {
-                _ = self.quit.changed() => {
-                    info!("Received signal to gracefully shut down");
-                    if let Err(e) = sqtx.send(()) {
-                        warn!("Failed to signal the HTTP server task: {:?}", e);
-                    } else if let Err(e) = server_handle.await {
-                        warn!("Failed to wait for the HTTP server task: {}", e);
-                    } else {
-                        info!("HTTP server task has been successfully shut down");
-                    }
-                    break;
-                },
-                result = &mut recv => {
-                    match result {
-                        Ok(measurements) => {
-                            trace!("Serving new measurements");
-                            match latest_measurement.lock() {
-                                Ok(ref mut lm) => {
-                                    **lm = measurements;
-                                }
-                                Err(e) => {
-                                    error!("Failed to acquire latest_measurement lock: {}", e);
-                                }
-                            };
-                        },
-                        Err(e) => {
-                            warn!("Failed to receive from the measurements channel: {}", e);
-                        },
-                    };
-                },
+    /// Returns the `Handle`s of the currently running probes, so that they can be used without
+    /// holding the lock (e.g., across `.await`s).
+    fn snapshot(probes: &probe::Registry) -> BTreeMap<String, probe::Handle> {
+        match probes.read() {
+            Ok(probes) => probes.clone(),
+            Err(e) => {
+                error!("Failed to acquire lock for probe registry: {}", e);
+                e.into_inner().clone()
             }
         }
     }
 
-    // Returns a plain Duration string, formatted in a human-readable form, according to crate
-    // humantime.
-    fn endpoint_period(period: Duration) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    // Looks up the probe with the given name or, if no name is given, the only probe configured.
+    // On failure, it returns the status code and error message to respond with.
+    fn lookup<'a>(
+        probes: &'a BTreeMap<String, probe::Handle>,
+        name: Option<&str>,
+        endpoint: &str,
+    ) -> Result<&'a probe::Handle, (StatusCode, String)> {
+        match name {
+            Some(name) => probes
+                .get(name)
+                .ok_or_else(|| (StatusCode::NOT_FOUND, format!("No such probe: '{}'", name))),
+            None if probes.len() == 1 => Ok(probes.values().next().expect("probes is empty")),
+            None => Err((
+                StatusCode::NOT_FOUND,
+                format!(
+                    "{} probes are configured; use '/{}/<probe>' instead",
+                    probes.len(),
+                    endpoint
+                ),
+            )),
+        }
+    }
+
+    // Returns the period that measurements of the probe (either specified as `/period/<probe>`, or
+    // the only one configured) are currently scheduled on (which may be shorter than the
+    // configured one, if it is adaptive), as a plain Duration string, formatted in a human-readable
+    // form, according to crate humantime.
+    // If measurements are scheduled through cron expressions rather than on a fixed period, or if
+    // no such probe exists, it returns 404 and an error message as a plain String.
+    fn endpoint_period(probes: probe::Registry) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
         warp::get()
             .and(warp::path("period"))
-            .and(warp::path::end())
-            .map(move || warp::reply::json(&humantime::format_duration(period).to_string()))
+            .and(Self::probe_name())
+            .map(move |name: Option<String>| {
+                let probes = Self::snapshot(&probes);
+                let handle = match Self::lookup(&probes, name.as_deref(), "period") {
+                    Ok(handle) => handle,
+                    Err((status, body)) => {
+                        return warp::reply::with_status(warp::reply::json(&body), status)
+                    }
+                };
+                let effective_period = handle.status.lock().map(|s| s.effective_period);
+                match effective_period {
+                    Ok(effective_period) => match effective_period {
+                        Some(period) => warp::reply::with_status(
+                            warp::reply::json(&humantime::format_duration(period).to_string()),
+                            StatusCode::OK,
+                        ),
+                        None => warp::reply::with_status(
+                            warp::reply::json(
+                                &"Measurements are scheduled through cron expressions, not on a period",
+                            ),
+                            StatusCode::NOT_FOUND,
+                        ),
+                    },
+                    Err(e) => {
+                        error!("Failed to acquire lock for scheduler status: {}", e);
+                        warp::reply::with_status(
+                            warp::reply::json(&format!("Internal synchronization error: {}", e)),
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                        )
+                    }
+                }
+            })
             .with(warp::reply::with::header(
                 "Content-Type",
                 "application/json",
@@ -129,26 +226,191 @@ impl Http {
             .boxed()
     }
 
-    // On success, it returns 200 OK along with a JSON-formatted Measurement; e.g.:
+    // On success, it returns 200 OK along with the JSON-formatted state of the scheduler of the
+    // probe (either specified as `/schedule/<probe>`, or the only one configured); e.g.:
+    //     {
+    //         "period": "10m",
+    //         "effective_period": "2m 30s",
+    //         "splay": "1m",
+    //         "startup_offset": "7m 12s 918ms 114us 263ns",
+    //         "next_splay": "23s 511ms 400us 2ns",
+    //         "next_round": "2021-09-20T18:45:10.470981524+03:00",
+    //         "next_measurement": "2021-09-20T23:05:10.470981524+03:00",
+    //         "in_blackout": true,
+    //         "blackout_until": "2021-09-20T23:00:00+03:00"
+    //     }
+    // If multiple probes are configured and none is specified, it returns a JSON object mapping
+    // each probe's name to the state of its scheduler.
+    // If no such probe exists, it returns 404; on failure, it returns 500 INTERNAL SERVER ERROR.
+    fn endpoint_schedule(
+        probes: probe::Registry,
+    ) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+        warp::get()
+            .and(warp::path("schedule"))
+            .and(Self::probe_name())
+            .map(move |name: Option<String>| {
+                let probes = Self::snapshot(&probes);
+                let selected: Vec<_> = match name {
+                    Some(name) => match probes.get_key_value(&name) {
+                        Some(probe) => vec![probe],
+                        None => {
+                            return warp::reply::with_status(
+                                warp::reply::json(&format!("No such probe: '{}'", name)),
+                                StatusCode::NOT_FOUND,
+                            )
+                        }
+                    },
+                    None => probes.iter().collect(),
+                };
+                let mut statuses = BTreeMap::new();
+                for (name, handle) in selected {
+                    match handle.status.lock() {
+                        Ok(schedule) => {
+                            statuses.insert(name, schedule.current());
+                        }
+                        Err(e) => {
+                            error!("Failed to acquire lock for scheduler status: {}", e);
+                            return warp::reply::with_status(
+                                warp::reply::json(&format!(
+                                    "Internal synchronization error: {}",
+                                    e
+                                )),
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                            );
+                        }
+                    }
+                }
+                match statuses.len() {
+                    1 => warp::reply::with_status(
+                        warp::reply::json(statuses.values().next().expect("statuses is empty")),
+                        StatusCode::OK,
+                    ),
+                    _ => warp::reply::with_status(warp::reply::json(&statuses), StatusCode::OK),
+                }
+            })
+            .with(warp::reply::with::header(
+                "Content-Type",
+                "application/json",
+            ))
+            .with(warp::trace::named("/schedule"))
+            .boxed()
+    }
+
+    // On success, it returns 200 OK along with the latest JSON-formatted Measurement of any probe
+    // or, if specified as `/latest/<probe>`, of that probe; e.g.:
     //     {
+    //         "probe": "default",
     //         "ping_latency": 0.918,
     //         "download_speed": 941.300376,
     //         "upload_speed": 941.043264
     //     }
-    // On failure, it returns 500 INTERNAL SERVER ERROR.
+    // If no measurement of the specified probe has been received yet, it returns 404; on failure,
+    // it returns 500 INTERNAL SERVER ERROR.
     fn endpoint_latest(
-        latest_measurement: Arc<Mutex<Measurement>>,
+        latest_measurement: Arc<Mutex<Latest>>,
     ) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
         warp::get()
             .and(warp::path("latest"))
+            .and(Self::probe_name())
+            .map(
+                move |name: Option<String>| match latest_measurement.lock() {
+                    Ok(latest_measurement) => match name {
+                        None => warp::reply::with_status(
+                            warp::reply::json(&latest_measurement.overall),
+                            StatusCode::OK,
+                        ),
+                        Some(name) => match latest_measurement.per_probe.get(&name) {
+                            Some(measurement) => warp::reply::with_status(
+                                warp::reply::json(measurement),
+                                StatusCode::OK,
+                            ),
+                            None => warp::reply::with_status(
+                                warp::reply::json(&format!("No measurements of probe '{}'", name)),
+                                StatusCode::NOT_FOUND,
+                            ),
+                        },
+                    },
+                    Err(e) => {
+                        error!("Failed to acquire lock for latest measurement: {}", e);
+                        warp::reply::with_status(
+                            warp::reply::json(&format!("Internal synchronization error: {}", e)),
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                        )
+                    }
+                },
+            )
+            .with(warp::reply::with::header(
+                "Content-Type",
+                "application/json",
+            ))
+            .with(warp::trace::named("/latest"))
+            .boxed()
+    }
+
+    // Returns 200 OK along with metrics about the measurements received, in the Prometheus text
+    // exposition format: per probe, the download and upload speeds, ping latency and jitter of the
+    // latest successful measurement (labelled with the measurer, network interface and server,
+    // where known), when it was received, the numbers of rounds and failed rounds, and a histogram
+    // of how long measuring took, per measurer.
+    // On failure, it returns 500 INTERNAL SERVER ERROR.
+    fn endpoint_metrics(
+        metrics: Arc<Mutex<Metrics>>,
+    ) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+        warp::get()
+            .and(warp::path("metrics"))
             .and(warp::path::end())
-            .map(move || match latest_measurement.lock() {
-                Ok(latest_measurement) => warp::reply::with_status(
-                    warp::reply::json(&*latest_measurement),
-                    StatusCode::OK,
-                ),
+            .map(move || match metrics.lock() {
+                Ok(metrics) => warp::reply::with_status(metrics.render(None), StatusCode::OK),
+                Err(e) => {
+                    error!("Failed to acquire lock for metrics: {}", e);
+                    warp::reply::with_status(
+                        format!("Internal synchronization error: {}", e),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )
+                }
+            })
+            .with(warp::reply::with::header(
+                "Content-Type",
+                "text/plain; version=0.0.4",
+            ))
+            .with(warp::trace::named("/metrics"))
+            .boxed()
+    }
+
+    // On success, it returns the JSON-formatted health of the Database and exporters (including
+    // this one), along with 200 OK if all of them are running or 503 SERVICE UNAVAILABLE if any of
+    // them died and is waiting to be restarted; e.g.:
+    //     {
+    //         "database": {
+    //             "state": "running",
+    //             "since": "2021-09-20T18:35:10.470981524+03:00",
+    //             "restarts": 0
+    //         },
+    //         "twitter": {
+    //             "state": "restarting",
+    //             "since": "2021-09-20T18:45:12.118354218+03:00",
+    //             "restarts": 2,
+    //             "last_failure": "failed: task 12 panicked",
+    //             "next_restart": "2021-09-20T18:45:16.118354218+03:00"
+    //         }
+    //     }
+    // On failure, it returns 500 INTERNAL SERVER ERROR.
+    fn endpoint_health(
+        health: supervisor::Registry,
+    ) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+        warp::get()
+            .and(warp::path("health"))
+            .and(warp::path::end())
+            .map(move || match health.lock() {
+                Ok(health) => {
+                    let status = match health.values().all(|h| h.state == State::Running) {
+                        true => StatusCode::OK,
+                        false => StatusCode::SERVICE_UNAVAILABLE,
+                    };
+                    warp::reply::with_status(warp::reply::json(&*health), status)
+                }
                 Err(e) => {
-                    error!("Failed to acquire lock for latest measurement: {}", e);
+                    error!("Failed to acquire lock for actor health: {}", e);
                     warp::reply::with_status(
                         warp::reply::json(&format!("Internal synchronization error: {}", e)),
                         StatusCode::INTERNAL_SERVER_ERROR,
@@ -159,13 +421,254 @@ impl Http {
                 "Content-Type",
                 "application/json",
             ))
-            .with(warp::trace::named("/latest"))
+            .with(warp::trace::named("/health"))
+            .boxed()
+    }
+
+    // Triggers an immediate round of measuring and exporting (coalesced with any other pending
+    // ones) of the probe specified as `probe=<name>` in the query string, or of all probes if none
+    // is specified.
+    // If `wait=true` is passed in the query string, it returns 200 OK along with the JSON-formatted
+    // Measurement once the round is over, or 429 TOO MANY REQUESTS along with a `Retry-After`
+    // header if the latest round began too recently; otherwise, it returns 202 ACCEPTED right
+    // away.
+    // Waiting requires a single probe to be selected, hence it returns 400 BAD REQUEST if multiple
+    // probes are configured and none is specified; if no such probe exists, it returns 404.
+    // On failure, it returns 503 SERVICE UNAVAILABLE.
+    // It is not served (i.e., it returns 404) if no `auth_token` is configured, unless
+    // unauthenticated access has been allowed.
+    fn endpoint_measure(
+        probes: probe::Registry,
+        access: Access,
+    ) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+        // Match the path before the method, so that other paths are not responded to with 405
+        warp::path("measure")
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(Self::authorized(access))
+            .and(warp::query::<MeasureQuery>())
+            .and_then(move |query: MeasureQuery| Self::trigger(Self::snapshot(&probes), query))
+            .with(warp::reply::with::header(
+                "Content-Type",
+                "application/json",
+            ))
+            .with(warp::trace::named("/measure"))
+            .boxed()
+    }
+
+    async fn trigger(
+        probes: BTreeMap<String, probe::Handle>,
+        query: MeasureQuery,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let triggers: Vec<_> = match query.probe {
+            Some(ref name) => match probes.get(name) {
+                Some(handle) => vec![handle.trigger.clone()],
+                None => {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&format!("No such probe: '{}'", name)),
+                        StatusCode::NOT_FOUND,
+                    )
+                    .into_response())
+                }
+            },
+            None => probes.values().map(|h| h.trigger.clone()).collect(),
+        };
+        if query.wait && triggers.len() > 1 {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&"Waiting requires a probe to be specified as 'probe=<name>'"),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response());
+        }
+
+        let (mut reply_tx, reply_rx) = if query.wait {
+            let (tx, rx) = oneshot::channel();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+        for trigger in triggers {
+            if let Err(e) = trigger.send(Trigger::new(reply_tx.take())).await {
+                error!("Failed to trigger an on-demand round: {}", e);
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&"Monitor is unavailable"),
+                    StatusCode::SERVICE_UNAVAILABLE,
+                )
+                .into_response());
+            }
+        }
+        let reply_rx = match reply_rx {
+            Some(reply_rx) => reply_rx,
+            None => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&"An on-demand round has been triggered"),
+                    StatusCode::ACCEPTED,
+                )
+                .into_response())
+            }
+        };
+
+        Ok(match reply_rx.await {
+            Ok(Ok(measurement)) => {
+                warp::reply::with_status(warp::reply::json(&measurement), StatusCode::OK)
+                    .into_response()
+            }
+            Ok(Err(e @ TriggerError::TooSoon(retry_after))) => warp::reply::with_header(
+                warp::reply::with_status(
+                    warp::reply::json(&e.to_string()),
+                    StatusCode::TOO_MANY_REQUESTS,
+                ),
+                "Retry-After",
+                // Round up to whole seconds
+                (retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)).to_string(),
+            )
+            .into_response(),
+            Ok(Err(e)) => warp::reply::with_status(
+                warp::reply::json(&e.to_string()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            )
+            .into_response(),
+            Err(e) => {
+                error!("Failed waiting for the on-demand round: {}", e);
+                warp::reply::with_status(
+                    warp::reply::json(&"Monitor is unavailable"),
+                    StatusCode::SERVICE_UNAVAILABLE,
+                )
+                .into_response()
+            }
+        })
+    }
+
+    // Changes how rounds are scheduled, for the probe specified as `probe=<name>` in the query
+    // string or for all probes if none is specified, without restarting them:
+    // - `/admin/pause` pauses scheduled rounds (on-demand ones still run);
+    // - `/admin/resume` resumes scheduled rounds;
+    // - `/admin/period?period=<duration>` schedules rounds on the given period from now on.
+    // On success, it returns 200 OK. If the change cannot be applied (e.g., setting the period of a
+    // probe scheduled through cron expressions), it returns 400 BAD REQUEST along with the reason;
+    // if no such probe exists, it returns 404.
+    // On failure, it returns 503 SERVICE UNAVAILABLE.
+    // It is not served (i.e., it returns 404) if no `auth_token` is configured, unless
+    // unauthenticated access has been allowed.
+    fn endpoint_admin(
+        probes: probe::Registry,
+        access: Access,
+    ) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+        // Match the path before the method, so that other paths are not responded to with 405
+        warp::path("admin")
+            .and(warp::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(Self::authorized(access))
+            .and(warp::query::<AdminQuery>())
+            .and_then(move |action: String, query: AdminQuery| {
+                Self::control(Self::snapshot(&probes), action, query)
+            })
+            .with(warp::reply::with::header(
+                "Content-Type",
+                "application/json",
+            ))
+            .with(warp::trace::named("/admin"))
+            .boxed()
+    }
+
+    async fn control(
+        probes: BTreeMap<String, probe::Handle>,
+        action: String,
+        query: AdminQuery,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let reply = |body: &str, status| {
+            Ok(warp::reply::with_status(warp::reply::json(&body), status).into_response())
+        };
+        let control = match (action.as_str(), query.period) {
+            ("pause", _) => Control::Pause,
+            ("resume", _) => Control::Resume,
+            ("period", Some(period)) => Control::SetPeriod(period),
+            ("period", None) => {
+                return reply(
+                    "A period must be specified as 'period=<duration>'",
+                    StatusCode::BAD_REQUEST,
+                )
+            }
+            (action, _) => {
+                return reply(
+                    &format!("Unknown action: '{}'", action),
+                    StatusCode::NOT_FOUND,
+                )
+            }
+        };
+        let selected: Vec<_> = match query.probe {
+            Some(ref name) => match probes.get_key_value(name) {
+                Some(probe) => vec![probe],
+                None => return reply(&format!("No such probe: '{}'", name), StatusCode::NOT_FOUND),
+            },
+            None => probes.iter().collect(),
+        };
+
+        let mut errors = vec![];
+        let mut status = StatusCode::OK;
+        for (name, handle) in selected {
+            match handle.control(control.clone()).await {
+                Ok(()) => {}
+                Err(e @ ControlError::Invalid(_)) => {
+                    status = status.max(StatusCode::BAD_REQUEST);
+                    errors.push(format!("probe '{}': {}", name, e));
+                }
+                Err(e @ ControlError::Unavailable) => {
+                    error!("Failed to control probe '{}': {}", name, e);
+                    status = StatusCode::SERVICE_UNAVAILABLE;
+                    errors.push(format!("probe '{}': {}", name, e));
+                }
+            }
+        }
+        if errors.is_empty() {
+            reply("OK", StatusCode::OK)
+        } else {
+            reply(&errors.join("; "), status)
+        }
+    }
+
+    // Rejects requests that do not carry the configured token as `Authorization: Bearer <token>`
+    // with 401 UNAUTHORIZED or, if no token is configured and unauthenticated access has not been
+    // allowed, all requests as if the endpoint did not exist.
+    fn authorized(access: Access) -> warp::filters::BoxedFilter<()> {
+        warp::header::optional::<String>("authorization")
+            .and_then(move |header: Option<String>| {
+                let access = access.clone();
+                async move {
+                    let expected = match access {
+                        Access::Token(AuthToken(token)) => token,
+                        Access::Anyone => return Ok(()),
+                        Access::NoOne => return Err(warp::reject::not_found()),
+                    };
+                    match header.as_deref().and_then(|h| h.strip_prefix("Bearer ")) {
+                        Some(given) if constant_time_eq(given.as_bytes(), expected.as_bytes()) => {
+                            Ok(())
+                        }
+                        _ => Err(warp::reject::custom(Unauthorized)),
+                    }
+                }
+            })
+            .untuple_one()
             .boxed()
     }
 
+    // Responds to requests rejected for lacking the configured token with 401 UNAUTHORIZED; any
+    // other rejections are handled by warp as usual.
+    async fn recover(rejection: warp::Rejection) -> Result<warp::reply::Response, warp::Rejection> {
+        if rejection.find::<Unauthorized>().is_none() {
+            return Err(rejection);
+        }
+        Ok(warp::reply::with_header(
+            warp::reply::with_status(warp::reply::json(&"Unauthorized"), StatusCode::UNAUTHORIZED),
+            "WWW-Authenticate",
+            "Bearer",
+        )
+        .into_response())
+    }
+
     // If the `plot` Cargo feature is enabled, this endpoint returns a plot image, either PNG (if
-    // the `twitter` Cargo feature is enabled) or SVG (if the `twitter` Cargo feature is not
-    // enabled).
+    // the `twitter` or `chat` Cargo features are enabled) or SVG (otherwise).
     // If the `plot` Cargo feature is not enabled, it returns 404 and an error message as a plain
     // String.
     fn endpoint_plot(
@@ -176,15 +679,15 @@ impl Http {
             #[cfg(feature = "plot")]
             {
                 let ret = ret.and(warp::fs::file(_plot_path.expect("Http.plot_path is None")));
-                #[cfg(feature = "twitter")]
+                #[cfg(any(feature = "twitter", feature = "chat"))]
                 {
-                    // If the "twitter" Cargo feature is enabled, plots are PNG, which are
-                    // supported by the Twitter API.
+                    // If the "twitter" or "chat" Cargo features are enabled, plots are PNG, which
+                    // are supported by the Twitter API and by chat services.
                     ret.with(warp::reply::with::header("Content-Type", "image/png"))
                 }
-                #[cfg(not(feature = "twitter"))]
+                #[cfg(not(any(feature = "twitter", feature = "chat")))]
                 {
-                    // If the "twitter" Cargo feature is disabled, plots are SVG, which are better.
+                    // Otherwise, plots are SVG, which are better.
                     ret.with(warp::reply::with::header("Content-Type", "image/svg+xml"))
                 }
             }
@@ -207,3 +710,91 @@ impl Http {
         .boxed()
     }
 }
+
+/// Compares the given byte strings in time that does not depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[async_trait]
+impl Exporter for Http {
+    /// Spawns the HTTP server as a separate task.
+    #[tracing::instrument(skip(self))]
+    async fn init(&mut self) -> Result<()> {
+        // Endpoints
+        let period = Self::endpoint_period(self.probes.clone());
+        let schedule = Self::endpoint_schedule(self.probes.clone());
+        let latest = Self::endpoint_latest(self.latest.clone());
+        let metrics = Self::endpoint_metrics(self.metrics.clone());
+        let plot = Self::endpoint_plot(self.plot_path.clone());
+        let health = Self::endpoint_health(self.health.clone());
+        let measure = Self::endpoint_measure(self.probes.clone(), self.access.clone());
+        let admin = Self::endpoint_admin(self.probes.clone(), self.access.clone());
+        match self.access {
+            Access::Token(_) => {}
+            Access::Anyone => warn!(
+                "Serving '/measure' and '/admin/*' on {} to anyone, without authentication",
+                self.bind_addr
+            ),
+            Access::NoOne => {
+                info!("Not serving '/measure' and '/admin/*', since no 'auth_token' is configured")
+            }
+        }
+        let routes = period
+            .or(schedule)
+            .or(latest)
+            .or(metrics)
+            .or(plot)
+            .or(health)
+            .or(measure)
+            .or(admin)
+            .recover(Self::recover);
+
+        // We are using a `oneshot` channel to notify the server to gracefully terminate once the
+        // exporter is signalled to.
+        let (sqtx, sqrx) = oneshot::channel();
+        let (addr, server) = warp::serve(routes)
+            .try_bind_with_graceful_shutdown(self.bind_addr, async {
+                sqrx.await.ok();
+            })
+            .with_context(|| format!("failed to bind to {}", self.bind_addr))?;
+        debug!("Binding to {} and serving...", addr);
+        self.server = Some((sqtx, tokio::spawn(server)));
+        Ok(())
+    }
+
+    async fn on_measurement(&mut self, measurement: Measurement) {
+        trace!("Serving new measurements");
+        match self.metrics.lock() {
+            Ok(mut metrics) => metrics.record(&measurement),
+            Err(e) => error!("Failed to acquire lock for metrics: {}", e),
+        }
+        // Skipped rounds did not measure anything, so keep serving the latest actual measurement
+        if measurement.skipped.is_some() {
+            return;
+        }
+        match self.latest.lock() {
+            Ok(ref mut latest) => {
+                if let Some(ref probe) = measurement.probe {
+                    latest.per_probe.insert(probe.clone(), measurement.clone());
+                }
+                latest.overall = measurement;
+            }
+            Err(e) => error!("Failed to acquire latest_measurement lock: {}", e),
+        }
+    }
+
+    async fn on_shutdown(&mut self) {
+        let (sqtx, server_handle) = match self.server.take() {
+            Some(server) => server,
+            None => return,
+        };
+        if let Err(e) = sqtx.send(()) {
+            warn!("Failed to signal the HTTP server task: {:?}", e);
+        } else if let Err(e) = server_handle.await {
+            warn!("Failed to wait for the HTTP server task: {}", e);
+        } else {
+            info!("HTTP server task has been successfully shut down");
+        }
+    }
+}
@@ -0,0 +1,469 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Debug,
+    time::Duration,
+};
+
+use anyhow::{bail, Context as _, Result};
+use async_trait::async_trait;
+use chrono::Local;
+use reqwest::{header, Client, RequestBuilder, Url};
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::{debug, info, trace, warn};
+
+use super::{is_retryable, Context, Exporter};
+use crate::{measure::Measurement, probe::DEFAULT_NAME};
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The base URL of the InfluxDB server (e.g., `http://influxdb:8086`).
+    url: String,
+    /// The organization and bucket to write to, through the v2 API (`/api/v2/write`).
+    org: Option<String>,
+    bucket: Option<String>,
+    /// The token to authenticate with to the v2 API.
+    token: Option<Secret>,
+    /// The database (and, optionally, its retention policy) to write to, through the v1 API
+    /// (`/write`).
+    database: Option<String>,
+    #[serde(alias = "RetentionPolicy")]
+    retention_policy: Option<String>,
+    /// The credentials to authenticate with to the v1 API.
+    username: Option<String>,
+    password: Option<Secret>,
+    /// The name of the InfluxDB measurement to write points to; `"netspeedmon"`, if absent.
+    measurement: Option<String>,
+    /// Tags to add to all points, apart from `probe`, `measurer`, `interface` and `server`.
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+    /// How many points to write at once; 1, if absent.
+    #[serde(alias = "BatchSize")]
+    batch_size: Option<usize>,
+    /// How long a point may wait for its batch to fill up, before being written anyway; 1 minute,
+    /// if absent.
+    #[serde(default, with = "humantime_serde", alias = "BatchTimeout")]
+    batch_timeout: Option<Duration>,
+    /// How many points that failed to be written to keep in memory, to retry them; 10000, if
+    /// absent.
+    #[serde(alias = "BufferLimit")]
+    buffer_limit: Option<usize>,
+    /// How long to wait before retrying failed writes; 30 seconds, if absent.
+    #[serde(default, with = "humantime_serde", alias = "RetryInterval")]
+    retry_interval: Option<Duration>,
+    /// How long to wait for each write to be responded to; 10 seconds, if absent.
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+}
+
+/// A secret, which is kept out of logs.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+struct Secret(String);
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+#[derive(Debug)]
+enum Auth {
+    None,
+    /// `Authorization: Token <token>`, for the v2 API.
+    Token(Secret),
+    /// HTTP basic authentication, for the v1 API.
+    Basic(String, Option<Secret>),
+}
+
+/// Writes each measurement as a point (in line protocol) to InfluxDB, in batches, keeping those
+/// that fail to be written in memory (up to a limit) to retry them.
+#[derive(Debug)]
+pub(crate) struct InfluxDb {
+    client: Client,
+    /// The URL to write to, including the query string that selects the bucket or database.
+    write_url: Url,
+    auth: Auth,
+    measurement: String,
+    tags: BTreeMap<String, String>,
+    batch_size: usize,
+    batch_timeout: Duration,
+    buffer_limit: usize,
+    retry_interval: Duration,
+    /// The points yet to be written, oldest first.
+    pending: VecDeque<String>,
+    /// When the oldest pending point was received.
+    pending_since: Option<Instant>,
+    /// When to retry writing, after the latest write failed.
+    retry_at: Option<Instant>,
+}
+
+impl InfluxDb {
+    const DEFAULT_MEASUREMENT: &'static str = "netspeedmon";
+    const DEFAULT_BATCH_TIMEOUT: Duration = Duration::from_secs(60);
+    const DEFAULT_BUFFER_LIMIT: usize = 10_000;
+    const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[tracing::instrument(skip(_ctx))]
+    pub(crate) fn new(config: Config, _ctx: &Context) -> Result<Self> {
+        trace!("Creating new '{}'...", std::any::type_name::<Self>());
+        let mut write_url =
+            Url::parse(&config.url).with_context(|| format!("invalid URL '{}'", config.url))?;
+        if write_url.cannot_be_a_base() {
+            bail!("the InfluxDB URL cannot be a base URL");
+        }
+        let (path, auth) = match (config.bucket, config.database) {
+            (Some(bucket), None) => {
+                let org = match config.org {
+                    Some(org) => org,
+                    None => bail!("writing to a 'bucket' requires an 'org'"),
+                };
+                write_url
+                    .query_pairs_mut()
+                    .append_pair("org", &org)
+                    .append_pair("bucket", &bucket)
+                    .append_pair("precision", "ms");
+                let auth = config.token.map_or(Auth::None, Auth::Token);
+                (&["api", "v2", "write"][..], auth)
+            }
+            (None, Some(database)) => {
+                write_url
+                    .query_pairs_mut()
+                    .append_pair("db", &database)
+                    .append_pair("precision", "ms");
+                if let Some(rp) = config.retention_policy {
+                    write_url.query_pairs_mut().append_pair("rp", &rp);
+                }
+                let auth = match config.username {
+                    Some(username) => Auth::Basic(username, config.password),
+                    None => Auth::None,
+                };
+                (&["write"][..], auth)
+            }
+            _ => bail!("either a 'bucket' (v2 API) or a 'database' (v1 API) must be configured"),
+        };
+        write_url
+            .path_segments_mut()
+            .expect("the InfluxDB URL cannot be a base URL")
+            .pop_if_empty()
+            .extend(path);
+        let client = Client::builder()
+            .timeout(config.timeout.unwrap_or(Self::DEFAULT_TIMEOUT))
+            .build()
+            .with_context(|| "failed to build the HTTP client")?;
+        Ok(Self {
+            client,
+            write_url,
+            auth,
+            measurement: config
+                .measurement
+                .unwrap_or_else(|| Self::DEFAULT_MEASUREMENT.to_owned()),
+            tags: config.tags,
+            batch_size: config.batch_size.unwrap_or(1).max(1),
+            batch_timeout: config.batch_timeout.unwrap_or(Self::DEFAULT_BATCH_TIMEOUT),
+            buffer_limit: config.buffer_limit.unwrap_or(Self::DEFAULT_BUFFER_LIMIT),
+            retry_interval: config
+                .retry_interval
+                .unwrap_or(Self::DEFAULT_RETRY_INTERVAL),
+            pending: VecDeque::new(),
+            pending_since: None,
+            retry_at: None,
+        })
+    }
+
+    /// Formats the given measurement as a point in line protocol, with the given timestamp (in
+    /// milliseconds since the Unix epoch).
+    fn line(&self, measurement: &Measurement, timestamp: i64) -> String {
+        let mut tags = self.tags.clone();
+        let probe = measurement.probe.as_deref().unwrap_or(DEFAULT_NAME);
+        tags.insert("probe".to_owned(), probe.to_owned());
+        let known = [
+            ("measurer", &measurement.measurer),
+            ("interface", &measurement.interface),
+            ("server", &measurement.server),
+        ];
+        for (name, value) in known.iter() {
+            if let Some(value) = value {
+                tags.insert((*name).to_owned(), value.clone());
+            }
+        }
+
+        let mut fields = vec![
+            ("ping_latency", Field::Float(measurement.ping_latency)),
+            ("download_speed", Field::Float(measurement.download_speed)),
+            ("upload_speed", Field::Float(measurement.upload_speed)),
+            ("failed", Field::Bool(measurement.is_failed())),
+        ];
+        if let Some(jitter) = measurement.jitter {
+            fields.push(("jitter", Field::Float(jitter)));
+        }
+        if let Some(duration) = measurement.duration {
+            fields.push(("duration", Field::Float(duration.as_secs_f64())));
+        }
+        if let Some(ref stats) = measurement.latency_stats {
+            fields.extend(
+                [
+                    ("latency_min", stats.min),
+                    ("latency_p50", stats.p50),
+                    ("latency_p90", stats.p90),
+                    ("latency_p99", stats.p99),
+                    ("latency_max", stats.max),
+                    ("latency_stddev", stats.stddev),
+                ]
+                .iter()
+                .map(|(name, value)| (*name, Field::Float(*value))),
+            );
+        }
+        let reasons = [
+            ("suspect", &measurement.suspect),
+            ("downgraded", &measurement.downgraded),
+            ("deferred", &measurement.deferred),
+        ];
+        for (name, reason) in reasons.iter() {
+            if let Some(reason) = reason {
+                fields.push((name, Field::String(reason.clone())));
+            }
+        }
+
+        let mut line = escape(&self.measurement, &[',', ' ']);
+        for (name, value) in tags.iter().filter(|(_, value)| !value.is_empty()) {
+            let special = [',', '=', ' '];
+            line.push_str(&format!(
+                ",{}={}",
+                escape(name, &special),
+                escape(value, &special)
+            ));
+        }
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|(name, value)| format!("{}={}", escape(name, &[',', '=', ' ']), value))
+            .collect();
+        format!("{} {} {}", line, fields.join(","), timestamp)
+    }
+
+    fn authenticated(&self, request: RequestBuilder) -> RequestBuilder {
+        match self.auth {
+            Auth::None => request,
+            Auth::Token(ref token) => {
+                request.header(header::AUTHORIZATION, format!("Token {}", token.0))
+            }
+            Auth::Basic(ref username, ref password) => {
+                request.basic_auth(username, password.as_ref().map(|password| &password.0))
+            }
+        }
+    }
+
+    /// Writes all pending points, in batches, stopping at the first batch that fails to be
+    /// written in a way that is worth retrying (to be retried later), and then drops the oldest
+    /// points that exceed the limit; batches that are rejected (e.g., as malformed) are dropped.
+    async fn flush(&mut self) {
+        self.retry_at = None;
+        while !self.pending.is_empty() {
+            let len = self.pending.len().min(self.batch_size);
+            let body = self
+                .pending
+                .range(..len)
+                .fold(String::new(), |mut body, line| {
+                    body.push_str(line);
+                    body.push('\n');
+                    body
+                });
+            let request = self
+                .client
+                .post(self.write_url.clone())
+                .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                .body(body);
+            let error = match self.authenticated(request).send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Wrote {} points", len);
+                    self.pending.drain(..len);
+                    continue;
+                }
+                Ok(response) if !is_retryable(response.status()) => {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    warn!(
+                        "Failed to write {} points (dropping them): {}: {}",
+                        len,
+                        status,
+                        body.trim()
+                    );
+                    self.pending.drain(..len);
+                    continue;
+                }
+                Ok(response) => response.status().to_string(),
+                Err(e) => e.to_string(),
+            };
+            warn!(
+                "Failed to write {} points (retrying in {}): {}",
+                len,
+                humantime::format_duration(self.retry_interval),
+                error
+            );
+            self.retry_at = Some(Instant::now() + self.retry_interval);
+            break;
+        }
+        let excess = self.pending.len().saturating_sub(self.buffer_limit);
+        if excess > 0 {
+            warn!(
+                "Dropping the {} oldest points that exceed the buffer",
+                excess
+            );
+            self.pending.drain(..excess);
+        }
+        self.pending_since = match self.pending.is_empty() {
+            true => None,
+            false => self.pending_since.or_else(|| Some(Instant::now())),
+        };
+    }
+}
+
+#[async_trait]
+impl Exporter for InfluxDb {
+    async fn on_measurement(&mut self, measurement: Measurement) {
+        // Skipped rounds did not measure anything
+        if measurement.skipped.is_some() {
+            return;
+        }
+        let line = self.line(&measurement, Local::now().timestamp_millis());
+        trace!("New point: {}", line);
+        self.pending.push_back(line);
+        self.pending_since.get_or_insert_with(Instant::now);
+        // While failing, wait for the retry instead
+        if self.retry_at.is_none() && self.pending.len() >= self.batch_size {
+            self.flush().await;
+        } else if self.pending.len() > self.buffer_limit {
+            warn!("Dropping the oldest point, which exceeds the buffer");
+            self.pending.pop_front();
+        }
+    }
+
+    fn wake_at(&self) -> Option<Instant> {
+        self.retry_at.or_else(|| {
+            self.pending_since
+                .map(|pending_since| pending_since + self.batch_timeout)
+        })
+    }
+
+    async fn on_wake(&mut self) {
+        self.flush().await
+    }
+
+    async fn on_shutdown(&mut self) {
+        if !self.pending.is_empty() {
+            info!("Writing {} pending points", self.pending.len());
+            self.flush().await;
+        }
+        if !self.pending.is_empty() {
+            warn!("Giving up on {} pending points", self.pending.len());
+        }
+    }
+}
+
+/// The value of a field of a point.
+enum Field {
+    Float(f64),
+    Bool(bool),
+    String(String),
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Float(value) => write!(f, "{}", value),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::String(value) => write!(f, "\"{}\"", escape(value, &['"', '\\'])),
+        }
+    }
+}
+
+/// Escapes the given characters (and backslashes, for identifiers) with backslashes.
+fn escape(value: &str, special: &[char]) -> String {
+    let mut ret = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::exporters::tests::responder;
+
+    fn influxdb(url: &str) -> InfluxDb {
+        let config = Config {
+            url: url.to_owned(),
+            org: Some("home".to_owned()),
+            bucket: Some("net".to_owned()),
+            token: None,
+            database: None,
+            retention_policy: None,
+            username: None,
+            password: None,
+            measurement: Some("speed test".to_owned()),
+            tags: vec![("site".to_owned(), "lab, 2nd floor".to_owned())]
+                .into_iter()
+                .collect(),
+            batch_size: Some(1),
+            batch_timeout: None,
+            buffer_limit: None,
+            retry_interval: None,
+            timeout: None,
+        };
+        let ctx = Context {
+            name: "influxdb".to_owned(),
+            plot_path: None,
+            probes: Default::default(),
+            health: Default::default(),
+        };
+        InfluxDb::new(config, &ctx).unwrap()
+    }
+
+    #[test]
+    fn line() {
+        let influxdb = influxdb("http://localhost:8086");
+        assert_eq!(
+            influxdb.write_url.as_str(),
+            "http://localhost:8086/api/v2/write?org=home&bucket=net&precision=ms"
+        );
+        let measurement = Measurement {
+            probe: Some("wan".to_owned()),
+            measurer: Some("ookla".to_owned()),
+            suspect: Some("too \"fast\"".to_owned()),
+            ..(3.5, 94.25, 9.5).into()
+        };
+        assert_eq!(
+            influxdb.line(&measurement, 42),
+            r#"speed\ test,measurer=ookla,probe=wan,site=lab\,\ 2nd\ floor ping_latency=3.5,download_speed=94.25,upload_speed=9.5,failed=false,suspect="too \"fast\"" 42"#
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected() {
+        let measurement: Measurement = (3.5, 94.25, 9.5).into();
+
+        // Rejected points are dropped...
+        let (url, requests) = responder(400).await;
+        let mut db = influxdb(&url);
+        db.on_measurement(measurement.clone()).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(db.pending.is_empty());
+        assert_eq!(db.wake_at(), None);
+
+        // ...whereas the rest are retried
+        let (url, requests) = responder(503).await;
+        let mut db = influxdb(&url);
+        db.on_measurement(measurement.clone()).await;
+        db.on_measurement(measurement).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(db.pending.len(), 2);
+        assert!(db.wake_at().is_some());
+    }
+}
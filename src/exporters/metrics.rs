@@ -0,0 +1,375 @@
+//! Metrics about the measurements received, which can be rendered in the Prometheus text
+//! exposition format.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use chrono::{DateTime, Local};
+
+use crate::{measure::Measurement, probe::DEFAULT_NAME};
+
+/// The upper bounds (in seconds) of the buckets of the measurer duration histograms.
+const DURATION_BUCKETS: [f64; 12] = [1., 2.5, 5., 10., 15., 20., 30., 45., 60., 90., 120., 300.];
+
+/// The name and help of a gauge, along with how to compute its value out of a measurement.
+type Gauge = (&'static str, &'static str, fn(&Measurement) -> Option<f64>);
+/// The name and help of a counter, along with how to read it.
+type Counter = (&'static str, &'static str, fn(&ProbeMetrics) -> u64);
+
+/// Metrics about the measurements of all probes, by probe.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    probes: BTreeMap<String, ProbeMetrics>,
+}
+
+#[derive(Debug, Default)]
+struct ProbeMetrics {
+    /// The latest measurement that did not fail.
+    latest: Option<Measurement>,
+    /// When the latest measurement that did not fail was received.
+    last_success: Option<DateTime<Local>>,
+    rounds: u64,
+    failed_rounds: u64,
+    /// How long measuring took, by kind of `Measurer`.
+    durations: BTreeMap<String, Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// The (cumulative) number of observations that fall within each of `DURATION_BUCKETS`.
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS.iter()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// A metric, along with all of its samples.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Family {
+    pub(crate) name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    pub(crate) samples: Vec<Sample>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Sample {
+    /// The name of the sample, which differs from that of its metric for histograms (e.g.,
+    /// `_bucket`, `_sum`, `_count`).
+    pub(crate) name: String,
+    pub(crate) labels: Vec<(&'static str, String)>,
+    pub(crate) value: f64,
+}
+
+impl Family {
+    fn new(name: &'static str, help: &'static str, kind: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            kind,
+            samples: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, suffix: &str, labels: Vec<(&'static str, String)>, value: f64) {
+        self.samples.push(Sample {
+            name: format!("{}{}", self.name, suffix),
+            labels,
+            value,
+        });
+    }
+}
+
+impl Metrics {
+    /// Accounts for a new measurement.
+    pub(crate) fn record(&mut self, measurement: &Measurement) {
+        // Skipped rounds did not measure anything
+        if measurement.skipped.is_some() {
+            return;
+        }
+        let probe = measurement.probe.as_deref().unwrap_or(DEFAULT_NAME);
+        let metrics = self.probes.entry(probe.to_owned()).or_default();
+        metrics.rounds += 1;
+        if let (Some(measurer), Some(duration)) = (&measurement.measurer, measurement.duration) {
+            metrics
+                .durations
+                .entry(measurer.clone())
+                .or_default()
+                .observe(duration.as_secs_f64());
+        }
+        if measurement.is_failed() {
+            metrics.failed_rounds += 1;
+        } else {
+            metrics.latest = Some(measurement.clone());
+            metrics.last_success = Some(Local::now());
+        }
+    }
+
+    /// Returns all metrics about the given probe or, if none is given, about all probes.
+    pub(crate) fn families(&self, probe: Option<&str>) -> Vec<Family> {
+        let probes: Vec<_> = self
+            .probes
+            .iter()
+            .filter(|(name, _)| match probe {
+                Some(probe) => probe == name.as_str(),
+                None => true,
+            })
+            .collect();
+        let probe_label = |probe: &str| vec![("probe", probe.to_owned())];
+
+        let gauges: [Gauge; 4] = [
+            (
+                "netspeedmon_download_bits_per_second",
+                "Download speed of the latest successful measurement.",
+                |m| Some(m.download_speed * 1e6),
+            ),
+            (
+                "netspeedmon_upload_bits_per_second",
+                "Upload speed of the latest successful measurement.",
+                |m| Some(m.upload_speed * 1e6),
+            ),
+            (
+                "netspeedmon_ping_latency_seconds",
+                "Ping latency of the latest successful measurement.",
+                |m| Some(m.ping_latency / 1e3),
+            ),
+            (
+                "netspeedmon_jitter_seconds",
+                "Ping jitter of the latest successful measurement, if the measurer reports it.",
+                |m| m.jitter.map(|jitter| jitter / 1e3),
+            ),
+        ];
+        let mut ret: Vec<_> = gauges
+            .iter()
+            .map(|(name, help, value)| {
+                let mut family = Family::new(name, help, "gauge");
+                for (probe, metrics) in probes.iter() {
+                    let latest = match metrics.latest {
+                        Some(ref latest) => latest,
+                        None => continue,
+                    };
+                    if let Some(value) = value(latest) {
+                        family.push("", measurement_labels(probe, latest), value);
+                    }
+                }
+                family
+            })
+            .collect();
+
+        let mut family = Family::new(
+            "netspeedmon_last_success_timestamp_seconds",
+            "When the latest successful measurement was received, as a Unix timestamp.",
+            "gauge",
+        );
+        for (probe, metrics) in probes.iter() {
+            if let Some(last_success) = metrics.last_success {
+                let value = last_success.timestamp_millis() as f64 / 1e3;
+                family.push("", probe_label(probe), value);
+            }
+        }
+        ret.push(family);
+
+        let counters: [Counter; 2] = [
+            (
+                "netspeedmon_rounds_total",
+                "Rounds whose measurements have been received.",
+                |metrics| metrics.rounds,
+            ),
+            (
+                "netspeedmon_rounds_failed_total",
+                "Rounds whose measurements have been received, but failed.",
+                |metrics| metrics.failed_rounds,
+            ),
+        ];
+        for (name, help, value) in counters.iter() {
+            let mut family = Family::new(name, help, "counter");
+            for (probe, metrics) in probes.iter() {
+                family.push("", probe_label(probe), value(metrics) as f64);
+            }
+            ret.push(family);
+        }
+
+        let mut family = Family::new(
+            "netspeedmon_measurer_duration_seconds",
+            "How long measuring took.",
+            "histogram",
+        );
+        for (probe, metrics) in probes.iter() {
+            for (measurer, histogram) in metrics.durations.iter() {
+                let labels = vec![("probe", probe.to_string()), ("measurer", measurer.clone())];
+                let with_le = |le: String| {
+                    let mut labels = labels.clone();
+                    labels.push(("le", le));
+                    labels
+                };
+                for (count, bound) in histogram.buckets.iter().zip(DURATION_BUCKETS.iter()) {
+                    family.push("_bucket", with_le(bound.to_string()), *count as f64);
+                }
+                let count = histogram.count as f64;
+                family.push("_bucket", with_le("+Inf".to_owned()), count);
+                family.push("_sum", labels.clone(), histogram.sum);
+                family.push("_count", labels, count);
+            }
+        }
+        ret.push(family);
+        ret
+    }
+
+    /// Renders all metrics about the given probe (or about all probes, if none is given) in the
+    /// Prometheus text exposition format.
+    pub(crate) fn render(&self, probe: Option<&str>) -> String {
+        let mut out = String::new();
+        for family in self.families(probe) {
+            // Writing to a String never fails
+            let _ = writeln!(out, "# HELP {} {}", family.name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", family.name, family.kind);
+            for sample in family.samples {
+                let _ = writeln!(
+                    out,
+                    "{}{} {}",
+                    sample.name,
+                    labels(&sample.labels),
+                    value(sample.value)
+                );
+            }
+        }
+        out
+    }
+}
+
+/// The labels of the given measurement of the given probe, for those of them that are known.
+fn measurement_labels(probe: &str, measurement: &Measurement) -> Vec<(&'static str, String)> {
+    let known = [
+        ("measurer", &measurement.measurer),
+        ("interface", &measurement.interface),
+        ("server", &measurement.server),
+    ];
+    let known = known
+        .iter()
+        .filter_map(|(name, value)| value.as_ref().map(|value| (*name, value.clone())));
+    std::iter::once(("probe", probe.to_owned()))
+        .chain(known)
+        .collect()
+}
+
+fn labels(labels: &[(&str, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<_> = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Formats the given value, spelling non-finite ones the way the text exposition format does
+/// (i.e., `+Inf`, `-Inf` and `NaN`), rather than the way Rust does.
+fn value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value.is_infinite() {
+        match value.is_sign_positive() {
+            true => "+Inf".to_owned(),
+            false => "-Inf".to_owned(),
+        }
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn render() {
+        let mut metrics = Metrics::default();
+        metrics.record(&Measurement {
+            probe: Some("lab".to_owned()),
+            measurer: Some("ookla".to_owned()),
+            duration: Some(Duration::from_secs(12)),
+            server: Some("Some \"ISP\"".to_owned()),
+            ..(3.5, 94.2, 9.4).into()
+        });
+        metrics.record(&Measurement {
+            probe: Some("lab".to_owned()),
+            measurer: Some("ookla".to_owned()),
+            duration: Some(Duration::from_secs(40)),
+            ..Default::default()
+        });
+        metrics.record(&Measurement {
+            probe: Some("wan".to_owned()),
+            ..(1., 2., 3.).into()
+        });
+        let out = metrics.render(None);
+        let labels = r#"{probe="lab",measurer="ookla",server="Some \"ISP\""}"#;
+        for line in [
+            format!("netspeedmon_download_bits_per_second{} 94200000", labels),
+            format!("netspeedmon_ping_latency_seconds{} 0.0035", labels),
+            r#"netspeedmon_rounds_total{probe="lab"} 2"#.to_owned(),
+            r#"netspeedmon_rounds_failed_total{probe="lab"} 1"#.to_owned(),
+            r#"netspeedmon_measurer_duration_seconds_bucket{probe="lab",measurer="ookla",le="10"} 0"#
+                .to_owned(),
+            r#"netspeedmon_measurer_duration_seconds_bucket{probe="lab",measurer="ookla",le="15"} 1"#
+                .to_owned(),
+            r#"netspeedmon_measurer_duration_seconds_bucket{probe="lab",measurer="ookla",le="+Inf"} 2"#
+                .to_owned(),
+            r#"netspeedmon_measurer_duration_seconds_sum{probe="lab",measurer="ookla"} 52"#
+                .to_owned(),
+            r#"netspeedmon_rounds_total{probe="wan"} 1"#.to_owned(),
+        ]
+        .iter()
+        {
+            assert!(out.lines().any(|l| l == line), "missing '{}' in:\n{}", line, out);
+        }
+        // No jitter was reported
+        assert!(!out.contains("netspeedmon_jitter_seconds{"));
+        // Metrics may be restricted to a single probe
+        assert!(!metrics.render(Some("lab")).contains(r#"probe="wan""#));
+    }
+
+    #[test]
+    fn non_finite() {
+        let mut metrics = Metrics::default();
+        metrics.record(&Measurement {
+            probe: Some("lab".to_owned()),
+            jitter: Some(f64::NEG_INFINITY),
+            ..(f64::NAN, f64::INFINITY, 9.4).into()
+        });
+        let out = metrics.render(None);
+        for line in [
+            r#"netspeedmon_download_bits_per_second{probe="lab"} +Inf"#,
+            r#"netspeedmon_ping_latency_seconds{probe="lab"} NaN"#,
+            r#"netspeedmon_jitter_seconds{probe="lab"} -Inf"#,
+        ]
+        .iter()
+        {
+            assert!(
+                out.lines().any(|l| l == *line),
+                "missing '{}' in:\n{}",
+                line,
+                out
+            );
+        }
+    }
+}
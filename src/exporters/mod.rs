@@ -1,6 +1,421 @@
+#[cfg(feature = "chat")]
+pub(super) mod chat;
 pub(super) mod database;
+#[cfg(feature = "email")]
+pub(super) mod email;
 #[cfg(feature = "http")]
 pub(super) mod http;
+#[cfg(feature = "influxdb")]
+pub(super) mod influxdb;
+#[cfg(any(feature = "http", feature = "pushgateway"))]
+pub(super) mod metrics;
+#[cfg(feature = "mqtt")]
+pub(super) mod mqtt;
+#[cfg(feature = "pushgateway")]
+pub(super) mod pushgateway;
 pub(super) mod stdout;
 #[cfg(feature = "twitter")]
 pub(super) mod twitter;
+#[cfg(feature = "webhook")]
+pub(super) mod webhook;
+
+use std::{collections::BTreeMap, fmt::Debug, path::PathBuf};
+
+use anyhow::{bail, Context as _, Result};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use tokio::{
+    sync::{broadcast, watch},
+    time::Instant,
+};
+use tracing::{debug, info, warn};
+
+use crate::{measure::Measurement, probe, supervisor};
+
+/// A sink of measurements, run (and restarted, if it dies) by the Supervisor.
+#[async_trait]
+pub(crate) trait Exporter: Send {
+    /// Prepares the exporter to receive measurements (e.g., binds to its address, verifies its
+    /// credentials); if it fails, the exporter is not run, but retried with backoff.
+    async fn init(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Exports the given measurement.
+    async fn on_measurement(&mut self, measurement: Measurement);
+
+    /// When the exporter needs to be woken up, regardless of measurements (e.g., to flush a batch
+    /// of them, or to retry failed requests), if ever.
+    fn wake_at(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Does whatever the exporter needed to be woken up for.
+    async fn on_wake(&mut self) {}
+
+    /// Cleans up, once all pending measurements have been exported upon being signalled to
+    /// terminate.
+    async fn on_shutdown(&mut self) {}
+}
+
+/// Whatever exporters may need upon being created, apart from their own configuration.
+#[derive(Debug, Clone)]
+pub(crate) struct Context {
+    /// The name of the exporter instance.
+    pub(crate) name: String,
+    /// Where the latest plot is stored, if a Database is configured.
+    #[cfg_attr(
+        not(any(
+            feature = "chat",
+            feature = "email",
+            feature = "http",
+            feature = "twitter"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) plot_path: Option<PathBuf>,
+    /// The `Handle`s of all running probes.
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub(crate) probes: probe::Registry,
+    /// The health of the Database and all exporters.
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub(crate) health: supervisor::Registry,
+}
+
+/// Creates an exporter out of its configuration section.
+type Factory = fn(serde_json::Value, &Context) -> Result<Box<dyn Exporter>>;
+
+/// All kinds of exporters that are available, by the name of their configuration section.
+fn registry() -> Vec<(&'static str, Factory)> {
+    #[allow(unused_mut)]
+    let mut ret: Vec<(&'static str, Factory)> =
+        vec![("stdout", |_, _| Ok(Box::new(stdout::StdOut::new())))];
+    #[cfg(feature = "chat")]
+    ret.push(("discord", |config, ctx| {
+        Ok(Box::new(chat::discord::Discord::new(parse(config)?, ctx)?))
+    }));
+    #[cfg(feature = "email")]
+    ret.push(("email", |config, ctx| {
+        Ok(Box::new(email::Email::new(parse(config)?, ctx)?))
+    }));
+    #[cfg(feature = "http")]
+    ret.push(("http", |config, ctx| {
+        Ok(Box::new(http::Http::new(parse(config)?, ctx)?))
+    }));
+    #[cfg(feature = "influxdb")]
+    ret.push(("influxdb", |config, ctx| {
+        Ok(Box::new(influxdb::InfluxDb::new(parse(config)?, ctx)?))
+    }));
+    #[cfg(feature = "chat")]
+    ret.push(("matrix", |config, ctx| {
+        Ok(Box::new(chat::matrix::Matrix::new(parse(config)?, ctx)?))
+    }));
+    #[cfg(feature = "mqtt")]
+    ret.push(("mqtt", |config, ctx| {
+        Ok(Box::new(mqtt::Mqtt::new(parse(config)?, ctx)?))
+    }));
+    #[cfg(feature = "pushgateway")]
+    ret.push(("pushgateway", |config, ctx| {
+        Ok(Box::new(pushgateway::Pushgateway::new(
+            parse(config)?,
+            ctx,
+        )?))
+    }));
+    #[cfg(feature = "chat")]
+    ret.push(("slack", |config, ctx| {
+        Ok(Box::new(chat::slack::Slack::new(parse(config)?, ctx)?))
+    }));
+    #[cfg(feature = "twitter")]
+    ret.push(("twitter", |config, ctx| {
+        Ok(Box::new(twitter::Twitter::new(parse(config)?, ctx)))
+    }));
+    #[cfg(feature = "webhook")]
+    ret.push(("webhook", |config, ctx| {
+        Ok(Box::new(webhook::Webhook::new(parse(config)?, ctx)?))
+    }));
+    ret
+}
+
+#[cfg_attr(
+    not(any(
+        feature = "chat",
+        feature = "email",
+        feature = "http",
+        feature = "influxdb",
+        feature = "mqtt",
+        feature = "pushgateway",
+        feature = "twitter",
+        feature = "webhook"
+    )),
+    allow(dead_code)
+)]
+fn parse<C: DeserializeOwned>(config: serde_json::Value) -> Result<C> {
+    serde_json::from_value(config).map_err(Into::into)
+}
+
+/// All kinds of exporters, whether the Cargo feature they require is enabled or not.
+const KINDS: &[&str] = &[
+    "discord",
+    "email",
+    "http",
+    "influxdb",
+    "matrix",
+    "mqtt",
+    "pushgateway",
+    "slack",
+    "stdout",
+    "twitter",
+    "webhook",
+];
+
+/// Returns the kind of exporter that the given name refers to (case-insensitively, e.g., `HTTP`),
+/// if it is available.
+fn kind(name: &str) -> Option<&'static str> {
+    registry()
+        .into_iter()
+        .map(|(kind, _)| kind)
+        .find(|kind| kind.eq_ignore_ascii_case(name))
+}
+
+/// Creates an exporter of the given kind, out of its configuration section.
+pub(crate) fn new_exporter(instance: &Instance, ctx: &Context) -> Result<Box<dyn Exporter>> {
+    match registry()
+        .into_iter()
+        .find(|(kind, _)| *kind == instance.kind)
+    {
+        Some((_, factory)) => factory(instance.config.clone(), ctx)
+            .with_context(|| format!("invalid configuration of exporter '{}'", ctx.name)),
+        None => bail!("unsupported exporter kind: '{}'", instance.kind),
+    }
+}
+
+/// A configured exporter.
+#[derive(Clone, PartialEq)]
+pub(crate) struct Instance {
+    pub(crate) kind: String,
+    /// The configuration section of the exporter, as is.
+    pub(crate) config: serde_json::Value,
+}
+
+impl Debug for Instance {
+    // Configuration sections may carry credentials, hence they are kept out of logs
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Instance")
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns all configured exporters, by name, out of:
+/// - the top-level `sections` that are named after a kind of exporter (e.g., `[http]`, `[HTTP]`
+///   or `stdout = true`), each configuring an exporter of that kind by the same (lowercase) name;
+/// - the `[exporter.<name>]` sections, each configuring an exporter of the kind specified as
+///   `kind`, or of the kind it is named after if absent.
+pub(crate) fn instances(
+    sections: &BTreeMap<String, serde_json::Value>,
+    named: &BTreeMap<String, serde_json::Value>,
+) -> Result<BTreeMap<String, Instance>> {
+    let mut ret = BTreeMap::new();
+    for (name, config) in sections {
+        let config = match enabled(config) {
+            Some(config) => config,
+            None => continue,
+        };
+        let kind = match kind(name) {
+            Some(kind) => kind.to_owned(),
+            None if KINDS.iter().any(|kind| kind.eq_ignore_ascii_case(name)) => bail!(
+                "unsupported exporter section '{}' (is the Cargo feature enabled?)",
+                name
+            ),
+            // The rest of the top-level keys configure the default probe, among others
+            None => continue,
+        };
+        if ret
+            .insert(kind.clone(), Instance { kind, config })
+            .is_some()
+        {
+            bail!(
+                "exporter '{}' is configured more than once",
+                name.to_lowercase()
+            );
+        }
+    }
+    for (name, config) in named {
+        let config = match enabled(config) {
+            Some(config) => config,
+            None => continue,
+        };
+        let kind = match config.get("kind") {
+            None => name.as_str(),
+            Some(serde_json::Value::String(kind)) => kind.as_str(),
+            Some(kind) => bail!("invalid kind of exporter '{}': {}", name, kind),
+        };
+        let kind = match self::kind(kind) {
+            Some(kind) => kind.to_owned(),
+            None => bail!(
+                "unsupported kind of exporter '{}': '{}' (is the Cargo feature enabled?)",
+                name,
+                kind
+            ),
+        };
+        if name == supervisor::Supervisor::DATABASE {
+            bail!("an exporter may not be named '{}'", name);
+        }
+        if ret
+            .insert(name.clone(), Instance { kind, config })
+            .is_some()
+        {
+            bail!("exporter '{}' is configured more than once", name);
+        }
+    }
+    Ok(ret)
+}
+
+/// Whether a request that was responded to with the given status may succeed if retried.
+#[cfg(any(feature = "influxdb", feature = "pushgateway", feature = "webhook"))]
+fn is_retryable(status: reqwest::StatusCode) -> bool {
+    status.is_server_error()
+        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Returns the configuration of an exporter out of its section, which may also be a boolean that
+/// enables it (with its default configuration) or disables it.
+fn enabled(section: &serde_json::Value) -> Option<serde_json::Value> {
+    match section {
+        serde_json::Value::Bool(false) => None,
+        serde_json::Value::Bool(true) => Some(serde_json::Value::Object(Default::default())),
+        section => Some(section.clone()),
+    }
+}
+
+/// Feeds the given exporter with measurements, until signalled to terminate.
+#[tracing::instrument(skip(exporter, rx, quit))]
+pub(crate) async fn run(
+    name: String,
+    mut exporter: Box<dyn Exporter>,
+    mut rx: broadcast::Receiver<Measurement>,
+    mut quit: watch::Receiver<bool>,
+) {
+    loop {
+        let wake_at = exporter.wake_at();
+        let wake = async {
+            match wake_at {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => futures::future::pending().await,
+            }
+        };
+        debug!("Now blocking, waiting for either a quit signal or a new measurement...");
+        tokio::select! {
+            // Drain any pending measurements before quitting
+            biased;
+            result = rx.recv() => match result {
+                Ok(measurement) => exporter.on_measurement(measurement).await,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Skipped {} measurements, having fallen behind", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    warn!("The measurements channel has been closed");
+                    break;
+                }
+            },
+            _ = quit.changed() => {
+                info!("Received signal to gracefully shut down");
+                break;
+            },
+            _ = wake => exporter.on_wake().await,
+        }
+    }
+    exporter.on_shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Serves HTTP requests on a local port, responding to each one with the given status, and
+    /// returns the URL to send them to, along with how many of them have been responded to.
+    #[cfg(any(feature = "influxdb", feature = "pushgateway"))]
+    pub(super) async fn responder(
+        status: u16,
+    ) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        use std::sync::{atomic::Ordering, Arc};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let responded = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let count = responded.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                // Read the whole request (i.e., its headers and then its body) before responding
+                let mut request = vec![];
+                let mut buf = [0; 4096];
+                loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    let complete = text.find("\r\n\r\n").is_some_and(|end| {
+                        let length = text[..end]
+                            .lines()
+                            .filter_map(|line| line.split_once(':'))
+                            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                            .map_or(0, |(_, value)| value.trim().parse().unwrap());
+                        request.len() >= end + 4 + length
+                    });
+                    if n == 0 || complete {
+                        break;
+                    }
+                }
+                count.fetch_add(1, Ordering::SeqCst);
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, responded)
+    }
+
+    fn sections(value: serde_json::Value) -> BTreeMap<String, serde_json::Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn instances() {
+        let top = sections(json!({ "STDOUT": true, "period": "10m" }));
+        let named = sections(json!({
+            "console": { "kind": "StdOut" },
+            "disabled": false,
+        }));
+        let instances = super::instances(&top, &named).unwrap();
+        let kinds: Vec<_> = instances
+            .iter()
+            .map(|(name, i)| (name.as_str(), i.kind.as_str()))
+            .collect();
+        assert_eq!(kinds, [("console", "stdout"), ("stdout", "stdout")]);
+        assert_eq!(instances["stdout"].config, json!({}));
+    }
+
+    #[test]
+    fn invalid_instances() {
+        let top = sections(json!({ "stdout": true }));
+        let duplicate = sections(json!({ "stdout": {} }));
+        assert!(super::instances(&top, &duplicate).is_err());
+        let unknown = sections(json!({ "console": {} }));
+        assert!(super::instances(&BTreeMap::new(), &unknown).is_err());
+        let case = sections(json!({ "STDOUT": true, "StdOut": {} }));
+        assert!(super::instances(&case, &BTreeMap::new()).is_err());
+        #[cfg(not(feature = "twitter"))]
+        {
+            let disabled = sections(json!({ "Twitter": {} }));
+            assert!(super::instances(&disabled, &BTreeMap::new()).is_err());
+        }
+        let database = sections(json!({ "database": { "kind": "stdout" } }));
+        assert!(super::instances(&BTreeMap::new(), &database).is_err());
+    }
+}
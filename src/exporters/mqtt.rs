@@ -0,0 +1,484 @@
+use std::{collections::BTreeSet, fmt::Debug, path::PathBuf, time::Duration};
+
+use anyhow::{bail, Context as _, Result};
+use async_trait::async_trait;
+use rumqttc::{
+    AsyncClient, Event, EventLoop, Key, LastWill, MqttOptions, Outgoing, Packet, QoS,
+    TlsConfiguration, Transport,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, trace, warn};
+
+use super::{Context, Exporter};
+use crate::{measure::Measurement, probe, probe::DEFAULT_NAME};
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The host of the MQTT broker.
+    host: String,
+    /// The port of the MQTT broker; 1883 (or 8883, over TLS), if absent.
+    port: Option<u16>,
+    /// The client identifier; `"netspeedmon-<hostname>"`, if absent.
+    #[serde(alias = "ClientId")]
+    client_id: Option<String>,
+    username: Option<String>,
+    password: Option<Secret>,
+    /// Whether to connect over TLS; implied by any of the certificate files below.
+    #[serde(default)]
+    tls: bool,
+    /// The (PEM) certificate of the CA to verify the broker against, instead of the platform's.
+    #[serde(alias = "CaFile")]
+    ca_file: Option<PathBuf>,
+    /// The (PEM) certificate and key to authenticate to the broker with; only along with a
+    /// `ca_file`.
+    #[serde(alias = "ClientCert")]
+    client_cert: Option<PathBuf>,
+    #[serde(alias = "ClientKey")]
+    client_key: Option<PathBuf>,
+    /// The topic that all others are nested under; `"netspeedmon/<client_id>"`, if absent.
+    topic: Option<String>,
+    /// The QoS level (0, 1 or 2) of all messages; 0, if absent.
+    qos: Option<u8>,
+    /// Whether measurements are retained by the broker; availability and discovery messages
+    /// always are.
+    #[serde(default)]
+    retain: bool,
+    /// How often to ping the broker, when idle; 30 seconds, if absent.
+    #[serde(default, with = "humantime_serde", alias = "KeepAlive")]
+    keep_alive: Option<Duration>,
+    /// How long to wait before reconnecting to the broker; 5 seconds, if absent.
+    #[serde(default, with = "humantime_serde", alias = "ReconnectInterval")]
+    reconnect_interval: Option<Duration>,
+    /// Whether to publish Home Assistant MQTT discovery messages; true, if absent.
+    discovery: Option<bool>,
+    /// The topic prefix Home Assistant discovers devices under; `"homeassistant"`, if absent.
+    #[serde(alias = "DiscoveryPrefix")]
+    discovery_prefix: Option<String>,
+}
+
+/// A secret, which is kept out of logs.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+struct Secret(String);
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Publishes each measurement to an MQTT broker, both as a whole (in JSON) and field by field,
+/// along with Home Assistant discovery messages for each probe.
+pub(crate) struct Mqtt {
+    publisher: Publisher,
+    /// The event loop, until `init` spawns the task that drives it.
+    eventloop: Option<EventLoop>,
+    task: Option<JoinHandle<()>>,
+    reconnect_interval: Duration,
+    probes: probe::Registry,
+    /// The probes that discovery messages have been published for.
+    announced: BTreeSet<String>,
+}
+
+impl Debug for Mqtt {
+    // The event loop does not implement `Debug`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mqtt")
+            .field("publisher", &self.publisher)
+            .field("reconnect_interval", &self.reconnect_interval)
+            .field("announced", &self.announced)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Everything needed to publish messages, shared with the task that drives the event loop.
+#[derive(Debug, Clone)]
+struct Publisher {
+    client: AsyncClient,
+    /// The topic that all others are nested under.
+    topic: String,
+    qos: QoS,
+    retain: bool,
+    /// The Home Assistant discovery prefix, if discovery is enabled.
+    discovery_prefix: Option<String>,
+    /// The identifier of the Home Assistant device (i.e., of this host).
+    node_id: String,
+}
+
+impl Mqtt {
+    const DEFAULT_PORT: u16 = 1883;
+    const DEFAULT_TLS_PORT: u16 = 8883;
+    const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(30);
+    const DEFAULT_RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+    const DEFAULT_DISCOVERY_PREFIX: &'static str = "homeassistant";
+    /// How many messages may be queued while disconnected, before new ones are dropped.
+    const QUEUE_CAPACITY: usize = 100;
+
+    #[tracing::instrument(skip(ctx))]
+    pub(crate) fn new(config: Config, ctx: &Context) -> Result<Self> {
+        trace!("Creating new '{}'...", std::any::type_name::<Self>());
+        let client_id = match config.client_id {
+            Some(client_id) => client_id,
+            None => format!(
+                "netspeedmon-{}",
+                std::fs::read_to_string("/proc/sys/kernel/hostname")
+                    .with_context(|| "failed to retrieve the hostname, to use in 'client_id'")?
+                    .trim()
+            ),
+        };
+        if client_id.is_empty() || client_id.starts_with(' ') {
+            bail!("invalid 'client_id': '{}'", client_id);
+        }
+        let qos = config.qos.unwrap_or(0);
+        let qos = rumqttc::qos(qos).map_err(|_| anyhow::anyhow!("unsupported 'qos': {}", qos))?;
+        let keep_alive = config.keep_alive.unwrap_or(Self::DEFAULT_KEEP_ALIVE);
+        if keep_alive < Duration::from_secs(5) {
+            bail!("'keep_alive' must be at least 5 seconds");
+        }
+        let node_id = sanitize(&client_id);
+        let topic = config
+            .topic
+            .unwrap_or_else(|| format!("netspeedmon/{}", node_id));
+
+        let transport = tls(config.ca_file, config.client_cert, config.client_key)?;
+        let tls = config.tls || transport.is_some();
+        let port = config.port.unwrap_or(match tls {
+            true => Self::DEFAULT_TLS_PORT,
+            false => Self::DEFAULT_PORT,
+        });
+        let mut options = MqttOptions::new(client_id, config.host, port);
+        options
+            .set_keep_alive(keep_alive)
+            .set_last_will(LastWill::new(
+                availability_topic(&topic),
+                "offline",
+                qos,
+                true,
+            ));
+        if let Some(username) = config.username {
+            options.set_credentials(username, config.password.map_or(String::new(), |p| p.0));
+        }
+        match transport {
+            Some(transport) => options.set_transport(transport),
+            None if tls => options.set_transport(Transport::tls_with_default_config()),
+            None => &mut options,
+        };
+        let (client, eventloop) = AsyncClient::new(options, Self::QUEUE_CAPACITY);
+
+        let discovery_prefix = match config.discovery.unwrap_or(true) {
+            true => Some(
+                config
+                    .discovery_prefix
+                    .unwrap_or_else(|| Self::DEFAULT_DISCOVERY_PREFIX.to_owned()),
+            ),
+            false => None,
+        };
+        Ok(Self {
+            publisher: Publisher {
+                client,
+                topic,
+                qos,
+                retain: config.retain,
+                discovery_prefix,
+                node_id,
+            },
+            eventloop: Some(eventloop),
+            task: None,
+            reconnect_interval: config
+                .reconnect_interval
+                .unwrap_or(Self::DEFAULT_RECONNECT_INTERVAL),
+            probes: ctx.probes.clone(),
+            announced: BTreeSet::new(),
+        })
+    }
+
+    /// Drives the event loop (which is what actually talks to the broker), reconnecting whenever
+    /// the connection fails, until disconnected.
+    async fn drive(
+        mut eventloop: EventLoop,
+        publisher: Publisher,
+        probes: probe::Registry,
+        reconnect_interval: Duration,
+    ) {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to the MQTT broker");
+                    // The broker has published the last will if the connection was lost
+                    publisher.publish_retained(availability_topic(&publisher.topic), "online");
+                    let probes = match probes.read() {
+                        Ok(probes) => probes.keys().cloned().collect(),
+                        Err(_) => Vec::new(),
+                    };
+                    for probe in probes.iter() {
+                        publisher.announce(probe);
+                    }
+                }
+                Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                    debug!("Disconnected from the MQTT broker");
+                    break;
+                }
+                Ok(event) => trace!("{:?}", event),
+                Err(e) => {
+                    warn!(
+                        "MQTT connection failed (reconnecting in {}): {}",
+                        humantime::format_duration(reconnect_interval),
+                        e
+                    );
+                    tokio::time::sleep(reconnect_interval).await;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Exporter for Mqtt {
+    async fn init(&mut self) -> Result<()> {
+        if let Some(eventloop) = self.eventloop.take() {
+            self.task = Some(tokio::spawn(Self::drive(
+                eventloop,
+                self.publisher.clone(),
+                self.probes.clone(),
+                self.reconnect_interval,
+            )));
+        }
+        Ok(())
+    }
+
+    async fn on_measurement(&mut self, measurement: Measurement) {
+        let probe = measurement
+            .probe
+            .clone()
+            .unwrap_or_else(|| DEFAULT_NAME.to_owned());
+        // Probes that have been added since connecting have not been announced yet
+        if !self.announced.contains(&probe) {
+            self.publisher.announce(&probe);
+            self.announced.insert(probe.clone());
+        }
+        for (topic, payload) in self.publisher.messages(&probe, &measurement) {
+            self.publisher
+                .publish(topic, payload, self.publisher.qos, self.publisher.retain);
+        }
+    }
+
+    async fn on_shutdown(&mut self) {
+        // A clean disconnection does not trigger the last will
+        self.publisher
+            .publish_retained(availability_topic(&self.publisher.topic), "offline");
+        if let Err(e) = self.publisher.client.try_disconnect() {
+            warn!("Failed to disconnect from the MQTT broker: {}", e);
+        }
+        if let Some(mut task) = self.task.take() {
+            if tokio::time::timeout(Duration::from_secs(5), &mut task)
+                .await
+                .is_err()
+            {
+                warn!("Timed out waiting to disconnect from the MQTT broker");
+                task.abort();
+            }
+        }
+    }
+}
+
+impl Publisher {
+    /// Queues a message to be published, dropping it if too many are queued already (e.g., while
+    /// disconnected), so that the exporter never blocks.
+    fn publish(&self, topic: String, payload: String, qos: QoS, retain: bool) {
+        if let Err(e) = self.client.try_publish(&topic, qos, retain, payload) {
+            warn!("Dropping message to '{}': {}", topic, e);
+        }
+    }
+
+    fn publish_retained(&self, topic: String, payload: &str) {
+        self.publish(topic, payload.to_owned(), self.qos, true)
+    }
+
+    /// The messages to publish for the given measurement of the given probe: the whole of it (in
+    /// JSON), its status and, unless it failed or was skipped, each of its values.
+    fn messages(&self, probe: &str, measurement: &Measurement) -> Vec<(String, String)> {
+        let topic = format!("{}/{}", self.topic, probe);
+        let json = serde_json::to_string(measurement).unwrap_or_default();
+        let mut ret = vec![
+            (topic.clone(), json),
+            (format!("{}/status", topic), measurement.status().to_owned()),
+        ];
+        if measurement.skipped.is_some() || measurement.is_failed() {
+            return ret;
+        }
+        let mut fields = vec![
+            ("download_speed", measurement.download_speed),
+            ("upload_speed", measurement.upload_speed),
+            ("ping_latency", measurement.ping_latency),
+        ];
+        if let Some(jitter) = measurement.jitter {
+            fields.push(("jitter", jitter));
+        }
+        ret.extend(
+            fields
+                .into_iter()
+                .map(|(field, value)| (format!("{}/{}", topic, field), value.to_string())),
+        );
+        ret
+    }
+
+    /// Publishes the Home Assistant discovery messages for the sensors of the given probe, if
+    /// discovery is enabled.
+    fn announce(&self, probe: &str) {
+        if let Some(prefix) = &self.discovery_prefix {
+            for (topic, payload) in self.discovery(prefix, probe) {
+                self.publish(topic, payload, self.qos, true);
+            }
+        }
+    }
+
+    fn discovery(&self, prefix: &str, probe: &str) -> Vec<(String, String)> {
+        let sensors = [
+            ("download_speed", "Download", Some(("Mbit/s", "data_rate"))),
+            ("upload_speed", "Upload", Some(("Mbit/s", "data_rate"))),
+            ("ping_latency", "Latency", Some(("ms", "duration"))),
+            ("status", "Status", None),
+        ];
+        sensors
+            .iter()
+            .map(|(field, name, unit)| {
+                let object_id = format!("{}_{}", sanitize(probe), field);
+                let mut config = json!({
+                    "name": format!("{} {}", probe, name),
+                    "unique_id": format!("{}_{}", self.node_id, object_id),
+                    "state_topic": format!("{}/{}/{}", self.topic, probe, field),
+                    "availability_topic": availability_topic(&self.topic),
+                    "device": {
+                        "identifiers": [self.node_id],
+                        "name": self.node_id,
+                        "model": "netspeedmon",
+                        "sw_version": env!("CARGO_PKG_VERSION"),
+                    },
+                });
+                if let Some((unit, device_class)) = unit {
+                    config["unit_of_measurement"] = json!(unit);
+                    config["device_class"] = json!(device_class);
+                    config["state_class"] = json!("measurement");
+                }
+                let topic = format!("{}/sensor/{}/{}/config", prefix, self.node_id, object_id);
+                (topic, config.to_string())
+            })
+            .collect()
+    }
+}
+
+/// The topic that `"online"` or `"offline"` is retained on.
+fn availability_topic(topic: &str) -> String {
+    format!("{}/availability", topic)
+}
+
+/// Replaces all characters that Home Assistant does not allow in identifiers.
+fn sanitize(id: &str) -> String {
+    id.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Configures TLS with the given CA (and, optionally, client) certificates, if any.
+fn tls(
+    ca_file: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+) -> Result<Option<Transport>> {
+    let read = |path: &PathBuf| {
+        std::fs::read(path).with_context(|| format!("failed to read '{}'", path.display()))
+    };
+    let ca = match ca_file {
+        Some(ref ca_file) => read(ca_file)?,
+        None if client_cert.is_some() || client_key.is_some() => {
+            bail!("a 'client_cert' and 'client_key' require a 'ca_file'")
+        }
+        None => return Ok(None),
+    };
+    let client_auth = match (client_cert, client_key) {
+        (Some(cert), Some(key)) => {
+            let key = read(&key)?;
+            // PKCS#1 keys are RSA ones; all others are expected in PKCS#8
+            let key = match String::from_utf8_lossy(&key).contains("BEGIN RSA PRIVATE KEY") {
+                true => Key::RSA(key),
+                false => Key::ECC(key),
+            };
+            Some((read(&cert)?, key))
+        }
+        (None, None) => None,
+        _ => bail!("a 'client_cert' requires a 'client_key', and vice versa"),
+    };
+    Ok(Some(Transport::tls_with_config(TlsConfiguration::Simple {
+        ca,
+        alpn: None,
+        client_auth,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let (client, _) = AsyncClient::new(MqttOptions::new("test", "localhost", 1883), 1);
+        let publisher = Publisher {
+            client,
+            topic: "netspeedmon/lab".to_owned(),
+            qos: QoS::AtMostOnce,
+            retain: false,
+            discovery_prefix: Some("homeassistant".to_owned()),
+            node_id: "lab".to_owned(),
+        };
+        let topics = |messages: Vec<(String, String)>| -> Vec<_> {
+            messages.into_iter().map(|(topic, _)| topic).collect()
+        };
+
+        let measurement = Measurement {
+            jitter: Some(0.5),
+            ..(3.5, 94.25, 9.5).into()
+        };
+        let messages = publisher.messages("wan", &measurement);
+        assert!(messages.contains(&("netspeedmon/lab/wan/status".to_owned(), "ok".to_owned())));
+        assert!(messages.contains(&(
+            "netspeedmon/lab/wan/download_speed".to_owned(),
+            "94.25".to_owned()
+        )));
+        assert_eq!(
+            topics(messages),
+            [
+                "netspeedmon/lab/wan",
+                "netspeedmon/lab/wan/status",
+                "netspeedmon/lab/wan/download_speed",
+                "netspeedmon/lab/wan/upload_speed",
+                "netspeedmon/lab/wan/ping_latency",
+                "netspeedmon/lab/wan/jitter",
+            ]
+        );
+        // Failures only report their status
+        let messages = publisher.messages("wan", &Measurement::default());
+        assert_eq!(
+            topics(messages),
+            ["netspeedmon/lab/wan", "netspeedmon/lab/wan/status"]
+        );
+
+        let discovery = publisher.discovery("homeassistant", "home wan");
+        let (topic, config) = &discovery[0];
+        assert_eq!(
+            topic,
+            "homeassistant/sensor/lab/home_wan_download_speed/config"
+        );
+        let config: serde_json::Value = serde_json::from_str(config).unwrap();
+        assert_eq!(config["unique_id"], "lab_home_wan_download_speed");
+        assert_eq!(
+            config["state_topic"],
+            "netspeedmon/lab/home wan/download_speed"
+        );
+        assert_eq!(config["availability_topic"], "netspeedmon/lab/availability");
+    }
+}
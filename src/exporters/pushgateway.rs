@@ -0,0 +1,532 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug, Display},
+    time::Duration,
+};
+
+use anyhow::{bail, Context as _, Result};
+use async_trait::async_trait;
+use chrono::Local;
+use prost::Message;
+use reqwest::{header, Client, RequestBuilder, Response, Url};
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::{debug, info, trace, warn};
+
+use super::{
+    is_retryable,
+    metrics::{Family, Metrics},
+    Context, Exporter,
+};
+use crate::{measure::Measurement, probe::DEFAULT_NAME};
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The base URL of the Pushgateway (e.g., `http://pushgateway:9091`).
+    url: Option<String>,
+    /// The URL of an endpoint that accepts Prometheus remote-write requests (e.g.,
+    /// `http://prometheus:9090/api/v1/write`).
+    #[serde(alias = "RemoteWriteUrl")]
+    remote_write_url: Option<String>,
+    /// The `job` label of all metrics; `"netspeedmon"`, if absent.
+    job: Option<String>,
+    /// The `instance` label of all metrics; the hostname, if absent.
+    instance: Option<String>,
+    /// The credentials to authenticate with (through HTTP basic authentication), to both the
+    /// Pushgateway and the remote-write endpoint.
+    username: Option<String>,
+    password: Option<Password>,
+    /// How long to wait for each request to be responded to; 10 seconds, if absent.
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+    /// How many requests that failed to keep (for each of the Pushgateway and the remote-write
+    /// endpoint), to retry them (in order) before the next ones; 100, if absent.
+    #[serde(alias = "RetryBuffer")]
+    retry_buffer: Option<usize>,
+    /// How long to wait before retrying failed requests; 30 seconds, if absent.
+    #[serde(default, with = "humantime_serde", alias = "RetryInterval")]
+    retry_interval: Option<Duration>,
+}
+
+/// A secret, which is kept out of logs.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+struct Password(String);
+
+impl Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Password(<redacted>)")
+    }
+}
+
+/// A request that is yet to succeed.
+#[derive(Debug)]
+enum Pending {
+    /// The metrics of a probe, to replace its group on the Pushgateway with.
+    Push { probe: String, body: String },
+    /// A snappy-compressed remote-write request.
+    Write(Vec<u8>),
+}
+
+impl Display for Pending {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pending::Push { probe, .. } => write!(f, "metrics of probe '{}'", probe),
+            Pending::Write(body) => write!(f, "remote-write request of {} bytes", body.len()),
+        }
+    }
+}
+
+/// The requests to either the Pushgateway or the remote-write endpoint that are yet to succeed,
+/// in order; each target is retried on its own, so that one failing does not hold up the other.
+#[derive(Debug, Default)]
+struct Queue {
+    pending: VecDeque<Pending>,
+    /// When to retry sending, after the latest request failed.
+    retry_at: Option<Instant>,
+}
+
+/// Pushes metrics about each measurement (the same ones served by the HTTP exporter on
+/// `/metrics`) to a Prometheus Pushgateway, grouped by `job`, `instance` and `probe`, and/or
+/// writes them to a Prometheus remote-write endpoint.
+#[derive(Debug)]
+pub(crate) struct Pushgateway {
+    client: Client,
+    url: Option<Url>,
+    remote_write_url: Option<Url>,
+    job: String,
+    instance: String,
+    username: Option<String>,
+    password: Option<Password>,
+    metrics: Metrics,
+    pushes: Queue,
+    writes: Queue,
+    retry_buffer: usize,
+    retry_interval: Duration,
+}
+
+impl Pushgateway {
+    const DEFAULT_JOB: &'static str = "netspeedmon";
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+    const DEFAULT_RETRY_BUFFER: usize = 100;
+    const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+    #[tracing::instrument(skip(_ctx))]
+    pub(crate) fn new(config: Config, _ctx: &Context) -> Result<Self> {
+        trace!("Creating new '{}'...", std::any::type_name::<Self>());
+        let parse = |url: Option<String>| {
+            url.map(|url| Url::parse(&url).with_context(|| format!("invalid URL '{}'", url)))
+                .transpose()
+        };
+        let url = parse(config.url)?;
+        let remote_write_url = parse(config.remote_write_url)?;
+        if url.is_none() && remote_write_url.is_none() {
+            bail!("either 'url' or 'remote_write_url' must be configured");
+        }
+        if matches!(url, Some(ref url) if url.cannot_be_a_base()) {
+            bail!("the Pushgateway URL cannot be a base URL");
+        }
+        let instance = match config.instance {
+            Some(instance) => instance,
+            None => std::fs::read_to_string("/proc/sys/kernel/hostname")
+                .with_context(|| "failed to retrieve the hostname, to use as 'instance'")?
+                .trim()
+                .to_owned(),
+        };
+        let client = Client::builder()
+            .timeout(config.timeout.unwrap_or(Self::DEFAULT_TIMEOUT))
+            .build()
+            .with_context(|| "failed to build the HTTP client")?;
+        Ok(Self {
+            client,
+            url,
+            remote_write_url,
+            job: config.job.unwrap_or_else(|| Self::DEFAULT_JOB.to_owned()),
+            instance,
+            username: config.username,
+            password: config.password,
+            metrics: Metrics::default(),
+            pushes: Queue::default(),
+            writes: Queue::default(),
+            retry_buffer: config.retry_buffer.unwrap_or(Self::DEFAULT_RETRY_BUFFER),
+            retry_interval: config
+                .retry_interval
+                .unwrap_or(Self::DEFAULT_RETRY_INTERVAL),
+        })
+    }
+
+    /// The URL of the Pushgateway group of the given probe.
+    fn group_url(&self, base: &Url, probe: &str) -> Url {
+        let mut url = base.clone();
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .expect("the Pushgateway URL cannot be a base URL");
+            segments.pop_if_empty().extend(&["metrics"]);
+            let labels = [
+                ("job", &self.job),
+                ("instance", &self.instance),
+                ("probe", &probe.to_owned()),
+            ];
+            for (name, value) in labels.iter() {
+                // Values that cannot be path segments are base64-encoded, as the Pushgateway
+                // allows
+                if value.is_empty() || value.contains('/') {
+                    let value = base64::encode_config(value.as_bytes(), base64::URL_SAFE);
+                    segments.extend(&[format!("{}@base64", name), value]);
+                } else {
+                    segments.extend(&[*name, value.as_str()]);
+                }
+            }
+        }
+        url
+    }
+
+    fn authenticated(&self, request: RequestBuilder) -> RequestBuilder {
+        match self.username {
+            Some(ref username) => {
+                let password = self.password.as_ref().map(|password| &password.0);
+                request.basic_auth(username, password)
+            }
+            None => request,
+        }
+    }
+
+    async fn send(&self, pending: &Pending) -> reqwest::Result<Response> {
+        let request = match pending {
+            Pending::Push { probe, body } => {
+                let url = self.group_url(self.url.as_ref().expect("no Pushgateway URL"), probe);
+                trace!("Pushing to '{}'", url);
+                // PUT replaces all metrics of the group
+                self.client
+                    .put(url)
+                    .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                    .body(body.clone())
+            }
+            Pending::Write(body) => self
+                .client
+                .post(self.remote_write_url.clone().expect("no remote-write URL"))
+                .header(header::CONTENT_TYPE, "application/x-protobuf")
+                .header(header::CONTENT_ENCODING, "snappy")
+                .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+                .body(body.clone()),
+        };
+        self.authenticated(request).send().await
+    }
+
+    /// Sends the pending requests of the given queue in order, stopping at the first one that
+    /// fails in a way that is worth retrying (to be retried later), and then drops the oldest ones
+    /// that do not fit in the retry buffer.
+    async fn flush(&self, queue: &mut Queue) {
+        queue.retry_at = None;
+        while let Some(pending) = queue.pending.front() {
+            let error = match self.send(pending).await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Sent {}", pending);
+                    queue.pending.pop_front();
+                    continue;
+                }
+                Ok(response) if !is_retryable(response.status()) => {
+                    warn!(
+                        "Failed to send {} (not retrying): {}",
+                        pending,
+                        response.status()
+                    );
+                    queue.pending.pop_front();
+                    continue;
+                }
+                Ok(response) => response.status().to_string(),
+                Err(e) => e.to_string(),
+            };
+            warn!(
+                "Failed to send {} (retrying in {}): {}",
+                pending,
+                humantime::format_duration(self.retry_interval),
+                error
+            );
+            queue.retry_at = Some(Instant::now() + self.retry_interval);
+            break;
+        }
+        self.truncate(queue);
+    }
+
+    /// Drops the oldest requests of the given queue that do not fit in the retry buffer.
+    fn truncate(&self, queue: &mut Queue) {
+        let excess = queue.pending.len().saturating_sub(self.retry_buffer);
+        if excess > 0 {
+            warn!(
+                "Dropping {} requests that the retry buffer cannot fit",
+                excess
+            );
+            queue.pending.drain(..excess);
+        }
+    }
+
+    /// Sends the pending requests of each queue that satisfies the given condition, whereas the
+    /// rest are only kept within the retry buffer.
+    async fn flush_if<F: Fn(&Queue) -> bool>(&mut self, condition: F) {
+        let mut queues = [
+            std::mem::take(&mut self.pushes),
+            std::mem::take(&mut self.writes),
+        ];
+        for queue in queues.iter_mut() {
+            match condition(queue) {
+                true => self.flush(queue).await,
+                false => self.truncate(queue),
+            }
+        }
+        let [pushes, writes] = queues;
+        self.pushes = pushes;
+        self.writes = writes;
+    }
+
+    /// Encodes the given metrics as a snappy-compressed remote-write request.
+    fn write_request(&self, families: &[Family], timestamp: i64) -> Result<Vec<u8>> {
+        let timeseries = families
+            .iter()
+            .flat_map(|family| family.samples.iter())
+            .map(|sample| {
+                let mut labels: Vec<_> = sample
+                    .labels
+                    .iter()
+                    .map(|(name, value)| Label {
+                        name: (*name).to_owned(),
+                        value: value.clone(),
+                    })
+                    .collect();
+                labels.extend(
+                    [
+                        ("__name__", &sample.name),
+                        ("job", &self.job),
+                        ("instance", &self.instance),
+                    ]
+                    .iter()
+                    .map(|(name, value)| Label {
+                        name: (*name).to_owned(),
+                        value: (*value).clone(),
+                    }),
+                );
+                // Remote-write receivers expect labels sorted by name
+                labels.sort_by(|a, b| a.name.cmp(&b.name));
+                TimeSeries {
+                    labels,
+                    samples: vec![RemoteSample {
+                        value: sample.value,
+                        timestamp,
+                    }],
+                }
+            })
+            .collect();
+        let request = WriteRequest { timeseries }.encode_to_vec();
+        snap::raw::Encoder::new()
+            .compress_vec(&request)
+            .with_context(|| "failed to compress remote-write request")
+    }
+}
+
+#[async_trait]
+impl Exporter for Pushgateway {
+    async fn on_measurement(&mut self, measurement: Measurement) {
+        self.metrics.record(&measurement);
+        if measurement.skipped.is_some() {
+            return;
+        }
+        let probe = measurement.probe.as_deref().unwrap_or(DEFAULT_NAME);
+        if self.url.is_some() {
+            // Only the latest metrics of each probe are worth pushing
+            self.pushes
+                .pending
+                .retain(|pending| !matches!(pending, Pending::Push { probe: p, .. } if p == probe));
+            let body = self.metrics.render(Some(probe));
+            let probe = probe.to_owned();
+            self.pushes.pending.push_back(Pending::Push { probe, body });
+        }
+        if self.remote_write_url.is_some() {
+            let families = self.metrics.families(Some(probe));
+            match self.write_request(&families, Local::now().timestamp_millis()) {
+                Ok(request) => self.writes.pending.push_back(Pending::Write(request)),
+                Err(e) => warn!("Failed to encode remote-write request: {:#}", e),
+            }
+        }
+        // While failing, wait for the retry instead
+        self.flush_if(|queue| queue.retry_at.is_none()).await;
+    }
+
+    fn wake_at(&self) -> Option<Instant> {
+        self.pushes
+            .retry_at
+            .into_iter()
+            .chain(self.writes.retry_at)
+            .min()
+    }
+
+    async fn on_wake(&mut self) {
+        let now = Instant::now();
+        self.flush_if(|queue| matches!(queue.retry_at, Some(at) if at <= now))
+            .await
+    }
+
+    async fn on_shutdown(&mut self) {
+        let pending = self.pushes.pending.len() + self.writes.pending.len();
+        if pending > 0 {
+            info!("Retrying {} pending requests", pending);
+            self.flush_if(|queue| !queue.pending.is_empty()).await;
+        }
+        let pending = self.pushes.pending.len() + self.writes.pending.len();
+        if pending > 0 {
+            warn!("Giving up on {} pending requests", pending);
+        }
+    }
+}
+
+// Prometheus remote-write protocol messages (`prometheus.WriteRequest` and its dependencies).
+
+#[derive(Clone, PartialEq, Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<RemoteSample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct RemoteSample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    /// Milliseconds since the Unix epoch.
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::exporters::tests::responder;
+
+    fn pushgateway(url: &str, remote_write_url: &str) -> Pushgateway {
+        let config = Config {
+            url: Some(url.to_owned()),
+            remote_write_url: Some(remote_write_url.to_owned()),
+            job: None,
+            instance: Some("node/1".to_owned()),
+            username: None,
+            password: None,
+            timeout: None,
+            retry_buffer: None,
+            retry_interval: None,
+        };
+        let ctx = Context {
+            name: "pushgateway".to_owned(),
+            plot_path: None,
+            probes: Default::default(),
+            health: Default::default(),
+        };
+        Pushgateway::new(config, &ctx).unwrap()
+    }
+
+    #[test]
+    fn group_url() {
+        let pg = pushgateway(
+            "http://localhost:9091/",
+            "http://localhost:9090/api/v1/write",
+        );
+        let url = pg.group_url(pg.url.as_ref().unwrap(), "lab");
+        assert_eq!(
+            url.as_str(),
+            "http://localhost:9091/metrics/job/netspeedmon/instance@base64/bm9kZS8x/probe/lab"
+        );
+    }
+
+    #[test]
+    fn write_request() {
+        let pg = pushgateway(
+            "http://localhost:9091",
+            "http://localhost:9090/api/v1/write",
+        );
+        let mut metrics = Metrics::default();
+        metrics.record(&Measurement {
+            probe: Some("lab".to_owned()),
+            ..(3.5, 94.2, 9.4).into()
+        });
+        let compressed = pg.write_request(&metrics.families(None), 42).unwrap();
+        let request = snap::raw::Decoder::new()
+            .decompress_vec(&compressed)
+            .unwrap();
+        let request = WriteRequest::decode(request.as_slice()).unwrap();
+        let download = request
+            .timeseries
+            .iter()
+            .find(|ts| {
+                ts.labels[0].name == "__name__"
+                    && ts.labels[0].value == "netspeedmon_download_bits_per_second"
+            })
+            .unwrap();
+        let labels: Vec<_> = download
+            .labels
+            .iter()
+            .map(|l| (l.name.as_str(), l.value.as_str()))
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                ("__name__", "netspeedmon_download_bits_per_second"),
+                ("instance", "node/1"),
+                ("job", "netspeedmon"),
+                ("probe", "lab"),
+            ]
+        );
+        assert_eq!(download.samples[0].value, 94.2e6);
+        assert_eq!(download.samples[0].timestamp, 42);
+    }
+
+    #[tokio::test]
+    async fn retries() {
+        let (url, pushes) = responder(400).await;
+        let (remote_write_url, writes) = responder(503).await;
+        let mut pg = pushgateway(&url, &remote_write_url);
+        let measurement = Measurement {
+            probe: Some("lab".to_owned()),
+            ..(3.5, 94.2, 9.4).into()
+        };
+
+        // Pushes are rejected, hence dropped, whereas writes are to be retried
+        pg.on_measurement(measurement.clone()).await;
+        assert_eq!(pushes.load(Ordering::SeqCst), 1);
+        assert_eq!(writes.load(Ordering::SeqCst), 1);
+        assert!(pg.pushes.pending.is_empty());
+        assert_eq!(pg.writes.pending.len(), 1);
+        assert!(pg.wake_at().is_some());
+        assert_eq!(pg.wake_at(), pg.writes.retry_at);
+
+        // Writes wait for the retry, without holding pushes up
+        pg.on_measurement(measurement).await;
+        assert_eq!(pushes.load(Ordering::SeqCst), 2);
+        assert_eq!(writes.load(Ordering::SeqCst), 1);
+        assert_eq!(pg.writes.pending.len(), 2);
+        pg.on_wake().await;
+        assert_eq!(writes.load(Ordering::SeqCst), 1);
+
+        // ...until it is due, stopping at the first one that fails again
+        pg.writes.retry_at = Some(Instant::now());
+        pg.on_wake().await;
+        assert_eq!(writes.load(Ordering::SeqCst), 2);
+        assert_eq!(pg.writes.pending.len(), 2);
+        assert!(pg.wake_at().unwrap() > Instant::now());
+    }
+}
@@ -1,55 +1,36 @@
-use tokio::{
-    io::{self, AsyncWriteExt},
-    sync::{broadcast, watch},
-};
-use tracing::{debug, info, trace, warn};
+use async_trait::async_trait;
+use tokio::io::{self, AsyncWriteExt};
+use tracing::{trace, warn};
 
-use crate::measure::Measurement;
+use super::Exporter;
+use crate::{measure::Measurement, probe::DEFAULT_NAME};
 
-pub(crate) struct StdOut {
-    rx: broadcast::Receiver<Measurement>,
-    quit: watch::Receiver<bool>,
-}
+pub(crate) struct StdOut;
 
 impl StdOut {
-    #[tracing::instrument(skip(rx, quit))]
-    pub(crate) fn new(rx: broadcast::Receiver<Measurement>, quit: watch::Receiver<bool>) -> Self {
+    #[tracing::instrument]
+    pub(crate) fn new() -> Self {
         trace!("Creating new '{}'", std::any::type_name::<Self>());
-        Self { rx, quit }
-    }
-
-    #[tracing::instrument(skip(self))]
-    pub(crate) async fn run(mut self) {
-        loop {
-            let recv = self.rx.recv();
-            tokio::pin!(recv);
-
-            debug!("Now blocking, waiting for either a quit signal or a new measurement...");
-            tokio::select! {
-                _ = self.quit.changed() => {
-                    info!("Received signal to gracefully shut down");
-                    break;
-                },
-                result = &mut recv => {
-                    match result {
-                        Ok(measurements) => {
-                            Self::report(measurements).await;
-                        },
-                        Err(e) => {
-                            warn!("Failed to receive from the measurements channel: {}", e);
-                        },
-                    }
-                },
-            }
-        }
+        Self
     }
 
     #[tracing::instrument]
     async fn report(measurement: Measurement) {
-        let msg = format!(
-            "Ping latency: {}ms; Download speed: {:.3}Mbps; Upload speed: {:.3}Mbps\n",
-            measurement.ping_latency, measurement.download_speed, measurement.upload_speed
-        );
+        let mut msg = match measurement.skipped {
+            Some(reason) => format!("Skipped: {}", reason),
+            None => format!(
+                "Ping latency: {}ms; Download speed: {:.3}Mbps; Upload speed: {:.3}Mbps",
+                measurement.ping_latency, measurement.download_speed, measurement.upload_speed
+            ),
+        };
+        if let Some(reason) = measurement.suspect {
+            msg.push_str(&format!(" (suspect: {})", reason));
+        }
+        match measurement.probe {
+            Some(probe) if probe != DEFAULT_NAME => msg = format!("[{}] {}", probe, msg),
+            _ => (),
+        }
+        msg.push('\n');
 
         trace!("About to write to stdout and then flush it");
         let mut stdout = io::stdout();
@@ -61,3 +42,10 @@ impl StdOut {
         }
     }
 }
+
+#[async_trait]
+impl Exporter for StdOut {
+    async fn on_measurement(&mut self, measurement: Measurement) {
+        Self::report(measurement).await
+    }
+}
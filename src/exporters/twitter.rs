@@ -3,19 +3,20 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{Context as _, Result};
+use async_trait::async_trait;
 use egg_mode::{
     auth::{self, KeyPair, Token},
     media::{media_types, upload_media},
     tweet::DraftTweet,
 };
 use serde::Deserialize;
-use tokio::sync::{broadcast, watch};
 use tracing::{debug, info, trace, warn};
 
-use crate::measure::Measurement;
+use super::{Context, Exporter};
+use crate::{measure::Measurement, probe::DEFAULT_NAME};
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub(crate) struct Config {
     consumer_key: String,
     consumer_secret: String,
@@ -32,75 +33,22 @@ impl Debug for Config {
 pub(crate) struct Twitter {
     token: Token,
     plot_path: Option<PathBuf>,
-    rx: broadcast::Receiver<Measurement>,
-    quit: watch::Receiver<bool>,
+    /// The latest tweet, which the next one is posted as a reply to.
+    last_tweet_id: Option<u64>,
 }
 
 impl Twitter {
-    #[tracing::instrument(skip(rx, quit))]
-    pub(crate) async fn new<P: AsRef<Path> + Debug>(
-        config: &Config,
-        plot_path: Option<P>,
-        rx: broadcast::Receiver<Measurement>,
-        quit: watch::Receiver<bool>,
-    ) -> Result<Self> {
+    #[tracing::instrument(skip(ctx))]
+    pub(crate) fn new(config: Config, ctx: &Context) -> Self {
         trace!("Creating new '{}'...", std::any::type_name::<Self>());
-
         let token = Token::Access {
-            consumer: KeyPair::new(config.consumer_key.clone(), config.consumer_secret.clone()),
-            access: KeyPair::new(config.access_token.clone(), config.access_secret.clone()),
+            consumer: KeyPair::new(config.consumer_key, config.consumer_secret),
+            access: KeyPair::new(config.access_token, config.access_secret),
         };
-        let resp = auth::verify_tokens(&token)
-            .await
-            .with_context(|| "failed to verify the given Twitter tokens")?;
-        info!(
-            "Credentials verified for {} (@{})",
-            resp.response.name, resp.response.screen_name
-        );
-        debug!("{:?}", resp.response);
-        debug!(
-            "Current rate limiting information: {:?}",
-            resp.rate_limit_status
-        );
-
-        Ok(Self {
+        Self {
             token,
-            plot_path: plot_path.map(|p| p.as_ref().to_owned()),
-            rx,
-            quit,
-        })
-    }
-
-    #[tracing::instrument(skip(self))]
-    pub(crate) async fn run(mut self) {
-        let mut last_tweet_id: Option<u64> = None;
-        loop {
-            let recv = self.rx.recv();
-            tokio::pin!(recv);
-
-            debug!("Now blocking, waiting for either a quit signal or a new measurement...");
-            tokio::select! {
-                _ = self.quit.changed() => {
-                    info!("Received signal to gracefully shut down");
-                    break;
-                },
-                result = &mut recv => {
-                    match result {
-                        Ok(measurements) => {
-                            last_tweet_id = Self::tweet(
-                                measurements,
-                                &self.token,
-                                last_tweet_id,
-                                self.plot_path.as_ref(),
-                            )
-                            .await
-                        },
-                        Err(e) => {
-                            warn!("Failed to receive from the measurements channel: {}", e);
-                        },
-                    };
-                },
-            }
+            plot_path: ctx.plot_path.clone(),
+            last_tweet_id: None,
         }
     }
 
@@ -112,10 +60,17 @@ impl Twitter {
         _plot_path: Option<P>,
     ) -> Option<u64> {
         // Crate a new draft tweet
-        let tweet_text = format!(
+        let mut tweet_text = match measurement.probe {
+            Some(ref probe) if probe != DEFAULT_NAME => format!("Probe: {}\n", probe),
+            _ => String::new(),
+        };
+        tweet_text.push_str(&format!(
             "Latest Measurement:\n⛖ Ping Latency: {:.3}ms\n⬇ Download Bandwidth: {:.3} Mbps\n⬆ Upload Bandwidth: {:.3} Mbps\n",
             measurement.ping_latency, measurement.download_speed, measurement.upload_speed
-        );
+        ));
+        if let Some(ref reason) = measurement.suspect {
+            tweet_text.push_str(&format!("⚠ Suspect: {}\n", reason));
+        }
         let mut draft = DraftTweet::new(tweet_text);
         if let Some(last_tweet_id) = last_tweet_id {
             draft = draft.in_reply_to(last_tweet_id);
@@ -169,3 +124,37 @@ impl Twitter {
         Ok(())
     }
 }
+
+#[async_trait]
+impl Exporter for Twitter {
+    #[tracing::instrument(skip(self))]
+    async fn init(&mut self) -> Result<()> {
+        let resp = auth::verify_tokens(&self.token)
+            .await
+            .with_context(|| "failed to verify the given Twitter tokens")?;
+        info!(
+            "Credentials verified for {} (@{})",
+            resp.response.name, resp.response.screen_name
+        );
+        debug!("{:?}", resp.response);
+        debug!(
+            "Current rate limiting information: {:?}",
+            resp.rate_limit_status
+        );
+        Ok(())
+    }
+
+    async fn on_measurement(&mut self, measurement: Measurement) {
+        // Skipped rounds did not measure anything
+        if measurement.skipped.is_some() {
+            return;
+        }
+        self.last_tweet_id = Self::tweet(
+            measurement,
+            &self.token,
+            self.last_tweet_id,
+            self.plot_path.as_ref(),
+        )
+        .await
+    }
+}
@@ -0,0 +1,548 @@
+use std::{collections::BTreeMap, fmt::Debug, time::Duration};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use hmac::{Hmac, Mac};
+use reqwest::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    Client, Method, Url,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::time::Instant;
+use tracing::{debug, info, trace, warn};
+
+use super::{is_retryable, Context, Exporter};
+use crate::{measure::Measurement, probe::DEFAULT_NAME};
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The URL to send each measurement to.
+    url: Option<String>,
+    /// More URLs to send each measurement to, apart from `url`.
+    #[serde(default)]
+    urls: Vec<String>,
+    /// The HTTP method to send measurements with (`"POST"` or `"PUT"`); `"POST"`, if absent.
+    method: Option<String>,
+    /// Headers to add to each request (e.g., `Authorization`); their values are kept out of logs,
+    /// since they often carry credentials.
+    #[serde(default)]
+    headers: BTreeMap<String, Secret>,
+    /// How to encode the body (`"json"` or `"form"`); `"json"`, if absent.
+    format: Option<Format>,
+    /// The body to render for each measurement, where each `{variable}` within strings is replaced
+    /// by the value of that variable (or, if it makes up the whole string, by the value itself,
+    /// keeping its JSON type); all variables, if absent. Forms must be tables of strings.
+    body: Option<Value>,
+    /// The secret to sign each body with (HMAC-SHA256), if any.
+    secret: Option<Secret>,
+    /// The header to send the signature in, as `sha256=<hex>`; `"X-Signature-256"`, if absent.
+    #[serde(alias = "SignatureHeader")]
+    signature_header: Option<String>,
+    /// How long to wait for each request to be responded to; 10 seconds, if absent.
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+    /// How many times to retry each request that fails; 3, if absent.
+    retries: Option<u32>,
+    /// How long to wait before the first retry, doubling before each next one; 1 second, if absent.
+    #[serde(default, with = "humantime_serde")]
+    backoff: Option<Duration>,
+    /// The longest to wait before any retry; 5 minutes, if absent.
+    #[serde(default, with = "humantime_serde", alias = "MaxBackoff")]
+    max_backoff: Option<Duration>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Json,
+    Form,
+}
+
+/// A secret, which is kept out of logs.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+struct Secret(String);
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Sends each measurement, rendered out of a template, to the configured URLs, retrying those
+/// requests that fail with an exponential backoff.
+pub(crate) struct Webhook {
+    client: Client,
+    urls: Vec<Url>,
+    method: Method,
+    headers: HeaderMap,
+    format: Format,
+    body: Option<Value>,
+    secret: Option<Secret>,
+    signature_header: HeaderName,
+    retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    /// The requests that failed and are yet to be retried.
+    pending: Vec<Delivery>,
+}
+
+impl Debug for Webhook {
+    // Header values may carry credentials
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Webhook")
+            .field("urls", &self.urls)
+            .field("method", &self.method)
+            .field("format", &self.format)
+            .field("retries", &self.retries)
+            .field("pending", &self.pending.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A request to (re)send.
+#[derive(Debug)]
+struct Delivery {
+    url: Url,
+    body: Vec<u8>,
+    /// How many times the request has failed so far.
+    failures: u32,
+    retry_at: Instant,
+}
+
+impl Webhook {
+    const DEFAULT_SIGNATURE_HEADER: &'static str = "X-Signature-256";
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+    const DEFAULT_RETRIES: u32 = 3;
+    const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+    const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+    #[tracing::instrument(skip(_ctx))]
+    pub(crate) fn new(config: Config, _ctx: &Context) -> Result<Self> {
+        trace!("Creating new '{}'...", std::any::type_name::<Self>());
+        let urls = config
+            .url
+            .iter()
+            .chain(config.urls.iter())
+            .map(|url| Url::parse(url).with_context(|| format!("invalid URL '{}'", url)))
+            .collect::<Result<Vec<_>>>()?;
+        if urls.is_empty() {
+            bail!("at least one 'url' must be configured");
+        }
+        let method = match config.method.as_deref().map(str::to_uppercase).as_deref() {
+            None | Some("POST") => Method::POST,
+            Some("PUT") => Method::PUT,
+            Some(method) => bail!("unsupported 'method': '{}'", method),
+        };
+        let mut headers = HeaderMap::new();
+        for (name, value) in config.headers {
+            let value = HeaderValue::from_str(&value.0)
+                .with_context(|| format!("invalid value of header '{}'", name))?;
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("invalid header name '{}'", name))?;
+            headers.insert(name, value);
+        }
+        let signature_header = config
+            .signature_header
+            .as_deref()
+            .unwrap_or(Self::DEFAULT_SIGNATURE_HEADER);
+        let signature_header = HeaderName::from_bytes(signature_header.as_bytes())
+            .with_context(|| format!("invalid 'signature_header': '{}'", signature_header))?;
+        let client = Client::builder()
+            .timeout(config.timeout.unwrap_or(Self::DEFAULT_TIMEOUT))
+            .build()
+            .with_context(|| "failed to build the HTTP client")?;
+        let ret = Self {
+            client,
+            urls,
+            method,
+            headers,
+            format: config.format.unwrap_or(Format::Json),
+            body: config.body,
+            secret: config.secret,
+            signature_header,
+            retries: config.retries.unwrap_or(Self::DEFAULT_RETRIES),
+            backoff: config.backoff.unwrap_or(Self::DEFAULT_BACKOFF),
+            max_backoff: config.max_backoff.unwrap_or(Self::DEFAULT_MAX_BACKOFF),
+            pending: Vec::new(),
+        };
+        // Catch unknown variables and malformed forms before any measurement arrives
+        ret.render(&Measurement::default(), Local::now())
+            .with_context(|| "invalid 'body'")?;
+        Ok(ret)
+    }
+
+    /// Renders the body of the request for the given measurement, received at the given time.
+    fn render(&self, measurement: &Measurement, now: DateTime<Local>) -> Result<Vec<u8>> {
+        let variables = variables(measurement, now);
+        let body = match self.body {
+            Some(ref template) => render(template, &variables)?,
+            None => Value::Object(
+                variables
+                    .into_iter()
+                    .filter(|(_, value)| !value.is_null())
+                    .map(|(name, value)| (name.to_owned(), value))
+                    .collect(),
+            ),
+        };
+        match self.format {
+            Format::Json => Ok(serde_json::to_vec(&body)?),
+            Format::Form => {
+                let fields = match body {
+                    Value::Object(fields) => fields,
+                    _ => bail!("the body of a form must be a table"),
+                };
+                let mut form = form_urlencoded::Serializer::new(String::new());
+                for (name, value) in fields.iter() {
+                    form.append_pair(name, &text(value)?);
+                }
+                Ok(form.finish().into_bytes())
+            }
+        }
+    }
+
+    /// The signature of the given body, if a secret is configured.
+    fn sign(&self, body: &[u8]) -> Option<String> {
+        let secret = self.secret.as_ref()?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.0.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(body);
+        let digest = mac.finalize().into_bytes();
+        Some(digest.iter().fold(String::from("sha256="), |mut ret, b| {
+            ret.push_str(&format!("{:02x}", b));
+            ret
+        }))
+    }
+
+    /// Sends the given request, returning it along with when to retry it if it failed in a way
+    /// that is worth retrying, and there are retries left.
+    async fn deliver(&self, mut delivery: Delivery) -> Option<Delivery> {
+        let content_type = match self.format {
+            Format::Json => "application/json",
+            Format::Form => "application/x-www-form-urlencoded",
+        };
+        let mut request = self
+            .client
+            .request(self.method.clone(), delivery.url.clone())
+            .header(header::CONTENT_TYPE, content_type)
+            .headers(self.headers.clone());
+        if let Some(signature) = self.sign(&delivery.body) {
+            request = request.header(self.signature_header.clone(), signature);
+        }
+        let error = match request.body(delivery.body.clone()).send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!("Delivered measurement to '{}'", delivery.url);
+                return None;
+            }
+            Ok(response) if !is_retryable(response.status()) => {
+                warn!(
+                    "Failed to deliver measurement to '{}' (not retrying): {}",
+                    delivery.url,
+                    response.status()
+                );
+                return None;
+            }
+            Ok(response) => response.status().to_string(),
+            Err(e) => e.to_string(),
+        };
+        delivery.failures += 1;
+        if delivery.failures > self.retries {
+            warn!(
+                "Giving up on delivering measurement to '{}', after {} attempts: {}",
+                delivery.url, delivery.failures, error
+            );
+            return None;
+        }
+        let backoff = self
+            .backoff
+            .checked_mul(1 << (delivery.failures - 1).min(16))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff));
+        warn!(
+            "Failed to deliver measurement to '{}' (retrying in {}): {}",
+            delivery.url,
+            humantime::format_duration(backoff),
+            error
+        );
+        delivery.retry_at = Instant::now() + backoff;
+        Some(delivery)
+    }
+
+    /// Sends all given requests, keeping those that are to be retried.
+    async fn deliver_all(&mut self, deliveries: Vec<Delivery>) {
+        for delivery in deliveries {
+            if let Some(delivery) = self.deliver(delivery).await {
+                self.pending.push(delivery);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Exporter for Webhook {
+    async fn on_measurement(&mut self, measurement: Measurement) {
+        let body = match self.render(&measurement, Local::now()) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to render the body of the request: {:#}", e);
+                return;
+            }
+        };
+        trace!("New body: {}", String::from_utf8_lossy(&body));
+        let deliveries = self
+            .urls
+            .iter()
+            .map(|url| Delivery {
+                url: url.clone(),
+                body: body.clone(),
+                failures: 0,
+                retry_at: Instant::now(),
+            })
+            .collect();
+        self.deliver_all(deliveries).await;
+    }
+
+    fn wake_at(&self) -> Option<Instant> {
+        self.pending.iter().map(|delivery| delivery.retry_at).min()
+    }
+
+    async fn on_wake(&mut self) {
+        let now = Instant::now();
+        let (due, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|delivery| delivery.retry_at <= now);
+        self.pending = pending;
+        self.deliver_all(due).await;
+    }
+
+    async fn on_shutdown(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        // Retry each pending request once more, without waiting for its backoff
+        info!("Retrying {} pending requests", self.pending.len());
+        for delivery in std::mem::take(&mut self.pending) {
+            if let Some(delivery) = self.deliver(delivery).await {
+                warn!("Giving up on delivering measurement to '{}'", delivery.url);
+            }
+        }
+    }
+}
+
+/// The variables that templates may refer to, for the given measurement received at the given
+/// time; those that are unknown are null.
+fn variables(measurement: &Measurement, now: DateTime<Local>) -> BTreeMap<&'static str, Value> {
+    let string = |value: &Option<String>| value.clone().map_or(Value::Null, Value::String);
+    let stats = measurement.latency_stats;
+    let stat = |f: fn(&crate::measure::LatencyStats) -> f64| {
+        stats.as_ref().map_or(Value::Null, |stats| f(stats).into())
+    };
+    vec![
+        (
+            "probe",
+            measurement.probe.as_deref().unwrap_or(DEFAULT_NAME).into(),
+        ),
+        ("status", measurement.status().into()),
+        ("failed", measurement.is_failed().into()),
+        ("ping_latency", measurement.ping_latency.into()),
+        ("download_speed", measurement.download_speed.into()),
+        ("upload_speed", measurement.upload_speed.into()),
+        ("jitter", measurement.jitter.map_or(Value::Null, Into::into)),
+        ("latency_min", stat(|stats| stats.min)),
+        ("latency_p50", stat(|stats| stats.p50)),
+        ("latency_p90", stat(|stats| stats.p90)),
+        ("latency_p99", stat(|stats| stats.p99)),
+        ("latency_max", stat(|stats| stats.max)),
+        ("latency_stddev", stat(|stats| stats.stddev)),
+        ("measurer", string(&measurement.measurer)),
+        (
+            "duration",
+            measurement
+                .duration
+                .map_or(Value::Null, |duration| duration.as_secs_f64().into()),
+        ),
+        ("interface", string(&measurement.interface)),
+        ("server", string(&measurement.server)),
+        (
+            "client_ip",
+            measurement
+                .client_ip
+                .map_or(Value::Null, |ip| ip.to_string().into()),
+        ),
+        ("suspect", string(&measurement.suspect)),
+        ("downgraded", string(&measurement.downgraded)),
+        ("deferred", string(&measurement.deferred)),
+        ("skipped", string(&measurement.skipped)),
+        ("timestamp", now.to_rfc3339().into()),
+        ("timestamp_unix", now.timestamp().into()),
+        ("timestamp_ms", now.timestamp_millis().into()),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// Renders the given template, replacing the variables within all of its strings.
+fn render(template: &Value, variables: &BTreeMap<&str, Value>) -> Result<Value> {
+    Ok(match template {
+        Value::String(template) => render_str(template, variables)?,
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| render(value, variables))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), render(value, variables)?)))
+                .collect::<Result<_>>()?,
+        ),
+        value => value.clone(),
+    })
+}
+
+/// Renders the given string, replacing each `{variable}` by its value (and `{{`, `}}` by `{`,
+/// `}`); a string that is made up of a single variable is replaced by its value as is.
+fn render_str(template: &str, variables: &BTreeMap<&str, Value>) -> Result<Value> {
+    let lookup = |name: &str| {
+        variables
+            .get(name.trim())
+            .ok_or_else(|| anyhow!("unknown variable '{}'", name.trim()))
+    };
+    if let Some(name) = template
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_suffix('}'))
+    {
+        if !name.contains(['{', '}']) {
+            return Ok(lookup(name)?.clone());
+        }
+    }
+    let mut ret = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        ret.push_str(&rest[..i]);
+        let (c, after) = (&rest[i..i + 1], &rest[i + 1..]);
+        if after.starts_with(c) {
+            ret.push_str(c);
+            rest = &after[1..];
+        } else if c == "{" {
+            let end = after
+                .find('}')
+                .ok_or_else(|| anyhow!("unclosed '{{' in '{}'", template))?;
+            ret.push_str(&text(lookup(&after[..end])?)?);
+            rest = &after[end + 1..];
+        } else {
+            bail!("unmatched '}}' in '{}' (use '}}}}')", template);
+        }
+    }
+    ret.push_str(rest);
+    Ok(Value::String(ret))
+}
+
+/// Formats the given (scalar) value as text; nulls are empty.
+fn text(value: &Value) -> Result<String> {
+    match value {
+        Value::Null => Ok(String::new()),
+        Value::String(value) => Ok(value.clone()),
+        Value::Bool(_) | Value::Number(_) => Ok(value.to_string()),
+        _ => bail!("only strings, numbers and booleans can be rendered as text"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+
+    fn webhook(config: serde_json::Value) -> Result<Webhook> {
+        let ctx = Context {
+            name: "webhook".to_owned(),
+            plot_path: None,
+            probes: Default::default(),
+            health: Default::default(),
+        };
+        Webhook::new(serde_json::from_value(config)?, &ctx)
+    }
+
+    #[test]
+    fn render() {
+        let now = Local.timestamp(1_600_000_000, 0);
+        let measurement = Measurement {
+            probe: Some("wan".to_owned()),
+            jitter: Some(0.5),
+            ..(3.5, 94.25, 9.5).into()
+        };
+
+        let json = webhook(json!({
+            "url": "http://localhost/hook",
+            "body": {
+                "text": "{probe}: {download_speed}/{upload_speed} Mbit/s {{{status}}}",
+                "down": "{download_speed}",
+                "server": "{server}",
+                "at": ["{timestamp_unix}"],
+            },
+        }))
+        .unwrap();
+        let body: Value = serde_json::from_slice(&json.render(&measurement, now).unwrap()).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "text": "wan: 94.25/9.5 Mbit/s {ok}",
+                "down": 94.25,
+                "server": null,
+                "at": [1_600_000_000],
+            })
+        );
+
+        let form = webhook(json!({
+            "url": "http://localhost/hook",
+            "format": "form",
+            "body": { "probe": "{probe}", "jitter": "{jitter} ms", "failed": "{failed}" },
+        }))
+        .unwrap();
+        assert_eq!(
+            form.render(&measurement, now).unwrap(),
+            b"failed=false&jitter=0.5+ms&probe=wan"
+        );
+
+        // All known variables, by default
+        let default = webhook(json!({ "url": "http://localhost/hook" })).unwrap();
+        let body: Value =
+            serde_json::from_slice(&default.render(&measurement, now).unwrap()).unwrap();
+        assert_eq!(body["jitter"], 0.5);
+        assert_eq!(body["timestamp_ms"], 1_600_000_000_000i64);
+        assert!(body.get("server").is_none());
+    }
+
+    #[test]
+    fn invalid() {
+        let unknown = json!({ "url": "http://localhost/hook", "body": { "a": "{nope}" } });
+        assert!(webhook(unknown).is_err());
+        let unclosed = json!({ "url": "http://localhost/hook", "body": "{probe" });
+        assert!(webhook(unclosed).is_err());
+        let nested_form = json!({
+            "url": "http://localhost/hook",
+            "format": "form",
+            "body": { "a": ["{probe}"] },
+        });
+        assert!(webhook(nested_form).is_err());
+        assert!(webhook(json!({ "method": "PUT" })).is_err());
+        let get = json!({ "url": "http://localhost/hook", "method": "GET" });
+        assert!(webhook(get).is_err());
+    }
+
+    #[test]
+    fn sign() {
+        let webhook = webhook(json!({ "url": "http://localhost/hook", "secret": "key" })).unwrap();
+        assert_eq!(
+            webhook
+                .sign(b"The quick brown fox jumps over the lazy dog")
+                .unwrap(),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}
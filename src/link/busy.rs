@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::{debug, trace, warn};
+
+use super::{default_route, interface_counters, Counters};
+
+/// Configuration for the busy link `Detector`; scheduled rounds are deferred while the traffic
+/// through the network interface is above the configured threshold.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The network interface to sample; the one the IPv4 default route goes through, if absent.
+    interface: Option<String>,
+    /// The rate (in Mbps), in either direction, above which the link is considered busy.
+    threshold: f64,
+    /// How long to sample the interface's counters for, to estimate its current rate.
+    #[serde(default, with = "humantime_serde", alias = "SampleWindow")]
+    sample_window: Option<Duration>,
+    /// How long to wait before checking again, while the link is busy.
+    #[serde(default, with = "humantime_serde", alias = "Retry")]
+    retry: Option<Duration>,
+    /// How long a round may be deferred for at most, before being skipped.
+    #[serde(default, with = "humantime_serde", alias = "MaxDeferral")]
+    max_deferral: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Detector {
+    config: Config,
+}
+
+impl Detector {
+    const DEFAULT_SAMPLE_WINDOW: Duration = Duration::from_secs(2);
+    const DEFAULT_RETRY: Duration = Duration::from_secs(60);
+    const DEFAULT_MAX_DEFERRAL: Duration = Duration::from_secs(10 * 60);
+
+    #[tracing::instrument]
+    pub(crate) fn new(config: Config) -> Result<Self> {
+        trace!("Creating new '{}'", std::any::type_name::<Self>());
+        if !(config.threshold > 0. && config.threshold.is_finite()) {
+            bail!("the busy link threshold must be positive");
+        }
+        if config.sample_window == Some(Duration::ZERO) || config.retry == Some(Duration::ZERO) {
+            bail!("the busy link sample_window and retry must be non-zero");
+        }
+        Ok(Self { config })
+    }
+
+    /// How long to wait before checking again, while the link is busy.
+    pub(crate) fn retry(&self) -> Duration {
+        self.config.retry.unwrap_or(Self::DEFAULT_RETRY)
+    }
+
+    /// How long a round may be deferred for at most, before being skipped.
+    pub(crate) fn max_deferral(&self) -> Duration {
+        self.config
+            .max_deferral
+            .unwrap_or(Self::DEFAULT_MAX_DEFERRAL)
+    }
+
+    /// Samples the interface's counters and returns the reason why the link is considered busy,
+    /// if it is; failing to sample them, the link is assumed to be idle.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn check(&self) -> Option<String> {
+        let interface = match self.config.interface {
+            Some(ref interface) => interface.clone(),
+            None => match default_route().await {
+                Ok(Some(route)) => route.interface,
+                Ok(None) => {
+                    debug!("No default route found");
+                    return None;
+                }
+                Err(e) => {
+                    warn!("Failed to look up the default route: {:#}", e);
+                    return None;
+                }
+            },
+        };
+
+        let window = self
+            .config
+            .sample_window
+            .unwrap_or(Self::DEFAULT_SAMPLE_WINDOW);
+        let (before, start) = (Self::sample(&interface).await?, Instant::now());
+        tokio::time::sleep(window).await;
+        let (after, end) = (Self::sample(&interface).await?, Instant::now());
+
+        let (rx, tx) = rates(before, after, end - start);
+        trace!(
+            "'{}' carries {:.3} Mbps in, {:.3} Mbps out",
+            interface,
+            rx,
+            tx
+        );
+        if rx > self.config.threshold || tx > self.config.threshold {
+            Some(format!(
+                "'{}' carries {:.3} Mbps in, {:.3} Mbps out (threshold: {} Mbps)",
+                interface, rx, tx, self.config.threshold
+            ))
+        } else {
+            None
+        }
+    }
+
+    async fn sample(interface: &str) -> Option<Counters> {
+        match interface_counters(interface).await {
+            Ok(Some(counters)) => Some(counters),
+            Ok(None) => {
+                warn!("No counters found for interface '{}'", interface);
+                None
+            }
+            Err(e) => {
+                warn!("Failed to sample the interface counters: {:#}", e);
+                None
+            }
+        }
+    }
+}
+
+/// The received and transmitted rates (in Mbps) between two samples of the counters, `elapsed`
+/// apart.
+fn rates(before: Counters, after: Counters, elapsed: Duration) -> (f64, f64) {
+    let mbps = |bytes: u64| bytes as f64 * 8. / elapsed.as_secs_f64() / 1_000_000.;
+    (
+        mbps(after.rx_bytes.saturating_sub(before.rx_bytes)),
+        mbps(after.tx_bytes.saturating_sub(before.tx_bytes)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates() {
+        let before = Counters {
+            rx_bytes: 1_000_000,
+            tx_bytes: 5_000,
+        };
+        let after = Counters {
+            rx_bytes: 6_000_000,
+            tx_bytes: 255_000,
+        };
+        let (rx, tx) = super::rates(before, after, Duration::from_secs(2));
+        assert!((rx - 20.).abs() < 1e-9);
+        assert!((tx - 1.).abs() < 1e-9);
+        // Counters may be reset (e.g., when the interface is recreated)
+        assert_eq!(
+            super::rates(after, before, Duration::from_secs(2)),
+            (0., 0.)
+        );
+    }
+}
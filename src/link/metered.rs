@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use tokio::process::Command;
+use tracing::{debug, trace, warn};
+
+use super::{default_route, neighbour_hw_addr};
+use crate::measure::Measurer;
+
+/// Configuration for the metered link `Detector`; the link is considered metered if any of the
+/// configured checks says so.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// A command (run through `sh -c`) whose successful exit status indicates a metered link.
+    check_command: Option<String>,
+    /// How long to wait for `check_command` to complete, before assuming an unmetered link.
+    #[serde(default, with = "humantime_serde")]
+    check_timeout: Option<Duration>,
+    /// The link is metered while the IPv4 default route goes through this network interface.
+    interface: Option<String>,
+    /// The link is metered while the IPv4 default gateway has this hardware (MAC) address.
+    gateway_mac: Option<String>,
+    /// What to do on a metered link.
+    ///
+    /// Currently supported actions:
+    /// - Skip measuring altogether: `"skip"` (default);
+    /// - Measure using the `Measurer` configured through `measurer`: `"downgrade"`.
+    action: Option<String>,
+    /// The kind of (lightweight) `Measurer` to fall back to when the action is `"downgrade"`.
+    measurer: Option<String>,
+}
+
+impl Config {
+    pub(crate) fn measurer(&self) -> Option<&str> {
+        self.measurer.as_deref()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Detector {
+    config: Config,
+    fallback: Option<Box<dyn Measurer>>,
+}
+
+impl Detector {
+    const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Creates a new `Detector`; `fallback` is the `Measurer` created out of the `measurer` in
+    /// its `config`, if any.
+    #[tracing::instrument]
+    pub(crate) fn new(config: Config, fallback: Option<Box<dyn Measurer>>) -> Result<Self> {
+        trace!("Creating new '{}'", std::any::type_name::<Self>());
+        let fallback = match config.action.as_deref().map(str::to_lowercase).as_deref() {
+            None | Some("skip") | Some("default") => None,
+            Some("downgrade") => match fallback {
+                Some(fallback) => Some(fallback),
+                None => bail!("metered action 'downgrade' requires a fallback 'measurer'"),
+            },
+            Some(unknown) => bail!("unsupported metered action: '{}'", unknown),
+        };
+        Ok(Self { config, fallback })
+    }
+
+    /// The `Measurer` to use on a metered link, if measuring should not be skipped altogether.
+    pub(crate) fn fallback_measurer(&mut self) -> Option<&mut Box<dyn Measurer>> {
+        self.fallback.as_mut()
+    }
+
+    /// Returns the reason why the link is considered metered, if it is.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn check(&self) -> Option<String> {
+        if self.config.interface.is_some() || self.config.gateway_mac.is_some() {
+            match default_route().await {
+                Err(e) => warn!("Failed to look up the default route: {:#}", e),
+                Ok(None) => debug!("No default route found"),
+                Ok(Some(route)) => {
+                    if self.config.interface.as_ref() == Some(&route.interface) {
+                        return Some(format!("default route is via '{}'", route.interface));
+                    }
+                    if let Some(ref gateway_mac) = self.config.gateway_mac {
+                        match neighbour_hw_addr(route.gateway).await {
+                            Err(e) => warn!("Failed to look up the gateway's address: {:#}", e),
+                            Ok(Some(hw_addr)) if hw_addr.eq_ignore_ascii_case(gateway_mac) => {
+                                return Some(format!("default gateway is '{}'", hw_addr));
+                            }
+                            Ok(_) => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(ref check_command) = self.config.check_command {
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(check_command)
+                .kill_on_drop(true)
+                .status();
+            let timeout = self
+                .config
+                .check_timeout
+                .unwrap_or(Self::DEFAULT_CHECK_TIMEOUT);
+            match tokio::time::timeout(timeout, status).await {
+                Err(_) => warn!("Timed out waiting for metered check command to complete"),
+                Ok(Err(e)) => warn!("Failed to run metered check command: {}", e),
+                Ok(Ok(status)) if status.success() => {
+                    return Some(format!("'{}' succeeded", check_command));
+                }
+                Ok(Ok(status)) => trace!("Metered check command exited with {}", status),
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,156 @@
+//! Inspection of the state of the local network link, to decide whether (and how) to measure.
+
+pub(super) mod busy;
+pub(super) mod metered;
+
+use std::net::Ipv4Addr;
+
+use anyhow::{Context, Result};
+
+/// The IPv4 default route, as found in `/proc/net/route`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DefaultRoute {
+    pub(crate) interface: String,
+    pub(crate) gateway: Ipv4Addr,
+}
+
+/// Looks up the IPv4 default route with the lowest metric, if any.
+pub(crate) async fn default_route() -> Result<Option<DefaultRoute>> {
+    let route = tokio::fs::read_to_string("/proc/net/route")
+        .await
+        .with_context(|| "failed to read '/proc/net/route'")?;
+    Ok(parse_default_route(&route))
+}
+
+/// Looks up the hardware address of the given neighbour in the ARP cache, if it is there.
+pub(crate) async fn neighbour_hw_addr(ip: Ipv4Addr) -> Result<Option<String>> {
+    let arp = tokio::fs::read_to_string("/proc/net/arp")
+        .await
+        .with_context(|| "failed to read '/proc/net/arp'")?;
+    Ok(parse_hw_addr(&arp, ip))
+}
+
+/// Byte counters of a network interface, as found in `/proc/net/dev`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Counters {
+    pub(crate) rx_bytes: u64,
+    pub(crate) tx_bytes: u64,
+}
+
+/// Looks up the byte counters of the given network interface, if it exists.
+pub(crate) async fn interface_counters(interface: &str) -> Result<Option<Counters>> {
+    let dev = tokio::fs::read_to_string("/proc/net/dev")
+        .await
+        .with_context(|| "failed to read '/proc/net/dev'")?;
+    Ok(parse_counters(&dev, interface))
+}
+
+fn parse_default_route(route: &str) -> Option<DefaultRoute> {
+    route
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            // Iface Destination Gateway Flags RefCnt Use Metric Mask MTU Window IRTT
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 8 || fields[1] != "00000000" || fields[7] != "00000000" {
+                return None;
+            }
+            // Addresses are printed as host-endian hexadecimal integers.
+            let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+            let metric: u32 = fields[6].parse().ok()?;
+            Some((
+                metric,
+                DefaultRoute {
+                    interface: fields[0].to_owned(),
+                    gateway: Ipv4Addr::from(gateway.to_ne_bytes()),
+                },
+            ))
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|(_, route)| route)
+}
+
+fn parse_hw_addr(arp: &str, ip: Ipv4Addr) -> Option<String> {
+    arp.lines().skip(1).find_map(|line| {
+        // IP address, HW type, Flags, HW address, Mask, Device
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields
+            .first()
+            .and_then(|addr| addr.parse::<Ipv4Addr>().ok())
+        {
+            Some(addr) if addr == ip => fields.get(3).map(|hw| hw.to_lowercase()),
+            _ => None,
+        }
+    })
+}
+
+fn parse_counters(dev: &str, interface: &str) -> Option<Counters> {
+    dev.lines().skip(2).find_map(|line| {
+        // Interface: 8 receive fields (bytes first), then 8 transmit fields (bytes first)
+        let (name, fields) = line.split_once(':')?;
+        if name.trim() != interface {
+            return None;
+        }
+        let fields: Vec<&str> = fields.split_whitespace().collect();
+        Some(Counters {
+            rx_bytes: fields.first()?.parse().ok()?,
+            tx_bytes: fields.get(8)?.parse().ok()?,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_endian = "little")]
+    #[test]
+    fn default_route() {
+        let route = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wwan0\t00000000\t0100000A\t0003\t0\t0\t700\t00000000\t0\t0\t0
+eth0\t00000000\t0102A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t0002A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+";
+        assert_eq!(
+            parse_default_route(route),
+            Some(DefaultRoute {
+                interface: "eth0".to_owned(),
+                gateway: Ipv4Addr::new(192, 168, 2, 1),
+            })
+        );
+        assert_eq!(parse_default_route(route.lines().next().unwrap()), None);
+    }
+
+    #[test]
+    fn hw_addr() {
+        let arp = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.2.1      0x1         0x2         AA:BB:CC:00:11:22     *        eth0
+192.168.2.17     0x1         0x2         aa:bb:cc:33:44:55     *        eth0
+";
+        assert_eq!(
+            parse_hw_addr(arp, Ipv4Addr::new(192, 168, 2, 1)).as_deref(),
+            Some("aa:bb:cc:00:11:22")
+        );
+        assert_eq!(parse_hw_addr(arp, Ipv4Addr::new(192, 168, 2, 2)), None);
+    }
+
+    #[test]
+    fn counters() {
+        let dev = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:  812345    1234    0    0    0     0          0         0   812345    1234    0    0    0     0       0          0
+  eth0:1234567890 987654    0   12    0     0          0       321 98765432  54321    0    0    0     0       0          0
+";
+        assert_eq!(
+            parse_counters(dev, "eth0"),
+            Some(Counters {
+                rx_bytes: 1_234_567_890,
+                tx_bytes: 98_765_432,
+            })
+        );
+        assert_eq!(parse_counters(dev, "eth"), None);
+    }
+}
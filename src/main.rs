@@ -1,20 +1,18 @@
 mod config;
 mod exporters;
+mod link;
 mod measure;
 mod monitor;
+mod probe;
+mod schedule;
+mod supervisor;
 
 use std::io;
 
-use anyhow::{bail, Result};
+use anyhow::Result;
 use tracing_subscriber::{filter::LevelFilter, fmt::format::FmtSpan, EnvFilter};
 
-#[cfg(feature = "zpeters")]
-use crate::measure::speedtestr::SpeedTestR;
-use crate::{
-    config::Config,
-    measure::{speedtest_cli::SpeedTestCli, Measurer},
-    monitor::Monitor,
-};
+use crate::{config::Config, monitor::Monitor};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -33,26 +31,5 @@ async fn main() -> Result<()> {
         .init();
 
     let config = Config::parse()?;
-    let measurer = initialize_measurer(&config)?;
-    Monitor::new(config, measurer).await?.run().await
-}
-
-#[tracing::instrument(skip(config))]
-fn initialize_measurer(config: &Config) -> Result<Box<dyn Measurer>> {
-    config
-        .measurer
-        .as_deref()
-        .map_or(
-            Ok(Box::new(SpeedTestCli::default())),
-            |m| match m.to_lowercase().as_str() {
-                "ookla" | "default" => Ok(Box::new(SpeedTestCli::default())),
-                "zpeters/speedtestr" | "zpeters" | "speedtestr" => {
-                    #[cfg(feature = "zpeters")]
-                    return Ok(Box::new(SpeedTestR::default()));
-                    #[cfg(not(feature = "zpeters"))]
-                    bail!("The Cargo feature 'zpeters' MUST be enabled to use the 'SpeedTestR' Measurer");
-                }
-                m => bail!("Unknown measurer '{}'", m),
-            },
-        )
+    Monitor::new(config).await?.run().await
 }
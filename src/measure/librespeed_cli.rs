@@ -0,0 +1,203 @@
+use std::{net::IpAddr, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::{process::Command, time::Instant};
+use tracing::{debug, error, trace, warn};
+
+use super::{Measurement, Measurer};
+
+/// Configuration for the `LibreSpeedCli` `Measurer`.
+#[derive(Debug, Default, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The ID of the LibreSpeed server to measure against (passed as `--server`).
+    server: Option<u32>,
+    /// Path to a JSON file listing self-hosted LibreSpeed servers (passed as `--local-json`).
+    local_json: Option<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+pub struct LibreSpeedCli {
+    config: Config,
+}
+
+impl LibreSpeedCli {
+    const BINARY: &'static str = "librespeed-cli";
+
+    #[tracing::instrument]
+    pub(crate) fn new(config: Config) -> Self {
+        trace!("Creating new '{}'", std::any::type_name::<Self>());
+        Self { config }
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["--json".to_owned()];
+        if let Some(server) = self.config.server {
+            args.push("--server".to_owned());
+            args.push(server.to_string());
+        }
+        if let Some(ref local_json) = self.config.local_json {
+            args.push("--local-json".to_owned());
+            args.push(local_json.to_string_lossy().into_owned());
+        }
+        args
+    }
+}
+
+/// A single result, as reported by `librespeed-cli --json`.
+#[derive(Debug, Deserialize)]
+struct Report {
+    server: ReportServer,
+    client: ReportClient,
+    /// Milliseconds.
+    ping: f64,
+    /// Milliseconds.
+    jitter: f64,
+    /// Megabits per second.
+    download: f64,
+    /// Megabits per second.
+    upload: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReportServer {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReportClient {
+    ip: String,
+}
+
+/// Parses the (non-empty) stdout of `librespeed-cli --json`, which is a JSON array with one
+/// object per server measured against; only the first one is taken into account.
+fn parse_output(stdout: &[u8]) -> Result<Measurement> {
+    let reports: Vec<Report> =
+        serde_json::from_slice(stdout).with_context(|| "failed to deserialize the JSON output")?;
+    let report = match reports.into_iter().next() {
+        Some(report) => report,
+        None => bail!("the JSON output contains no results"),
+    };
+    Ok(Measurement {
+        ping_latency: report.ping,
+        download_speed: report.download,
+        upload_speed: report.upload,
+        jitter: Some(report.jitter),
+        server: Some(report.server.name),
+        client_ip: report.client.ip.parse::<IpAddr>().ok(),
+        ..Default::default()
+    })
+}
+
+#[async_trait]
+impl Measurer for LibreSpeedCli {
+    #[tracing::instrument]
+    async fn measure(&mut self, deadline: Instant) -> Measurement {
+        let fork_output = Command::new(Self::BINARY)
+            .args(self.args())
+            .kill_on_drop(true)
+            .output();
+
+        trace!("Now blocking, waiting for execution to complete or to time out...");
+        let out = match tokio::time::timeout_at(deadline, fork_output).await {
+            Err(task_timeout_err) => {
+                error!(
+                    "Timed out waiting for the '{}' binary to complete its execution: {}",
+                    Self::BINARY,
+                    task_timeout_err
+                );
+                return Default::default();
+            }
+            Ok(task_result) => match task_result {
+                Err(io_err) => {
+                    error!(
+                        "Failed to spawn the '{}' binary or to retrieve its output: {}",
+                        Self::BINARY,
+                        io_err
+                    );
+                    return Default::default();
+                }
+                Ok(out) => {
+                    debug!(
+                        "The execution of the '{}' binary finished with '{}' and stdout: '{:?}'",
+                        Self::BINARY,
+                        out.status,
+                        std::str::from_utf8(&out.stdout)
+                    );
+                    out
+                }
+            },
+        };
+
+        // Handle errors or indications thereof; unlike Ookla's binary, `librespeed-cli` reports
+        // its errors on stderr rather than in its JSON output.
+        if !out.stderr.is_empty() {
+            warn!(
+                "The execution of the '{}' binary finished with a non-empty stderr: '{:?}'",
+                Self::BINARY,
+                std::str::from_utf8(&out.stderr)
+            );
+        }
+        if !out.status.success() || out.stdout.is_empty() {
+            error!(
+                "The execution of the '{}' binary failed with code '{:?}'",
+                Self::BINARY,
+                out.status.code(),
+            );
+            return Default::default();
+        }
+
+        parse_output(&out.stdout).unwrap_or_else(|e| {
+            error!(
+                "Failed to parse the output of the '{}' binary: {:#}",
+                Self::BINARY,
+                e
+            );
+            Default::default()
+        })
+    }
+
+    fn kind(&self) -> &'static str {
+        "librespeed"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let out = br#"[{
+            "timestamp": "2021-09-20T18:35:10.470981524+03:00",
+            "server": { "name": "Self-hosted (Athens)", "url": "https://speed.example.com/" },
+            "client": {
+                "ip": "203.0.113.7", "hostname": "", "city": "", "region": "", "country": "",
+                "loc": "", "org": "", "postal": "", "timezone": ""
+            },
+            "bytes_sent": 91750400,
+            "bytes_received": 183500800,
+            "ping": 3.25,
+            "jitter": 0.41,
+            "upload": 93.87,
+            "download": 187.56,
+            "share": ""
+        }]"#;
+        let m = parse_output(out)?;
+        assert_eq!(
+            (m.ping_latency, m.download_speed, m.upload_speed),
+            (3.25, 187.56, 93.87)
+        );
+        assert_eq!(m.jitter, Some(0.41));
+        assert_eq!(m.server.as_deref(), Some("Self-hosted (Athens)"));
+        assert_eq!(m.client_ip, Some("203.0.113.7".parse()?));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert!(parse_output(b"[]").is_err());
+        assert!(parse_output(b"{}").is_err());
+    }
+}
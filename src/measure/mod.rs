@@ -1,23 +1,133 @@
+pub(super) mod librespeed_cli;
+pub(super) mod plugin;
 pub(super) mod speedtest_cli;
 #[cfg(feature = "zpeters")]
 pub(super) mod speedtestr;
+pub(super) mod validator;
 
-use std::fmt::Debug;
+use std::{fmt::Debug, net::IpAddr, time::Duration};
 
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tokio::time::Instant;
+use tracing::trace;
+
+#[cfg(feature = "zpeters")]
+use self::speedtestr::SpeedTestR;
+use self::{librespeed_cli::LibreSpeedCli, plugin::Plugin, speedtest_cli::SpeedTestCli};
+use crate::probe::Config;
 
 #[async_trait]
-pub(super) trait Measurer: Debug {
+pub(super) trait Measurer: Debug + Send + Sync {
     async fn measure(&mut self, deadline: Instant) -> Measurement;
+
+    /// The kind of the `Measurer`, as reported along with its measurements.
+    fn kind(&self) -> &'static str;
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+/// Creates a new `Measurer` of the given kind (or the default one, if no kind is specified).
+#[tracing::instrument(skip(config))]
+pub(super) fn new_measurer(kind: Option<&str>, config: &Config) -> Result<Box<dyn Measurer>> {
+    trace!("Creating new Measurer of kind {:?}", kind);
+    kind.map_or(Ok(Box::new(SpeedTestCli::default())), |m| {
+        match m.to_lowercase().as_str() {
+            "ookla" | "default" => Ok(Box::new(SpeedTestCli::default())),
+            "librespeed" | "librespeed-cli" => Ok(Box::new(LibreSpeedCli::new(
+                config.librespeed_config.clone().unwrap_or_default(),
+            ))),
+            "plugin" => Ok(Box::new(Plugin::new(
+                config.plugin_config.clone().ok_or_else(|| {
+                    anyhow!("The 'plugin' Measurer requires a 'plugin' configuration section")
+                })?,
+            ))),
+            "zpeters/speedtestr" | "zpeters" | "speedtestr" => {
+                #[cfg(feature = "zpeters")]
+                return Ok(Box::new(SpeedTestR::default()));
+                #[cfg(not(feature = "zpeters"))]
+                bail!(
+                    "The Cargo feature 'zpeters' MUST be enabled to use the 'SpeedTestR' Measurer"
+                );
+            }
+            m => bail!("Unknown measurer '{}'", m),
+        }
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct Measurement {
+    /// The name of the probe that took this measurement.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe: Option<String>,
     pub ping_latency: f64,
     pub download_speed: f64,
     pub upload_speed: f64,
+    /// Ping jitter in milliseconds, for `Measurer`s that report it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<f64>,
+    /// The kind of `Measurer` that took this measurement.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub measurer: Option<String>,
+    /// How long the `Measurer` took to take this measurement.
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub duration: Option<Duration>,
+    /// The network interface that the default route went through, once this measurement was
+    /// taken.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// The name of the server measured against, for `Measurer`s that report it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// The client's (public) IP address as seen by the server, for `Measurer`s that report it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<IpAddr>,
+    /// Summary of the distribution of latency samples, for `Measurer`s that take many of them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_stats: Option<LatencyStats>,
+    /// Why this measurement failed validation, if it did (but was not rejected).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suspect: Option<String>,
+    /// Why a fallback `Measurer` was used instead of the configured one, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downgraded: Option<String>,
+    /// Why (and for how long) this round was deferred, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deferred: Option<String>,
+    /// Why this round was skipped without measuring, if it was; such (empty) measurements are
+    /// recorded in the Database's history (without evicting actual ones) and exported as well.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+impl Measurement {
+    pub(crate) fn skipped(reason: String) -> Self {
+        Self {
+            skipped: Some(reason),
+            ..Default::default()
+        }
+    }
+
+    /// `Measurer`s report failures through all-zero measurements.
+    pub(crate) fn is_failed(&self) -> bool {
+        self.ping_latency == 0. && self.download_speed == 0. && self.upload_speed == 0.
+    }
+
+    /// A one-word summary of this measurement: `"skipped"`, `"failed"`, `"suspect"` or `"ok"`.
+    #[cfg_attr(
+        not(any(feature = "email", feature = "mqtt", feature = "webhook")),
+        allow(dead_code)
+    )]
+    pub(crate) fn status(&self) -> &'static str {
+        if self.skipped.is_some() {
+            "skipped"
+        } else if self.is_failed() {
+            "failed"
+        } else if self.suspect.is_some() {
+            "suspect"
+        } else {
+            "ok"
+        }
+    }
 }
 
 impl From<(f64, f64, f64)> for Measurement {
@@ -26,6 +136,96 @@ impl From<(f64, f64, f64)> for Measurement {
             ping_latency,
             download_speed,
             upload_speed,
+            ..Default::default()
+        }
+    }
+}
+
+/// Summary statistics (in milliseconds) over the latency samples taken during a single round.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+    pub stddev: f64,
+}
+
+impl LatencyStats {
+    /// Summarizes the given latency samples, ignoring any that are not finite; returns `None` if
+    /// no samples are left.
+    pub(crate) fn from_samples(samples: &[f64]) -> Option<Self> {
+        let mut sorted: Vec<f64> = samples.iter().copied().filter(|s| s.is_finite()).collect();
+        if sorted.is_empty() {
+            return None;
         }
+        sorted.sort_unstable_by(|a, b| a.partial_cmp(b).expect("finite f64s are comparable"));
+
+        let n = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / n;
+        let variance = sorted.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / n;
+        Some(Self {
+            min: sorted[0],
+            p50: Self::percentile(&sorted, 50.),
+            p90: Self::percentile(&sorted, 90.),
+            p99: Self::percentile(&sorted, 99.),
+            max: sorted[sorted.len() - 1],
+            stddev: variance.sqrt(),
+        })
+    }
+
+    /// Linearly interpolates the `p`-th percentile of the given non-empty, sorted samples.
+    fn percentile(sorted: &[f64], p: f64) -> f64 {
+        let rank = p / 100. * (sorted.len() - 1) as f64;
+        let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+        sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+    }
+}
+
+/// Returns the median of the given non-empty slice of values, sorting it in place (non-finite
+/// values are ordered as per `f64::total_cmp`, rather than causing a panic).
+pub(crate) fn median(values: &mut [f64]) -> f64 {
+    values.sort_unstable_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len() % 2 == 1 {
+        values[mid]
+    } else {
+        (values[mid - 1] + values[mid]) / 2.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_stats() {
+        assert_eq!(LatencyStats::from_samples(&[]), None);
+        assert_eq!(LatencyStats::from_samples(&[f64::NAN]), None);
+
+        let single = LatencyStats::from_samples(&[4.]).unwrap();
+        assert_eq!(
+            (single.min, single.p50, single.p99, single.max),
+            (4., 4., 4., 4.)
+        );
+        assert_eq!(single.stddev, 0.);
+
+        let samples: Vec<f64> = (1..=101).rev().map(f64::from).collect();
+        let stats = LatencyStats::from_samples(&samples).unwrap();
+        assert_eq!((stats.min, stats.max), (1., 101.));
+        assert_eq!((stats.p50, stats.p90, stats.p99), (51., 91., 100.));
+        assert!((stats.stddev - 29.154_759).abs() < 1e-6);
+    }
+
+    #[test]
+    fn median() {
+        assert_eq!(super::median(&mut [3., 1., 2.]), 2.);
+        assert_eq!(super::median(&mut [4., 1., 3., 2.]), 2.5);
+        assert_eq!(super::median(&mut [f64::NAN, 1., 2.]), 2.);
+        assert_eq!(
+            super::median(&mut [f64::INFINITY, f64::NEG_INFINITY, 1.]),
+            1.
+        );
     }
 }
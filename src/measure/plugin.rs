@@ -0,0 +1,382 @@
+//! A `Measurer` that delegates measuring to a long-running external plugin process.
+//!
+//! The plugin is spawned once, and then it is talked to over line-delimited JSON-RPC 2.0 on its
+//! stdin (requests) and stdout (responses); its stderr is inherited, so that it may log freely.
+//! Two methods are currently called:
+//! - `health`, with no params, to which any non-error `result` is a sign of good health;
+//! - `measure`, with params `{"timeout_ms": <u64>, "deadline": "<RFC 3339 timestamp>"}`, whose
+//!   `result` is expected to be an object like:
+//!   `{"ping_latency": 3.2, "download_speed": 187.5, "upload_speed": 93.8}` (milliseconds and
+//!   Mbps), optionally also including `"jitter"`, `"server"`, `"client_ip"` and
+//!   `"latency_samples"` (an array of all individual latency samples, in milliseconds).
+//!
+//! If the plugin crashes, fails a health check or closes its stdout, it is killed (if need be)
+//! and restarted before the next measurement.
+
+use std::{net::IpAddr, process::Stdio, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::Local;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    time::Instant,
+};
+use tracing::{debug, error, info, trace, warn};
+
+use super::{LatencyStats, Measurement, Measurer};
+
+/// Configuration for the `Plugin` `Measurer`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The plugin executable; looked up in `PATH` if it is not a path.
+    command: String,
+    /// Command line arguments to be passed to the plugin executable.
+    #[serde(default)]
+    args: Vec<String>,
+    /// How long to wait for the plugin to respond to a health check before each measurement.
+    #[serde(default, with = "humantime_serde")]
+    health_check_timeout: Option<Duration>,
+}
+
+/// The expected `result` of a `measure` call.
+#[derive(Debug, Deserialize)]
+struct MeasureResult {
+    ping_latency: f64,
+    download_speed: f64,
+    upload_speed: f64,
+    jitter: Option<f64>,
+    server: Option<String>,
+    client_ip: Option<IpAddr>,
+    #[serde(default)]
+    latency_samples: Vec<f64>,
+}
+
+impl From<MeasureResult> for Measurement {
+    fn from(r: MeasureResult) -> Self {
+        Self {
+            ping_latency: r.ping_latency,
+            download_speed: r.download_speed,
+            upload_speed: r.upload_speed,
+            jitter: r.jitter,
+            server: r.server,
+            client_ip: r.client_ip,
+            latency_stats: LatencyStats::from_samples(&r.latency_samples),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+enum CallError {
+    /// The call did not complete before its deadline; the plugin may still be working on it.
+    Timeout,
+    /// The plugin responded with a JSON-RPC error object.
+    Rpc(Value),
+    /// Communication with the plugin failed; it has most probably crashed.
+    Transport(anyhow::Error),
+}
+
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "timed out waiting for the plugin to respond"),
+            Self::Rpc(err) => write!(f, "the plugin responded with error: {}", err),
+            Self::Transport(err) => write!(f, "failed to communicate with the plugin: {:#}", err),
+        }
+    }
+}
+
+/// A running instance of the plugin executable.
+#[derive(Debug)]
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+#[derive(Debug)]
+pub struct Plugin {
+    config: Config,
+    process: Option<Process>,
+    next_id: u64,
+    started: bool,
+    restarts: u64,
+}
+
+impl Plugin {
+    const DEFAULT_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[tracing::instrument]
+    pub(crate) fn new(config: Config) -> Self {
+        trace!("Creating new '{}'", std::any::type_name::<Self>());
+        Self {
+            config,
+            process: None,
+            next_id: 0,
+            started: false,
+            restarts: 0,
+        }
+    }
+
+    fn spawn(&self) -> Result<Process> {
+        let mut child = Command::new(&self.config.command)
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("failed to spawn plugin '{}'", self.config.command))?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("failed to capture the plugin's stdin"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("failed to capture the plugin's stdout"))?;
+        Ok(Process {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// Makes sure that a plugin process that has not (visibly) crashed is available, spawning a
+    /// new one if need be.
+    fn ensure_running(&mut self) -> Result<&mut Process> {
+        if let Some(ref mut process) = self.process {
+            match process.child.try_wait() {
+                Ok(None) => {}
+                Ok(Some(status)) => {
+                    warn!(
+                        "Plugin '{}' has exited with {}",
+                        self.config.command, status
+                    );
+                    self.process = None;
+                }
+                Err(e) => {
+                    warn!("Failed to check the status of the plugin process: {}", e);
+                    self.process = None;
+                }
+            }
+        }
+        if self.process.is_none() {
+            let process = self.spawn()?;
+            if self.started {
+                self.restarts += 1;
+                info!(
+                    "Restarted plugin '{}' (restart #{})",
+                    self.config.command, self.restarts
+                );
+            } else {
+                info!("Started plugin '{}'", self.config.command);
+                self.started = true;
+            }
+            self.process = Some(process);
+        }
+        Ok(self
+            .process
+            .as_mut()
+            .expect("plugin process has just been set"))
+    }
+
+    /// Kills the current plugin process (if any), so that a new one is spawned next time.
+    fn discard(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            if let Err(e) = process.child.start_kill() {
+                debug!("Failed to kill the plugin process: {}", e);
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn call(
+        &mut self,
+        method: &str,
+        params: Value,
+        deadline: Instant,
+    ) -> Result<Value, CallError> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let process = self.ensure_running().map_err(CallError::Transport)?;
+
+        let mut request =
+            json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }).to_string();
+        request.push('\n');
+        trace!("Sending request: {}", request.trim_end());
+        let exchange = async {
+            process.stdin.write_all(request.as_bytes()).await?;
+            process.stdin.flush().await?;
+            loop {
+                let line = match process.stdout.next_line().await? {
+                    Some(line) => line,
+                    None => return Err(anyhow!("the plugin closed its stdout")),
+                };
+                trace!("Received response: {}", line);
+                let response: Value = match serde_json::from_str(&line) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        warn!(
+                            "Ignoring malformed line from the plugin ({}): {:?}",
+                            e, line
+                        );
+                        continue;
+                    }
+                };
+                // Skip any late responses to earlier calls that have timed out
+                if response["id"].as_u64() != Some(id) {
+                    debug!("Ignoring response to a previous call: {:?}", line);
+                    continue;
+                }
+                return Ok(response);
+            }
+        };
+
+        match tokio::time::timeout_at(deadline, exchange).await {
+            Err(_) => Err(CallError::Timeout),
+            Ok(Err(e)) => Err(CallError::Transport(e)),
+            Ok(Ok(mut response)) => match response.get_mut("error") {
+                Some(err) if !err.is_null() => Err(CallError::Rpc(err.take())),
+                _ => Ok(response["result"].take()),
+            },
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn health_check(&mut self, deadline: Instant) -> Result<(), CallError> {
+        let timeout = self
+            .config
+            .health_check_timeout
+            .unwrap_or(Self::DEFAULT_HEALTH_CHECK_TIMEOUT);
+        let deadline = deadline.min(Instant::now() + timeout);
+        self.call("health", Value::Null, deadline).await.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl Measurer for Plugin {
+    #[tracing::instrument]
+    async fn measure(&mut self, deadline: Instant) -> Measurement {
+        // Make sure the plugin is responsive before asking it to measure; if it is not, give it
+        // a fresh start.
+        if let Err(e) = self.health_check(deadline).await {
+            warn!(
+                "Plugin '{}' failed its health check: {}",
+                self.config.command, e
+            );
+            self.discard();
+        }
+
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let wall_clock_deadline = Local::now()
+            + chrono::Duration::from_std(timeout).unwrap_or_else(|_| chrono::Duration::zero());
+        let params = json!({
+            "timeout_ms": timeout.as_millis() as u64,
+            "deadline": wall_clock_deadline.to_rfc3339(),
+        });
+        let result = match self.call("measure", params, deadline).await {
+            Ok(result) => result,
+            Err(e) => {
+                error!("Plugin '{}' failed to measure: {}", self.config.command, e);
+                if matches!(e, CallError::Transport(_)) {
+                    self.discard();
+                }
+                return Default::default();
+            }
+        };
+
+        match serde_json::from_value::<MeasureResult>(result) {
+            Ok(result) => result.into(),
+            Err(e) => {
+                error!(
+                    "Failed to deserialize the result reported by plugin '{}': {}",
+                    self.config.command, e
+                );
+                Default::default()
+            }
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "plugin"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESULT: &str = concat!(
+        r#"{"ping_latency":3.2,"download_speed":187.5,"upload_speed":93.8,"#,
+        r#""server":"test","latency_samples":[3,3.5]}"#
+    );
+
+    fn plugin(script: String) -> Plugin {
+        Plugin::new(Config {
+            command: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script],
+            health_check_timeout: None,
+        })
+    }
+
+    // A script that responds to each request with the given result, after running `before`.
+    fn responder(before: &str, result: &str) -> String {
+        format!(
+            r#"while read -r line; do
+                {}
+                id=$(echo "$line" | sed 's/.*"id":\([0-9]*\).*/\1/')
+                echo '{{"jsonrpc":"2.0","id":'"$id"',"result":{}}}'
+            done"#,
+            before, result
+        )
+    }
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_secs(10)
+    }
+
+    #[tokio::test]
+    async fn good() {
+        let mut p = plugin(responder(":", RESULT));
+        for _ in 0..2 {
+            let m = p.measure(deadline()).await;
+            assert_eq!(
+                (m.ping_latency, m.download_speed, m.upload_speed),
+                (3.2, 187.5, 93.8)
+            );
+            assert_eq!(m.server.as_deref(), Some("test"));
+            assert_eq!(m.latency_stats.map(|s| s.max), Some(3.5));
+        }
+        assert_eq!(p.restarts, 0);
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        // Malformed lines are ignored
+        let mut p = plugin(responder("echo 'not json'", RESULT));
+        assert!(!p.measure(deadline()).await.is_failed());
+
+        // Malformed results are failed measurements, but the plugin keeps running
+        let mut p = plugin(responder(":", r#"{"ping_latency":"fast"}"#));
+        assert!(p.measure(deadline()).await.is_failed());
+        assert!(p.process.is_some());
+    }
+
+    #[tokio::test]
+    async fn exit() {
+        // Responds to health checks, but exits instead of responding to measurement requests
+        let script = responder(r#"case "$line" in *'"measure"'*) exit 1;; esac"#, RESULT);
+        let mut p = plugin(script);
+        assert!(p.measure(deadline()).await.is_failed());
+        assert!(p.process.is_none());
+
+        // It is restarted before the next measurement
+        assert!(p.measure(deadline()).await.is_failed());
+        assert_eq!(p.restarts, 1);
+    }
+}
@@ -102,4 +102,8 @@ impl Measurer for SpeedTestCli {
 
         (ping_latency, download_speed, upload_speed).into()
     }
+
+    fn kind(&self) -> &'static str {
+        "ookla"
+    }
 }
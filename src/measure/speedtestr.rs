@@ -4,7 +4,7 @@ use speedtestr::server;
 use tokio::time::Instant;
 use tracing::{error, trace};
 
-use super::{Measurement, Measurer};
+use super::{LatencyStats, Measurement, Measurer};
 
 #[derive(Debug, Default)]
 pub struct SpeedTestR;
@@ -62,19 +62,21 @@ impl Measurer for SpeedTestR {
         };
 
         //
-        // Now, measure the ping latency
+        // Now, measure the ping latency, one ping at a time, to summarize all samples
         //
         let best_server_id = best_server.id.clone();
-        let ping_latency = match tokio::time::timeout_at(
+        let ping_samples = match tokio::time::timeout_at(
             deadline,
             tokio::task::spawn_blocking(move || {
-                match server::ping_server(best_server_id.as_str(), Self::NUM_PINGS) {
-                    Ok(ping_latency) => ping_latency as f64,
-                    Err(e) => {
-                        error!("Failed to ping server: {}", e);
-                        0.
-                    }
-                }
+                (0..Self::NUM_PINGS)
+                    .filter_map(|_| match server::ping_server(best_server_id.as_str(), 1) {
+                        Ok(ping_latency) => Some(ping_latency as f64),
+                        Err(e) => {
+                            error!("Failed to ping server: {}", e);
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>()
             }),
         )
         .await
@@ -85,17 +87,20 @@ impl Measurer for SpeedTestR {
                     task_timeout_err
                 );
                 return Default::default(); // no time left to measure download & upload
-            },
-            Ok(task_result) => task_result.map_or_else(
-                |join_err| {
-                    error!(
-                        "Failed to join the blocking task for 'speedtestr::server::ping_server': {}",
-                        join_err
-                    );
-                    0.
-                },
-                |ping_latency| ping_latency,
-            ),
+            }
+            Ok(task_result) => task_result.unwrap_or_else(|join_err| {
+                error!(
+                    "Failed to join the blocking task for 'speedtestr::server::ping_server': {}",
+                    join_err
+                );
+                vec![]
+            }),
+        };
+        let latency_stats = LatencyStats::from_samples(&ping_samples);
+        let ping_latency = if ping_samples.is_empty() {
+            0.
+        } else {
+            ping_samples.iter().sum::<f64>() / ping_samples.len() as f64
         };
 
         //
@@ -124,7 +129,11 @@ impl Measurer for SpeedTestR {
                     "The blocking task for 'speedtestr::server::download' timed out: {}",
                     task_timeout_err
                 );
-                return (ping_latency, 0., 0.).into(); // no time left to measure upload
+                // no time left to measure upload
+                return Measurement {
+                    latency_stats,
+                    ..(ping_latency, 0., 0.).into()
+                };
             }
             Ok(task_result) => task_result.map_or_else(
                 |join_err| {
@@ -177,6 +186,13 @@ impl Measurer for SpeedTestR {
             ),
         };
 
-        (ping_latency, download_speed, upload_speed).into()
+        Measurement {
+            latency_stats,
+            ..(ping_latency, download_speed, upload_speed).into()
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "speedtestr"
     }
 }
@@ -0,0 +1,319 @@
+use std::collections::VecDeque;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use tracing::{debug, info, trace, warn};
+
+use super::{median, Measurement};
+
+/// Configuration for the `Validator`, which sits between the `Measurer` and the `Database`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// What to do with measurements that fail validation.
+    ///
+    /// Currently supported actions:
+    /// - Forward them, marked as suspect: `"mark"` (default);
+    /// - Drop them, so that they are neither stored nor exported: `"reject"`.
+    action: Option<String>,
+    /// Absolute bounds for ping latency, in milliseconds.
+    ping_latency: Option<Bounds>,
+    /// Absolute bounds for download speed, in Mbps.
+    download_speed: Option<Bounds>,
+    /// Absolute bounds for upload speed, in Mbps.
+    upload_speed: Option<Bounds>,
+    /// Threshold for the modified z-score (based on the median absolute deviation) of a value
+    /// against the recent history, above which it is considered an outlier. Statistical outlier
+    /// detection is disabled if this is not specified; 3.5 is a common choice.
+    outlier_threshold: Option<f64>,
+    /// The number of recent valid measurements that outliers are detected against.
+    history_size: Option<usize>,
+    /// The minimum number of recent valid measurements required for outlier detection to kick in.
+    min_history: Option<usize>,
+    /// The number of consecutive outliers in the same direction after which the link is
+    /// considered to have changed for good, so that they replace the recent history; `min_history`,
+    /// if absent.
+    #[serde(alias = "RebaselineAfter")]
+    rebaseline_after: Option<usize>,
+}
+
+/// Inclusive absolute bounds for a single measured value.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub(crate) struct Bounds {
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Mark,
+    Reject,
+}
+
+/// The outcome of checking a `Measurement`.
+#[derive(Debug)]
+struct Check {
+    /// Why the `Measurement` failed validation, if it did.
+    reasons: Vec<String>,
+    /// The values that are outliers, each as its name and whether it is above the recent ones.
+    outliers: Vec<(&'static str, bool)>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Validator {
+    config: Config,
+    action: Action,
+    history: VecDeque<Measurement>,
+    history_size: usize,
+    min_history: usize,
+    rebaseline_after: usize,
+    /// The latest consecutive measurements that failed validation only for being outliers, along
+    /// with the directions that they are all outliers in.
+    streak: Vec<Measurement>,
+    streak_outliers: Vec<(&'static str, bool)>,
+}
+
+impl Validator {
+    const DEFAULT_HISTORY_SIZE: usize = 30;
+    const DEFAULT_MIN_HISTORY: usize = 10;
+    /// Scales the MAD so that it is a consistent estimator of the standard deviation.
+    const MAD_SCALE: f64 = 0.6745;
+
+    #[tracing::instrument]
+    pub(crate) fn new(config: Config) -> Result<Self> {
+        trace!("Creating new '{}'", std::any::type_name::<Self>());
+        let action = match config.action.as_deref().map(str::to_lowercase).as_deref() {
+            None | Some("mark") | Some("default") => Action::Mark,
+            Some("reject") | Some("drop") => Action::Reject,
+            Some(unknown) => bail!("unsupported validation action: '{}'", unknown),
+        };
+        let history_size = config.history_size.unwrap_or(Self::DEFAULT_HISTORY_SIZE);
+        let min_history = config.min_history.unwrap_or(Self::DEFAULT_MIN_HISTORY);
+        if min_history == 0 || min_history > history_size {
+            bail!(
+                "validation min_history ({}) must be in [1, history_size ({})]",
+                min_history,
+                history_size
+            );
+        }
+        let rebaseline_after = config.rebaseline_after.unwrap_or(min_history);
+        if rebaseline_after == 0 || rebaseline_after > history_size {
+            bail!(
+                "validation rebaseline_after ({}) must be in [1, history_size ({})]",
+                rebaseline_after,
+                history_size
+            );
+        }
+        Ok(Self {
+            config,
+            action,
+            history: VecDeque::with_capacity(history_size),
+            history_size,
+            min_history,
+            rebaseline_after,
+            streak: Vec::with_capacity(rebaseline_after),
+            streak_outliers: vec![],
+        })
+    }
+
+    /// Validates the given `Measurement`, returning it (marked as suspect, if it failed
+    /// validation and the configured action is to mark it) or `None` if it has been rejected.
+    #[tracing::instrument(skip(self))]
+    pub(crate) fn validate(&mut self, mut measurement: Measurement) -> Option<Measurement> {
+        // Failed measurements are reported as such elsewhere; nothing to validate here.
+        if measurement.is_failed() {
+            return Some(measurement);
+        }
+
+        let Check { reasons, outliers } = self.check(&measurement);
+        if reasons.is_empty() {
+            self.streak.clear();
+            if self.history.len() == self.history_size {
+                self.history.pop_front();
+            }
+            self.history.push_back(measurement.clone());
+            return Some(measurement);
+        }
+        if reasons.len() == outliers.len() {
+            self.extend_streak(measurement.clone(), outliers);
+        } else {
+            // Values out of bounds are absurd, rather than a sign of a change in the link
+            self.streak.clear();
+        }
+
+        let reason = reasons.join("; ");
+        match self.action {
+            Action::Mark => {
+                warn!("Marking measurement as suspect: {}", reason);
+                measurement.suspect = Some(reason);
+                Some(measurement)
+            }
+            Action::Reject => {
+                warn!("Rejecting measurement {:?}: {}", measurement, reason);
+                None
+            }
+        }
+    }
+
+    /// Keeps track of the given outlier, replacing the recent history with the latest outliers
+    /// once enough of them in a row are outliers in the same direction (e.g., after the link has
+    /// been upgraded), which would otherwise keep failing validation forever.
+    fn extend_streak(&mut self, measurement: Measurement, outliers: Vec<(&'static str, bool)>) {
+        if self.streak.is_empty() {
+            self.streak_outliers = outliers;
+        } else {
+            self.streak_outliers
+                .retain(|outlier| outliers.contains(outlier));
+            if self.streak_outliers.is_empty() {
+                self.streak.clear();
+                self.streak_outliers = outliers;
+            }
+        }
+        self.streak.push(measurement);
+        if self.streak.len() < self.rebaseline_after {
+            return;
+        }
+        info!(
+            "{} consecutive outliers; replacing the recent history with them",
+            self.streak.len()
+        );
+        self.history.clear();
+        self.history.extend(self.streak.drain(..));
+    }
+
+    fn check(&self, m: &Measurement) -> Check {
+        type Getter = fn(&Measurement) -> f64;
+        let fields: [(&str, Option<Bounds>, Getter); 3] = [
+            ("ping latency", self.config.ping_latency, |m| m.ping_latency),
+            ("download speed", self.config.download_speed, |m| {
+                m.download_speed
+            }),
+            ("upload speed", self.config.upload_speed, |m| m.upload_speed),
+        ];
+
+        let mut reasons = vec![];
+        let mut outliers = vec![];
+        for (name, bounds, get) in fields.iter() {
+            let value = get(m);
+            if !value.is_finite() || value < 0. {
+                reasons.push(format!("{} is {}", name, value));
+                continue;
+            }
+            if let Some(Bounds { min, max }) = bounds {
+                if let Some(min) = min.filter(|min| value < *min) {
+                    reasons.push(format!("{} {} is below {}", name, value, min));
+                    continue;
+                }
+                if let Some(max) = max.filter(|max| value > *max) {
+                    reasons.push(format!("{} {} is above {}", name, value, max));
+                    continue;
+                }
+            }
+            if let Some(z) = self.modified_z_score(value, *get) {
+                debug!("Modified z-score of {} {} is {:.3}", name, value, z);
+                if z.abs() > self.config.outlier_threshold.unwrap_or(f64::INFINITY) {
+                    reasons.push(format!(
+                        "{} {} is an outlier (modified z-score {:.2})",
+                        name, value, z
+                    ));
+                    outliers.push((*name, z > 0.));
+                }
+            }
+        }
+        Check { reasons, outliers }
+    }
+
+    /// Returns the modified z-score of `value` against the values in recent history, if outlier
+    /// detection is enabled and there is enough (non-constant) history to compare against.
+    fn modified_z_score(&self, value: f64, get: fn(&Measurement) -> f64) -> Option<f64> {
+        self.config.outlier_threshold?;
+        if self.history.len() < self.min_history {
+            return None;
+        }
+        let mut values: Vec<f64> = self.history.iter().map(get).collect();
+        let center = median(&mut values);
+        let mut deviations: Vec<f64> = values.iter().map(|v| (v - center).abs()).collect();
+        let mad = median(&mut deviations);
+        if mad == 0. {
+            return None;
+        }
+        Some(Self::MAD_SCALE * (value - center) / mad)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(action: &str, outlier_threshold: Option<f64>) -> Validator {
+        Validator::new(Config {
+            action: Some(action.to_owned()),
+            ping_latency: None,
+            download_speed: Some(Bounds {
+                min: None,
+                max: Some(100.),
+            }),
+            upload_speed: None,
+            outlier_threshold,
+            history_size: Some(10),
+            min_history: Some(5),
+            rebaseline_after: Some(3),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn bounds() {
+        let mut v = validator("mark", None);
+        let m = v.validate((10., 94., 9.).into()).unwrap();
+        assert_eq!(m.suspect, None);
+        let m = v.validate((10., 20_000., 9.).into()).unwrap();
+        assert!(m.suspect.unwrap().contains("download speed"));
+        let m = v.validate((-1., 94., 9.).into()).unwrap();
+        assert!(m.suspect.unwrap().contains("ping latency"));
+
+        let mut v = validator("reject", None);
+        assert!(v.validate((10., 20_000., 9.).into()).is_none());
+        // Failed measurements are left alone
+        assert!(v.validate(Default::default()).is_some());
+    }
+
+    #[test]
+    fn outliers() {
+        let mut v = validator("mark", Some(3.5));
+        for latency in [10., 11., 9., 10.5, 9.5, 10.].iter() {
+            let m = v.validate((*latency, 90., 9.).into()).unwrap();
+            assert_eq!(m.suspect, None);
+        }
+        let m = v.validate((250., 90., 9.).into()).unwrap();
+        assert!(m.suspect.unwrap().contains("outlier"));
+        // Suspect measurements do not pollute the history
+        assert_eq!(v.history.len(), 6);
+    }
+
+    #[test]
+    fn rebaseline() {
+        let mut v = validator("mark", Some(3.5));
+        for download in [50., 51., 49., 50.5, 49.5, 50.].iter() {
+            let m = v.validate((10., *download, 9.).into()).unwrap();
+            assert_eq!(m.suspect, None);
+        }
+        // Outliers in different directions, or interrupted by valid measurements, are spikes
+        for download in [95., 5., 95., 50., 95.].iter() {
+            let m = v.validate((10., *download, 9.).into()).unwrap();
+            assert_eq!(m.suspect.is_some(), *download != 50.);
+        }
+        assert_eq!(v.history.len(), 7);
+        // Consecutive outliers in the same direction mean that the link has changed for good
+        for download in [96., 94.].iter() {
+            let m = v.validate((10., *download, 9.).into()).unwrap();
+            assert!(m.suspect.unwrap().contains("outlier"));
+        }
+        assert_eq!(v.history.len(), 3);
+        for download in [95., 94.5, 95.5].iter() {
+            let m = v.validate((10., *download, 9.).into()).unwrap();
+            assert_eq!(m.suspect, None);
+        }
+        let m = v.validate((10., 50., 9.).into()).unwrap();
+        assert!(m.suspect.unwrap().contains("outlier"));
+    }
+}
@@ -1,243 +1,586 @@
-#[cfg(any(feature = "http", feature = "twitter"))]
-use std::path::PathBuf;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
 use tokio::{
     signal::unix::{signal, SignalKind},
-    sync::{broadcast, mpsc, oneshot, watch},
-    task::JoinHandle,
-    time::{self, Instant, Interval},
+    sync::{mpsc, watch},
+    task::{JoinError, JoinHandle},
+    time::{self, Instant},
 };
-use tracing::{debug, error, info, trace};
-
-#[cfg(all(feature = "plot", any(feature = "http", feature = "twitter")))]
-use crate::exporters::database::plotter::PLOT_FILE_NAME;
-#[cfg(feature = "http")]
-use crate::exporters::http::Http;
-#[cfg(feature = "twitter")]
-use crate::exporters::twitter::Twitter;
+use tracing::{debug, error, info, warn};
+
 use crate::{
     config::Config,
-    exporters::{
-        database::{self, Database},
-        stdout::StdOut,
-    },
-    measure::{Measurement, Measurer},
+    probe::{self, Control, Outcome, Probe, Reload, Routes, Trigger},
+    supervisor::Supervisor,
 };
 
 pub(crate) struct Monitor {
-    //config: Config,
-    /// An implementation of a `Measurer`, which provides `Monitor` with `Measurement`s to
-    /// propagate them to other actors (e.g., the Database, the exporters).
-    measurer: Box<dyn Measurer>,
-    /// Sending end of a `mpsc` channel to allow Monitor to broadcast new measurements to the
-    /// Database task.
-    db_tx: mpsc::Sender<database::SyncMessage>,
-    /// Sending end of a `broadcast` channel to allow Monitor to broadcast new measurements to
-    /// exporter tasks.
-    exp_tx: broadcast::Sender<Measurement>,
-    /// Sending end of the `watch` (spmc) channel to signal other actors (i.e., exporters and
-    /// database) to gracefully terminate.
+    /// The configuration that the running actors have been created out of.
+    config: Config,
+    /// The probes that have been created but not spawned yet.
+    probes: Vec<Probe>,
+    /// The `Handle`s of all running probes, by name.
+    registry: probe::Registry,
+    /// The spawned probes, including any that have been signalled to quit but are not over yet.
+    running: Vec<RunningProbe>,
+    /// The outcomes of the probes that are over.
+    outcome: Outcome,
+    /// Sending end of the `watch` (spmc) channel to signal the signal handling task to terminate,
+    /// if the Monitor decides to quit on its own (e.g., once all rounds are over).
     quit: watch::Sender<bool>,
     /// Receiving end of a `mpsc` channel to be notified by the signal handling task to gracefully
     /// terminate upon signal retrieval (only for SIGINT, SIGTERM and SIGQUIT, for now).
     sqrx: mpsc::Receiver<()>,
-    /// Ticks on configured periods of time to initiate new rounds of measuring and exporting.
-    ticker: Interval,
+    /// Receiving end of a `mpsc` channel to receive the configuration reparsed by the signal
+    /// handling task upon SIGHUP.
+    reload_rx: mpsc::Receiver<Config>,
     /// The `JoinHandle` for the signal handling task.
     sighandler_handle: JoinHandle<()>,
-    /// The `JoinHandle`s for all other actors (apart from the signal handling task).
-    exporter_handles: Vec<JoinHandle<()>>,
+    /// Runs the Database and the exporters, restarting any of them that dies.
+    supervisor: Supervisor,
+}
+
+/// Configuration for how the Monitor shuts down upon a signal to terminate.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub(crate) struct ShutdownConfig {
+    /// What to do with rounds in progress.
+    ///
+    /// Currently supported policies:
+    /// - Let them finish, and be stored and exported: `"finish"` (default);
+    /// - Abort them right away: `"abort"`.
+    policy: Option<String>,
+    /// How long to wait at most for rounds in progress to finish, and for the Database and the
+    /// exporters to flush them, before exiting anyway.
+    #[serde(default, with = "humantime_serde", alias = "Grace")]
+    grace: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Policy {
+    Finish,
+    Abort,
+}
+
+impl ShutdownConfig {
+    const DEFAULT_GRACE: Duration = Duration::from_secs(60);
+
+    fn policy(&self) -> Result<Policy> {
+        match self.policy.as_deref().map(str::to_lowercase).as_deref() {
+            None | Some("finish") => Ok(Policy::Finish),
+            Some("abort") => Ok(Policy::Abort),
+            Some(unknown) => bail!("unsupported shutdown policy: '{}'", unknown),
+        }
+    }
+
+    fn grace(&self) -> Duration {
+        self.grace.unwrap_or(Self::DEFAULT_GRACE)
+    }
+}
+
+/// A spawned `Probe`.
+struct RunningProbe {
+    name: String,
+    /// Sending end of the `watch` (spmc) channel to signal the probe to gracefully terminate.
+    quit: watch::Sender<bool>,
+    task: JoinHandle<Outcome>,
 }
 
 impl Monitor {
-    const MEASUREMENTS_CHANNEL_CAPACITY: usize = 1024;
+    const RELOAD_CHANNEL_CAPACITY: usize = 1;
 
     #[tracing::instrument(skip(config))]
-    pub(crate) async fn new(config: Config, measurer: Box<dyn Measurer>) -> Result<Self> {
-        let ticker = time::interval(config.period);
-        let (db_tx, exp_tx, quit, exporter_handles) = Self::spawn_exporters(&config).await?;
-        let (sighandler_handle, sqrx) = Self::install_signal_handlers().await?;
+    pub(crate) async fn new(config: Config) -> Result<Self> {
+        config.shutdown_config.policy()?;
+        let mut probes = config
+            .probes()?
+            .into_iter()
+            .map(|(name, pc)| {
+                Probe::new(name.clone(), &pc, config.min_trigger_gap, config.rounds)
+                    .with_context(|| format!("failed to initialize probe '{}'", name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let registry: probe::Registry = Arc::new(RwLock::new(
+            probes
+                .iter()
+                .map(|probe| (probe.name().to_owned(), probe.handle()))
+                .collect(),
+        ));
+
+        let supervisor = Supervisor::new(&config, registry.clone()).await?;
+        let (db_tx, exporters) = supervisor.senders();
+        for probe in probes.iter_mut() {
+            probe.route(db_tx, exporters)?;
+        }
+
+        let (quit, _) = watch::channel(false);
+        let (sighandler_handle, sqrx, reload_rx) =
+            Self::install_signal_handlers(registry.clone(), quit.subscribe()).await?;
         Ok(Self {
-            //config,
-            measurer,
-            db_tx,
-            exp_tx,
+            config,
+            probes,
+            registry,
+            running: vec![],
+            outcome: Outcome::default(),
             quit,
             sqrx,
-            ticker,
+            reload_rx,
             sighandler_handle,
-            exporter_handles,
+            supervisor,
         })
     }
 
+    /// Shuts down upon a signal to terminate, according to the configured policy: rounds in
+    /// progress are either aborted right away, or let finish (and be stored and exported) within
+    /// the grace period. Another signal to terminate forces it to exit immediately.
     #[tracing::instrument(skip(self))]
-    async fn shutdown(self) -> Result<()> {
-        debug!(
-            "Signalling all {} actors to quit...",
-            self.quit.receiver_count()
-        );
-        self.quit.send(true)?;
-        self.sighandler_handle.await?;
-        self.quit.closed().await;
-        futures::future::join_all(self.exporter_handles).await;
-        debug!("All actors appear to have exited");
-        Ok(())
+    async fn terminate(mut self) -> Result<()> {
+        let shutdown_config = &self.config.shutdown_config;
+        let deadline = Instant::now() + shutdown_config.grace();
+        // The policy has been validated upon (re)loading the configuration
+        let policy = shutdown_config.policy().unwrap_or(Policy::Finish);
+        Self::stop_probes(&mut self.running, policy, deadline, &mut self.sqrx).await?;
+        self.shutdown(deadline).await
     }
 
-    #[tracing::instrument(skip(config))]
-    async fn spawn_exporters(
-        config: &Config,
-    ) -> Result<(
-        mpsc::Sender<database::SyncMessage>,
-        broadcast::Sender<Measurement>,
-        watch::Sender<bool>,
-        Vec<JoinHandle<()>>,
-    )> {
-        let mut exporter_handles = vec![];
-
-        // A watch channel to signal tasks when to quit.
-        let (quit_tx, _) = watch::channel(false);
-        // A broadcast channel to broadcast measurements to exporters.
-        let (exp_tx, _) = broadcast::channel(Self::MEASUREMENTS_CHANNEL_CAPACITY);
-        // A mpsc channel to broadcast measurements to the Database.
-        let (db_tx, db_rx) = mpsc::channel(1);
-
-        // NOTE: Now that the Database works synchronously with respect to the Monitor, it does not
-        // *have* to be modeled as an actor. FIXME?
-        if let Some(ref dc) = config.db_config {
-            debug!("Initializing Database exporter...");
-            let db = Database::new(dc.clone(), db_rx, quit_tx.subscribe())
-                .with_context(|| "failed to initialize Database exporter")?;
-            exporter_handles.push(tokio::spawn(async move { db.run().await }));
-        }
-
-        if config.stdout {
-            debug!("Initializing Standard Output exporter...");
-            let rx = exp_tx.subscribe();
-            let quit = quit_tx.subscribe();
-            exporter_handles.push(tokio::spawn(
-                async move { StdOut::new(rx, quit).run().await },
-            ));
-        }
-
-        #[cfg(any(feature = "http", feature = "twitter"))]
-        let plot_path: Option<PathBuf> = config.db_config.as_ref().map(|_c| {
-            #[cfg(feature = "plot")]
-            {
-                PathBuf::from(_c.path()).join(PLOT_FILE_NAME)
+    /// Stops the given probes according to the given policy, aborting any of them that are still
+    /// running by the given deadline; it fails if another signal to terminate arrives first.
+    async fn stop_probes(
+        running: &mut [RunningProbe],
+        policy: Policy,
+        deadline: Instant,
+        sqrx: &mut mpsc::Receiver<()>,
+    ) -> Result<()> {
+        match policy {
+            Policy::Finish => {
+                debug!("Signalling all probes to quit...");
+                for running in running.iter() {
+                    let _ = running.quit.send(true);
+                }
             }
-            #[cfg(not(feature = "plot"))]
-            {
-                PathBuf::new()
+            Policy::Abort => {
+                info!("Aborting all rounds in progress...");
+                for running in running.iter() {
+                    running.task.abort();
+                }
             }
-        });
+        }
+
+        let tasks = running.iter_mut().map(|r| &mut r.task);
+        tokio::select! {
+            _ = futures::future::join_all(tasks) => {}
+            _ = time::sleep_until(deadline) => {
+                warn!("Aborting rounds still in progress, as the grace period is over");
+                for running in running.iter() {
+                    running.task.abort();
+                }
+            }
+            Some(()) = sqrx.recv() => {
+                bail!("forced to exit before all rounds in progress were over");
+            }
+        }
+        Ok(())
+    }
 
-        #[cfg(feature = "http")]
-        if let Some(ref hc) = config.http_config {
-            debug!("Initializing HTTP exporter...");
-            let http = Http::new(
-                hc,
-                plot_path.as_ref(),
-                config.period,
-                exp_tx.subscribe(),
-                quit_tx.subscribe(),
-            )
-            .with_context(|| "failed to initialize HTTP exporter")?;
-            exporter_handles.push(tokio::spawn(async move { http.run().await }));
-        }
-
-        #[cfg(feature = "twitter")]
-        if let Some(ref tc) = config.twitter_config {
-            debug!("Initializing Twitter exporter...");
-            let twitter = Twitter::new(
-                tc,
-                plot_path.as_ref(),
-                exp_tx.subscribe(),
-                quit_tx.subscribe(),
-            )
-            .await
-            .with_context(|| "failed to initialize Twitter exporter")?;
-            exporter_handles.push(tokio::spawn(async move { twitter.run().await }));
-        }
-
-        Ok((db_tx, exp_tx, quit_tx, exporter_handles))
-    }
-
-    #[tracing::instrument]
-    async fn install_signal_handlers() -> Result<(JoinHandle<()>, mpsc::Receiver<()>)> {
+    /// Signals all actors to gracefully terminate, and waits for them to until the given deadline.
+    /// Another signal to terminate forces it to exit immediately.
+    #[tracing::instrument(skip(self))]
+    async fn shutdown(mut self, deadline: Instant) -> Result<()> {
+        tokio::select! {
+            _ = self.supervisor.shutdown(deadline) => {}
+            Some(()) = self.sqrx.recv() => {
+                bail!("forced to exit before all actors were over");
+            }
+        }
+        debug!("Signalling the signal handling task to quit...");
+        // The signal handling task may already be over
+        let _ = self.quit.send(true);
+        self.sighandler_handle.await?;
+        debug!("All actors appear to have exited");
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(probes, quit))]
+    async fn install_signal_handlers(
+        probes: probe::Registry,
+        mut quit: watch::Receiver<bool>,
+    ) -> Result<(JoinHandle<()>, mpsc::Receiver<()>, mpsc::Receiver<Config>)> {
         let mut sigint = signal(SignalKind::interrupt())?;
         let mut sigterm = signal(SignalKind::terminate())?;
         let mut sigquit = signal(SignalKind::quit())?;
+        let mut sigusr1 = signal(SignalKind::user_defined1())?;
+        let mut sighup = signal(SignalKind::hangup())?;
         let (sqtx, sqrx) = mpsc::channel(1);
+        let (reload_tx, reload_rx) = mpsc::channel(Self::RELOAD_CHANNEL_CAPACITY);
         let signal_handler = tokio::spawn(async move {
-            let (sigint, sigterm, sigquit) = (sigint.recv(), sigterm.recv(), sigquit.recv());
-            tokio::select! {
-                _ = sigint => {
-                    info!("Received a SIGINT");
-                },
-                _ = sigterm => {
-                    info!("Received a SIGTERM");
-                },
-                _ = sigquit => {
-                    info!("Received a SIGQUIT");
-                },
-            }
-            info!("Beginning graceful termination...");
-            sqtx.send(())
-                .await
-                .expect("failed to signal Monitor to begin graceful termination through sqtx");
+            let mut terminating = false;
+            loop {
+                tokio::select! {
+                    _ = sigint.recv() => info!("Received a SIGINT"),
+                    _ = sigterm.recv() => info!("Received a SIGTERM"),
+                    _ = sigquit.recv() => info!("Received a SIGQUIT"),
+                    // Monitor may also decide to quit on its own (e.g., once all rounds are over)
+                    _ = quit.changed() => {
+                        return;
+                    },
+                    _ = sigusr1.recv() => {
+                        info!("Received a SIGUSR1; triggering an on-demand round of all probes");
+                        Self::trigger_all(&probes);
+                        continue;
+                    },
+                    _ = sighup.recv() => {
+                        info!("Received a SIGHUP; reloading the configuration");
+                        match Config::parse() {
+                            // If the channel is full, the Monitor is about to reload anyway;
+                            // subsequent SIGHUPs will pick up any later changes
+                            Ok(config) => if let Err(e) = reload_tx.try_send(config) {
+                                debug!("Failed to forward the reloaded configuration: {}", e);
+                            },
+                            Err(e) => error!("Failed to reload the configuration: {:#}", e),
+                        }
+                        continue;
+                    },
+                }
+                // Keep listening, as another signal to terminate forces the Monitor to exit
+                match terminating {
+                    false => info!("Beginning graceful termination..."),
+                    true => warn!("Exiting immediately, without waiting for graceful termination"),
+                }
+                terminating = true;
+                if sqtx.send(()).await.is_err() {
+                    return;
+                }
+            }
         });
-        Ok((signal_handler, sqrx))
+        Ok((signal_handler, sqrx, reload_rx))
+    }
+
+    /// Triggers an on-demand round of all registered probes, without waiting for them.
+    fn trigger_all(probes: &probe::Registry) {
+        let triggers: Vec<_> = match probes.read() {
+            Ok(probes) => probes.values().map(|h| h.trigger.clone()).collect(),
+            Err(e) => {
+                error!("Failed to acquire lock for probe registry: {}", e);
+                return;
+            }
+        };
+        for trigger in triggers.iter() {
+            // If the channel is full, a round is pending anyway
+            if let Err(e) = trigger.try_send(Trigger::new(None)) {
+                debug!("Failed to trigger an on-demand round: {}", e);
+            }
+        }
     }
 
+    /// Applies a reloaded configuration, restarting only the exporters whose configuration
+    /// changed, starting or stopping probes that were added or removed, and letting the rest of
+    /// the probes recreate only those of their components whose configuration changed. Errors are
+    /// reported, leaving the affected actors as they were (or stopped, if they failed to restart).
+    #[tracing::instrument(skip(self, config))]
+    async fn reload(&mut self, config: Config) {
+        let probe_configs = match config
+            .shutdown_config
+            .policy()
+            .and_then(|_| config.probes())
+        {
+            Ok(probe_configs) => probe_configs,
+            Err(e) => {
+                error!("Failed to reload the configuration: {:#}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = self.supervisor.reload(&config).await {
+            error!("Failed to reload the configuration: {:#}", e);
+            return;
+        }
+        self.update_probes(probe_configs, config.min_trigger_gap);
+
+        // The number of rounds is only configured through the command line, which is unchanged
+        self.config = Config {
+            rounds: self.config.rounds,
+            ..config
+        };
+        info!("Reloaded the configuration");
+    }
+
+    /// Starts or stops probes that were added or removed, respectively, and reloads the rest of
+    /// them, routing all of them anew.
+    fn update_probes(
+        &mut self,
+        probe_configs: Vec<(String, probe::Config)>,
+        min_trigger_gap: Option<Duration>,
+    ) {
+        let (db_tx, senders) = self.supervisor.senders();
+        let mut handles = match self.registry.write() {
+            Ok(handles) => handles,
+            Err(e) => {
+                error!("Failed to acquire lock for probe registry: {}", e);
+                return;
+            }
+        };
+        let removed: Vec<_> = handles
+            .keys()
+            .filter(|name| !probe_configs.iter().any(|(n, _)| n == *name))
+            .cloned()
+            .collect();
+        for name in removed {
+            info!("Stopping probe '{}'...", name);
+            handles.remove(&name);
+            // It is reaped once over, along with the rest of the running probes
+            for running in self.running.iter().filter(|r| r.name == name) {
+                let _ = running.quit.send(true);
+            }
+        }
+        for (name, pc) in probe_configs {
+            let routes = match Routes::resolve(&name, pc.exporters.as_deref(), db_tx, senders) {
+                Ok(routes) => routes,
+                Err(e) => {
+                    error!("Failed to reload probe '{}': {:#}", name, e);
+                    continue;
+                }
+            };
+            if let Some(handle) = handles.get(&name) {
+                // Reload in the background, as the probe may be in the middle of a round
+                let (handle, name) = (handle.clone(), name.clone());
+                let reload = Reload {
+                    config: pc,
+                    min_trigger_gap,
+                    routes,
+                };
+                tokio::spawn(async move {
+                    match handle.control(Control::Reload(Box::new(reload))).await {
+                        Ok(()) => debug!("Reloaded probe '{}'", name),
+                        Err(e) => error!("Failed to reload probe '{}': {}", name, e),
+                    }
+                });
+                continue;
+            }
+            match Probe::new(name.clone(), &pc, min_trigger_gap, self.config.rounds)
+                .and_then(|mut probe| probe.route(db_tx, senders).map(|()| probe))
+            {
+                Ok(probe) => {
+                    info!("Starting probe '{}'...", name);
+                    handles.insert(name, probe.handle());
+                    self.running.push(Self::spawn_probe(probe));
+                }
+                Err(e) => error!("Failed to start probe '{}': {:#}", name, e),
+            }
+        }
+    }
+
+    fn spawn_probe(probe: Probe) -> RunningProbe {
+        let (quit, rx) = watch::channel(false);
+        RunningProbe {
+            name: probe.name().to_owned(),
+            quit,
+            task: tokio::spawn(async move { probe.run(rx).await }),
+        }
+    }
+
+    /// Keeps running without a probe whose task failed (e.g., panicked), unregistering it so that
+    /// it is started anew upon the next reload; the round it failed in counts as a failed one.
+    fn probe_failed(&mut self, running: &RunningProbe, e: JoinError) {
+        error!("Probe '{}' task failed: {}", running.name, e);
+        self.outcome.completed_rounds += 1;
+        self.outcome.failed_rounds += 1;
+        // Unless it has been stopped upon a reload, in which case it is unregistered already
+        if *running.quit.borrow() {
+            return;
+        }
+        match self.registry.write() {
+            Ok(mut handles) => {
+                handles.remove(&running.name);
+            }
+            Err(e) => error!("Failed to acquire lock for probe registry: {}", e),
+        }
+        warn!(
+            "Running without probe '{}' until the configuration is reloaded",
+            running.name
+        );
+    }
+
+    /// Runs all probes until a signal to terminate arrives or, if the number of rounds is bounded,
+    /// until they are over; in the latter case, it fails if any of them failed to measure.
     #[tracing::instrument(skip(self))]
     pub(crate) async fn run(mut self) -> Result<()> {
+        self.running = std::mem::take(&mut self.probes)
+            .into_iter()
+            .map(Self::spawn_probe)
+            .collect();
+
         loop {
+            if self.running.is_empty() && self.config.rounds.is_some() {
+                break;
+            }
+            let idle = self.running.is_empty();
+            let tasks = self.running.iter_mut().map(|r| &mut r.task);
+            let over = async {
+                match idle {
+                    true => futures::future::pending().await,
+                    false => futures::future::select_all(tasks).await,
+                }
+            };
             tokio::select! {
-                _ = self.sqrx.recv() => {
-                    return self.shutdown().await
+                _ = self.sqrx.recv() => return self.terminate().await,
+                Some(config) = self.reload_rx.recv() => self.reload(config).await,
+                event = self.supervisor.wait() => {
+                    // Acting upon it here, rather than in the (cancellable) future above, makes
+                    // sure that restarts are not interrupted halfway
+                    if self.supervisor.supervise(event).await {
+                        // Route all probes to the new Database, leaving the rest as they are
+                        match self.config.probes() {
+                            Ok(probe_configs) => {
+                                self.update_probes(probe_configs, self.config.min_trigger_gap)
+                            }
+                            Err(e) => error!("Failed to route probes to the Database: {:#}", e),
+                        }
+                    }
                 }
-                now = self.ticker.tick() => {
-                    debug!("Tick!");
-                    self.measure_and_export(now).await;
+                (outcome, index, _) = over => {
+                    let running = self.running.swap_remove(index);
+                    match outcome {
+                        Ok(outcome) => {
+                            debug!("Probe '{}' is over", running.name);
+                            self.outcome.completed_rounds += outcome.completed_rounds;
+                            self.outcome.failed_rounds += outcome.failed_rounds;
+                        }
+                        Err(e) => self.probe_failed(&running, e),
+                    }
                 }
             }
         }
+
+        // All probes are over on their own, hence the number of rounds must have been bounded
+        let Outcome {
+            completed_rounds: completed,
+            failed_rounds: failed,
+        } = self.outcome;
+        info!("All {} rounds are over; shutting down...", completed);
+        let deadline = Instant::now() + self.config.shutdown_config.grace();
+        self.shutdown(deadline).await?;
+        if failed > 0 {
+            bail!("{} out of {} rounds failed", failed, completed);
+        }
+        Ok(())
     }
+}
 
-    #[tracing::instrument(skip(self, start))]
-    async fn measure_and_export(&mut self, start: Instant) {
-        let deadline = start + self.ticker.period();
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::atomic::{AtomicBool, Ordering},
+    };
 
-        // Acquire new measurements from the Measurer
-        let latest_measurement = self.measurer.measure(deadline).await;
+    use super::*;
 
-        // First, inform (synchronously) the Database (which may optionally include the Plotter)
-        trace!("Sending the newest measurement to Database, synchronously");
-        let (sync_tx, mut sync_rx) = oneshot::channel();
-        if let Err(e) = self
-            .db_tx
-            .send_timeout(
-                database::SyncMessage::new(latest_measurement, sync_tx),
-                deadline.saturating_duration_since(Instant::now()),
-            )
-            .await
-        {
-            error!("Failed to send measurement to Database: {}", e);
-            sync_rx.close();
-        } else if let Err(e) = sync_rx.await {
-            error!("Failed waiting to sync with Database: {}", e);
+    const GRACE: Duration = Duration::from_secs(60);
+
+    // A probe that takes `round` to finish its round in progress, once signalled to quit.
+    fn probe(round: Duration) -> (RunningProbe, Arc<AtomicBool>) {
+        let (quit, mut rx) = watch::channel(false);
+        let finished = Arc::new(AtomicBool::new(false));
+        let task = {
+            let finished = finished.clone();
+            tokio::spawn(async move {
+                let _ = rx.changed().await;
+                time::sleep(round).await;
+                finished.store(true, Ordering::SeqCst);
+                Outcome::default()
+            })
+        };
+        let running = RunningProbe {
+            name: "test".to_owned(),
+            quit,
+            task,
+        };
+        (running, finished)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn finish() -> Result<()> {
+        let (running, finished) = probe(Duration::from_secs(10));
+        let (_sqtx, mut sqrx) = mpsc::channel(1);
+        let start = Instant::now();
+        Monitor::stop_probes(&mut [running], Policy::Finish, start + GRACE, &mut sqrx).await?;
+        assert!(finished.load(Ordering::SeqCst));
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn abort() -> Result<()> {
+        let (running, finished) = probe(Duration::from_secs(10));
+        let (_sqtx, mut sqrx) = mpsc::channel(1);
+        let start = Instant::now();
+        Monitor::stop_probes(&mut [running], Policy::Abort, start + GRACE, &mut sqrx).await?;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        time::sleep(GRACE).await;
+        assert!(!finished.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn grace() -> Result<()> {
+        let (slow, slow_finished) = probe(GRACE * 2);
+        let (fast, fast_finished) = probe(Duration::from_secs(10));
+        let (_sqtx, mut sqrx) = mpsc::channel(1);
+        let start = Instant::now();
+        let mut running = [slow, fast];
+        Monitor::stop_probes(&mut running, Policy::Finish, start + GRACE, &mut sqrx).await?;
+        assert_eq!(start.elapsed(), GRACE);
+        assert!(fast_finished.load(Ordering::SeqCst));
+        // The round still in progress has been aborted
+        time::sleep(GRACE * 2).await;
+        assert!(!slow_finished.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forced() {
+        let (running, finished) = probe(GRACE * 2);
+        let (sqtx, mut sqrx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            time::sleep(Duration::from_secs(5)).await;
+            sqtx.send(()).await
+        });
+        let start = Instant::now();
+        let stopped =
+            Monitor::stop_probes(&mut [running], Policy::Finish, start + GRACE, &mut sqrx).await;
+        assert!(stopped.is_err());
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+        assert!(!finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn trigger_all() {
+        let config: probe::Config = serde_json::from_value(serde_json::json!({ "period": "1h" }))
+            .expect("invalid probe configuration");
+        let mut triggers = vec![];
+        let mut handles = BTreeMap::new();
+        for name in ["a", "b"].iter() {
+            let mut handle = Probe::new(name.to_string(), &config, None, None)
+                .unwrap()
+                .handle();
+            let (trigger, rx) = mpsc::channel(1);
+            handle.trigger = trigger;
+            handles.insert(name.to_string(), handle);
+            triggers.push(rx);
         }
+        let registry: probe::Registry = Arc::new(RwLock::new(handles));
 
-        // Then, inform (asynchronously) all other exporters
-        debug!(
-            "Number of active exporters-receivers: {}",
-            self.exp_tx.receiver_count()
-        );
-        match self.exp_tx.send(latest_measurement) {
-            Ok(num_recvr) => trace!("Broadcasted measurement to {} exporters", num_recvr),
-            Err(e) => error!("Failed to broadcast measurement to exporters: {}", e),
+        // Triggers do not block while a round is pending anyway
+        for _ in 0..3 {
+            Monitor::trigger_all(&registry);
+        }
+        for rx in triggers.iter_mut() {
+            assert!(rx.try_recv().is_ok());
+            assert!(rx.try_recv().is_err());
         }
     }
 }
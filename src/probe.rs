@@ -0,0 +1,861 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    num::NonZeroU64,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use tokio::{
+    sync::{broadcast, mpsc, oneshot, watch},
+    time::Instant,
+};
+use tracing::{debug, error, info, trace};
+
+use crate::{
+    exporters::database,
+    link::{self, busy, metered},
+    measure::{
+        self, librespeed_cli, plugin, validator, validator::Validator, Measurement, Measurer,
+    },
+    schedule::{self, Scheduler},
+};
+
+/// The name of the probe configured at the top level of the configuration file, which is only
+/// used if no named probes are configured.
+pub(crate) const DEFAULT_NAME: &str = "default";
+
+/// Configuration for a single `Probe`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    #[serde(flatten)]
+    pub(crate) schedule_config: schedule::Config,
+    #[serde(alias = "Measurer")]
+    pub(crate) measurer: Option<String>,
+    #[serde(rename = "librespeed", alias = "LibreSpeed")]
+    pub(crate) librespeed_config: Option<librespeed_cli::Config>,
+    #[serde(rename = "plugin", alias = "Plugin")]
+    pub(crate) plugin_config: Option<plugin::Config>,
+    #[serde(rename = "validation", alias = "Validation")]
+    pub(crate) validation_config: Option<validator::Config>,
+    #[serde(rename = "metered", alias = "Metered")]
+    pub(crate) metered_config: Option<metered::Config>,
+    #[serde(rename = "busy", alias = "Busy")]
+    pub(crate) busy_config: Option<busy::Config>,
+    /// The exporters that measurements are routed to, by name (i.e., `"database"`, or the name of
+    /// any configured exporter instance); all configured ones, if absent.
+    #[serde(alias = "Exporters")]
+    pub(crate) exporters: Option<Vec<String>>,
+}
+
+/// A request for an immediate round of measuring and exporting, outside the schedule.
+#[derive(Debug)]
+pub(crate) struct Trigger {
+    /// Where to report the outcome of the round, if the requester waits for it.
+    reply: Option<oneshot::Sender<Result<Measurement, TriggerError>>>,
+}
+
+impl Trigger {
+    pub(crate) fn new(reply: Option<oneshot::Sender<Result<Measurement, TriggerError>>>) -> Self {
+        Self { reply }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum TriggerError {
+    /// The latest round began too recently; another one may begin after the given duration.
+    TooSoon(Duration),
+    /// The round resulted in no measurement (e.g., because it was rejected by the Validator).
+    NoMeasurement,
+}
+
+impl fmt::Display for TriggerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooSoon(retry_after) => write!(
+                f,
+                "the latest round began too recently; retry in {}",
+                humantime::format_duration(*retry_after)
+            ),
+            Self::NoMeasurement => write!(f, "the round resulted in no measurement"),
+        }
+    }
+}
+
+/// A request to change how a running `Probe` works, without restarting it.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub(crate) enum Control {
+    /// Skip scheduled rounds (on-demand ones still run) until resumed.
+    Pause,
+    /// Run scheduled rounds again.
+    Resume,
+    /// Schedule rounds on the given period from now on.
+    SetPeriod(Duration),
+    /// Apply a new configuration, recreating only the components whose configuration changed.
+    Reload(Box<Reload>),
+}
+
+/// A new configuration for a running `Probe`.
+#[derive(Debug, Clone)]
+pub(crate) struct Reload {
+    pub(crate) config: Config,
+    pub(crate) min_trigger_gap: Option<Duration>,
+    pub(crate) routes: Routes,
+}
+
+/// Where the measurements of a `Probe` are routed to.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Routes {
+    /// Sending end of a `mpsc` channel to send new measurements to the Database task, if they are
+    /// routed to it.
+    db_tx: Option<mpsc::Sender<database::SyncMessage>>,
+    /// Sending ends of `broadcast` channels to send new measurements to the exporter tasks that
+    /// they are routed to.
+    exporters: Vec<broadcast::Sender<Measurement>>,
+}
+
+impl Routes {
+    /// Resolves the routes configured for the named probe (all of them, if absent) among the
+    /// Database and exporters that are available (i.e., configured).
+    pub(crate) fn resolve(
+        name: &str,
+        routes: Option<&[String]>,
+        db_tx: Option<&mpsc::Sender<database::SyncMessage>>,
+        exporters: &BTreeMap<String, broadcast::Sender<Measurement>>,
+    ) -> Result<Self> {
+        let routes = match routes {
+            Some(routes) => routes,
+            None => {
+                return Ok(Self {
+                    db_tx: db_tx.cloned(),
+                    exporters: exporters.values().cloned().collect(),
+                })
+            }
+        };
+        let mut ret = Self::default();
+        for route in routes.iter() {
+            match (route.as_str(), exporters.get(route.as_str())) {
+                ("database", _) => match db_tx {
+                    Some(db_tx) => ret.db_tx = Some(db_tx.clone()),
+                    None => bail!("probe '{}' is routed to the unconfigured Database", name),
+                },
+                (_, Some(exporter)) => ret.exporters.push(exporter.clone()),
+                (route, None) => bail!(
+                    "probe '{}' is routed to unknown or unconfigured exporter '{}'",
+                    name,
+                    route
+                ),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ControlError {
+    /// The request cannot be applied to the `Probe` (e.g., a period to a cron schedule).
+    Invalid(String),
+    /// The `Probe` is no longer running.
+    Unavailable,
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(reason) => write!(f, "{}", reason),
+            Self::Unavailable => write!(f, "the probe is unavailable"),
+        }
+    }
+}
+
+/// The `Handle`s of all running `Probe`s, by name, kept up to date as they are added or removed
+/// (e.g., upon reloading the configuration).
+pub(crate) type Registry = Arc<RwLock<BTreeMap<String, Handle>>>;
+
+/// What other actors need to interact with a running `Probe`.
+#[derive(Debug, Clone)]
+pub(crate) struct Handle {
+    /// The state of the `Probe`'s `Scheduler`.
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub(crate) status: Arc<Mutex<schedule::Status>>,
+    /// Sending end of a `mpsc` channel to ask the `Probe` for immediate rounds.
+    pub(crate) trigger: mpsc::Sender<Trigger>,
+    /// Sending end of a `mpsc` channel to change how the `Probe` works.
+    control: mpsc::Sender<(Control, oneshot::Sender<Result<()>>)>,
+}
+
+impl Handle {
+    /// Asks the `Probe` to apply the given change, and waits for it to be applied.
+    pub(crate) async fn control(&self, control: Control) -> Result<(), ControlError> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.control
+            .send((control, reply_tx))
+            .await
+            .map_err(|_| ControlError::Unavailable)?;
+        match reply_rx.await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(ControlError::Invalid(format!("{:#}", e))),
+            Err(_) => Err(ControlError::Unavailable),
+        }
+    }
+}
+
+/// The outcome of all scheduled rounds run by a `Probe`.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Outcome {
+    /// The number of scheduled rounds that have been run, except for skipped ones.
+    pub(crate) completed_rounds: u64,
+    /// The number of scheduled rounds that failed to measure, or whose measurements were
+    /// rejected.
+    pub(crate) failed_rounds: u64,
+}
+
+/// How a scheduled round fares against a busy link.
+#[derive(Debug)]
+enum Deferral {
+    /// Measure, noting why (and for how long) the round was deferred, if it was.
+    Measure(Option<String>),
+    /// Skip the round, since the link remained busy for as long as it could be deferred.
+    Skip(String),
+    /// Quit, since signalled to while waiting for the link to become idle.
+    Quit,
+}
+
+/// A named task that takes measurements using its own `Measurer` on its own schedule, and routes
+/// them to the (shared) Database and exporters.
+pub(crate) struct Probe {
+    name: String,
+    /// The configuration that the components of the `Probe` have been created out of.
+    config: Config,
+    /// An implementation of a `Measurer`, which provides the `Probe` with `Measurement`s to
+    /// propagate them to other actors (e.g., the Database, the exporters).
+    measurer: Box<dyn Measurer>,
+    /// An optional stage between the `Measurer` and the Database, to mark or reject measurements
+    /// that fail validation.
+    validator: Option<Validator>,
+    /// An optional check before each round, to skip it (or to measure using a lightweight
+    /// `Measurer`) while the link is metered.
+    metered: Option<metered::Detector>,
+    /// An optional check before each scheduled round, to defer it while the link is busy.
+    busy: Option<busy::Detector>,
+    /// Ticks on configured periods of time or cron schedules, to initiate new rounds of measuring
+    /// and exporting.
+    scheduler: Scheduler,
+    /// Where measurements are routed to.
+    routes: Routes,
+    /// Sending end of the `trigger_rx` channel, to be handed out to other actors.
+    trigger_tx: mpsc::Sender<Trigger>,
+    /// Receiving end of a `mpsc` channel to be asked (by the HTTP exporter or the signal handling
+    /// task, upon SIGUSR1) to initiate immediate rounds of measuring and exporting.
+    trigger_rx: mpsc::Receiver<Trigger>,
+    /// Sending end of the `control_rx` channel, to be handed out to other actors.
+    control_tx: mpsc::Sender<(Control, oneshot::Sender<Result<()>>)>,
+    /// Receiving end of a `mpsc` channel to be asked (by the HTTP exporter or the Monitor) to
+    /// change how the `Probe` works, replying with whether the change has been applied.
+    control_rx: mpsc::Receiver<(Control, oneshot::Sender<Result<()>>)>,
+    /// The minimum time since the beginning of the latest round for an on-demand one to begin.
+    min_trigger_gap: Option<Duration>,
+    /// When the latest round of measuring began.
+    last_round: Option<Instant>,
+    /// The number of scheduled rounds to run before exiting; unbounded if absent.
+    rounds: Option<NonZeroU64>,
+    outcome: Outcome,
+}
+
+impl Probe {
+    const TRIGGER_CHANNEL_CAPACITY: usize = 16;
+    const CONTROL_CHANNEL_CAPACITY: usize = 16;
+
+    #[tracing::instrument(skip(config))]
+    pub(crate) fn new(
+        name: String,
+        config: &Config,
+        min_trigger_gap: Option<Duration>,
+        rounds: Option<NonZeroU64>,
+    ) -> Result<Self> {
+        trace!("Creating new '{}'", std::any::type_name::<Self>());
+        let measurer = Self::new_measurer(config)?;
+        let scheduler = Scheduler::new(&config.schedule_config)
+            .with_context(|| "failed to initialize Scheduler")?;
+        let validator = Self::new_validator(config)?;
+        let metered = Self::new_metered(config)?;
+        let busy = Self::new_busy(config)?;
+        let (trigger_tx, trigger_rx) = mpsc::channel(Self::TRIGGER_CHANNEL_CAPACITY);
+        let (control_tx, control_rx) = mpsc::channel(Self::CONTROL_CHANNEL_CAPACITY);
+        Ok(Self {
+            name,
+            config: config.clone(),
+            measurer,
+            validator,
+            metered,
+            busy,
+            scheduler,
+            routes: Default::default(),
+            trigger_tx,
+            trigger_rx,
+            control_tx,
+            control_rx,
+            min_trigger_gap,
+            last_round: None,
+            rounds,
+            outcome: Default::default(),
+        })
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn handle(&self) -> Handle {
+        Handle {
+            status: self.scheduler.status(),
+            trigger: self.trigger_tx.clone(),
+            control: self.control_tx.clone(),
+        }
+    }
+
+    /// Connects the `Probe` to the Database and exporters that its measurements are routed to,
+    /// among the ones that are available (i.e., configured).
+    pub(crate) fn route(
+        &mut self,
+        db_tx: Option<&mpsc::Sender<database::SyncMessage>>,
+        exporters: &BTreeMap<String, broadcast::Sender<Measurement>>,
+    ) -> Result<()> {
+        self.routes = Routes::resolve(
+            &self.name,
+            self.config.exporters.as_deref(),
+            db_tx,
+            exporters,
+        )?;
+        Ok(())
+    }
+
+    fn new_measurer(config: &Config) -> Result<Box<dyn Measurer>> {
+        measure::new_measurer(config.measurer.as_deref(), config)
+    }
+
+    fn new_validator(config: &Config) -> Result<Option<Validator>> {
+        config
+            .validation_config
+            .clone()
+            .map(Validator::new)
+            .transpose()
+            .with_context(|| "failed to initialize Validator")
+    }
+
+    fn new_metered(config: &Config) -> Result<Option<metered::Detector>> {
+        let mc = match config.metered_config {
+            Some(ref mc) => mc,
+            None => return Ok(None),
+        };
+        let fallback = mc
+            .measurer()
+            .map(|kind| measure::new_measurer(Some(kind), config))
+            .transpose()?;
+        metered::Detector::new(mc.clone(), fallback)
+            .map(Some)
+            .with_context(|| "failed to initialize metered link Detector")
+    }
+
+    fn new_busy(config: &Config) -> Result<Option<busy::Detector>> {
+        config
+            .busy_config
+            .clone()
+            .map(busy::Detector::new)
+            .transpose()
+            .with_context(|| "failed to initialize busy link Detector")
+    }
+
+    /// Runs rounds of measuring and exporting until a signal to quit arrives or, if the number of
+    /// rounds is bounded, until they are over.
+    #[tracing::instrument(skip(self, quit), fields(probe = %self.name))]
+    pub(crate) async fn run(mut self, mut quit: watch::Receiver<bool>) -> Outcome {
+        loop {
+            tokio::select! {
+                _ = quit.changed() => {
+                    info!("Received signal to gracefully shut down");
+                    break;
+                }
+                now = self.scheduler.tick() => {
+                    debug!("Tick!");
+                    if self.scheduler.is_paused() {
+                        debug!("Skipping round while paused");
+                        continue;
+                    }
+                    let deadline = self.scheduler.deadline(now);
+                    let outcome = match self.scheduler.blackout_until() {
+                        Some(end) => {
+                            info!("Skipping measurement during blackout window (until {})", end);
+                            let skipped = self.skipped("blackout".to_owned());
+                            self.export(skipped.clone(), deadline).await;
+                            Some(skipped)
+                        }
+                        None => match self.defer_while_busy(deadline, &mut quit).await {
+                            Deferral::Measure(deferred) => {
+                                self.measure_and_export(Instant::now(), deadline, deferred).await
+                            }
+                            Deferral::Skip(reason) => {
+                                let skipped = self.skipped(reason);
+                                self.export(skipped.clone(), deadline).await;
+                                Some(skipped)
+                            }
+                            Deferral::Quit => {
+                                info!("Received signal to gracefully shut down");
+                                break;
+                            }
+                        },
+                    };
+                    self.record(outcome.as_ref());
+                    if self.count_round(outcome.as_ref()) {
+                        info!("All {} rounds are over", self.outcome.completed_rounds);
+                        break;
+                    }
+                }
+                Some(trigger) = self.trigger_rx.recv() => {
+                    debug!("Triggered!");
+                    self.on_demand(trigger).await;
+                }
+                Some((control, reply)) = self.control_rx.recv() => {
+                    // The requester may have given up waiting; nothing to do about it
+                    let _ = reply.send(self.control(control));
+                }
+            }
+        }
+        self.outcome
+    }
+
+    /// Applies the given change to how rounds are scheduled.
+    #[tracing::instrument(skip(self))]
+    fn control(&mut self, control: Control) -> Result<()> {
+        match control {
+            Control::Pause => {
+                info!("Pausing scheduled rounds");
+                self.scheduler.set_paused(true);
+            }
+            Control::Resume => {
+                info!("Resuming scheduled rounds");
+                self.scheduler.set_paused(false);
+            }
+            Control::SetPeriod(period) => self.scheduler.set_period(period)?,
+            Control::Reload(reload) => self.reload(*reload)?,
+        }
+        Ok(())
+    }
+
+    /// Applies a new configuration, recreating only the components whose configuration changed;
+    /// if any of them fails to be recreated, the `Probe` is left unchanged.
+    #[tracing::instrument(skip(self, reload))]
+    fn reload(&mut self, reload: Reload) -> Result<()> {
+        let (old, new) = (&self.config, &reload.config);
+        let measurer_changed = old.measurer != new.measurer
+            || old.librespeed_config != new.librespeed_config
+            || old.plugin_config != new.plugin_config;
+        let measurer = match measurer_changed {
+            true => Some(Self::new_measurer(new)?),
+            false => None,
+        };
+        let validator = match old.validation_config != new.validation_config {
+            true => Some(Self::new_validator(new)?),
+            false => None,
+        };
+        // The fallback Measurer is configured through the same sections as the main one
+        let metered = match measurer_changed || old.metered_config != new.metered_config {
+            true => Some(Self::new_metered(new)?),
+            false => None,
+        };
+        let busy = match old.busy_config != new.busy_config {
+            true => Some(Self::new_busy(new)?),
+            false => None,
+        };
+        if old.schedule_config != new.schedule_config {
+            self.scheduler
+                .reconfigure(&new.schedule_config)
+                .with_context(|| "failed to reconfigure Scheduler")?;
+            info!("Reconfigured the Scheduler");
+        }
+
+        if let Some(measurer) = measurer {
+            info!("Recreated the Measurer: {:?}", measurer);
+            self.measurer = measurer;
+        }
+        if let Some(validator) = validator {
+            info!("Recreated the Validator");
+            self.validator = validator;
+        }
+        if let Some(metered) = metered {
+            info!("Recreated the metered link Detector");
+            self.metered = metered;
+        }
+        if let Some(busy) = busy {
+            info!("Recreated the busy link Detector");
+            self.busy = busy;
+        }
+        self.min_trigger_gap = reload.min_trigger_gap;
+        self.routes = reload.routes;
+        self.config = reload.config;
+        Ok(())
+    }
+
+    /// Publishes the outcome of the latest round (whether scheduled or on-demand).
+    fn record(&self, outcome: Option<&Measurement>) {
+        let outcome = match outcome {
+            None => "rejected".to_owned(),
+            Some(m) => match (&m.skipped, &m.suspect) {
+                (Some(reason), _) => format!("skipped: {}", reason),
+                _ if m.is_failed() => "failed".to_owned(),
+                (None, Some(reason)) => format!("suspect: {}", reason),
+                (None, None) => "measured".to_owned(),
+            },
+        };
+        self.scheduler.record(outcome);
+    }
+
+    /// Keeps track of the outcome of the latest scheduled round, returning whether it was the
+    /// last one to run; skipped rounds (e.g., during blackout windows) do not count, so that a
+    /// bounded number of rounds are all actually measured.
+    fn count_round(&mut self, outcome: Option<&Measurement>) -> bool {
+        match outcome {
+            Some(m) if m.skipped.is_some() => return false,
+            Some(m) if !m.is_failed() => {}
+            _ => self.outcome.failed_rounds += 1,
+        }
+        self.outcome.completed_rounds += 1;
+        matches!(self.rounds, Some(rounds) if self.outcome.completed_rounds >= rounds.get())
+    }
+
+    /// Runs an immediate round on behalf of the given trigger, coalescing it with any other
+    /// pending ones, unless the latest round began too recently.
+    #[tracing::instrument(skip(self))]
+    async fn on_demand(&mut self, trigger: Trigger) {
+        let mut triggers = vec![trigger];
+        while let Ok(trigger) = self.trigger_rx.try_recv() {
+            triggers.push(trigger);
+        }
+        debug!("Coalesced {} pending trigger(s)", triggers.len());
+
+        let now = Instant::now();
+        let since_last_round = self
+            .last_round
+            .map(|last| now.saturating_duration_since(last));
+        let outcome = match (self.min_trigger_gap, since_last_round) {
+            (Some(gap), Some(since)) if since < gap => {
+                let err = TriggerError::TooSoon(gap - since);
+                info!("Ignoring on-demand round: {}", err);
+                Err(err)
+            }
+            _ => {
+                info!("Beginning on-demand round");
+                let deadline = self.scheduler.on_demand_deadline(now);
+                let outcome = self.measure_and_export(now, deadline, None).await;
+                self.record(outcome.as_ref());
+                outcome.ok_or(TriggerError::NoMeasurement)
+            }
+        };
+
+        for reply in triggers.into_iter().filter_map(|trigger| trigger.reply) {
+            // The requester may have given up waiting; nothing to do about it
+            let _ = reply.send(outcome.clone());
+        }
+    }
+
+    /// Waits while the link is busy (if configured to check), for up to the maximum deferral and
+    /// no later than the given deadline.
+    #[tracing::instrument(skip(self, quit))]
+    async fn defer_while_busy(
+        &self,
+        deadline: Instant,
+        quit: &mut watch::Receiver<bool>,
+    ) -> Deferral {
+        let busy = match self.busy {
+            Some(ref busy) => busy,
+            None => return Deferral::Measure(None),
+        };
+        let start = Instant::now();
+        let give_up = deadline.min(start + busy.max_deferral());
+        let mut deferred = None;
+        while let Some(reason) = busy.check().await {
+            let retry = busy.retry();
+            if Instant::now() + retry > give_up {
+                info!("Link is still busy ({}); skipping measurement", reason);
+                return Deferral::Skip(format!("busy link: {}", reason));
+            }
+            info!(
+                "Link is busy ({}); deferring measurement by {}",
+                reason,
+                humantime::format_duration(retry)
+            );
+            tokio::select! {
+                _ = quit.changed() => return Deferral::Quit,
+                _ = tokio::time::sleep(retry) => {}
+            }
+            deferred = Some(reason);
+        }
+        Deferral::Measure(deferred.map(|reason| {
+            let deferred_for = Duration::from_secs(start.elapsed().as_secs());
+            format!(
+                "busy link for {}: {}",
+                humantime::format_duration(deferred_for),
+                reason
+            )
+        }))
+    }
+
+    fn skipped(&self, reason: String) -> Measurement {
+        Measurement {
+            probe: Some(self.name.clone()),
+            ..Measurement::skipped(reason)
+        }
+    }
+
+    /// Runs a round of measuring and exporting, returning the resulting measurement (if any).
+    #[tracing::instrument(skip(self, start))]
+    async fn measure_and_export(
+        &mut self,
+        start: Instant,
+        deadline: Instant,
+        deferred: Option<String>,
+    ) -> Option<Measurement> {
+        self.last_round = Some(start);
+
+        // On a metered link, either skip this round or measure using a lightweight Measurer
+        let metered = match self.metered {
+            Some(ref detector) => detector.check().await,
+            None => None,
+        };
+        let fallback = self
+            .metered
+            .as_mut()
+            .and_then(metered::Detector::fallback_measurer);
+        let measurer = match (metered.as_ref(), fallback) {
+            (None, _) => &mut self.measurer,
+            (Some(reason), Some(fallback)) => {
+                info!(
+                    "Link is metered ({}); measuring with {:?}",
+                    reason, fallback
+                );
+                fallback
+            }
+            (Some(reason), None) => {
+                info!("Link is metered ({}); skipping measurement", reason);
+                let skipped = self.skipped(format!("metered link: {}", reason));
+                self.export(skipped.clone(), deadline).await;
+                return Some(skipped);
+            }
+        };
+
+        // Acquire new measurements from the Measurer
+        let kind = measurer.kind();
+        let began = Instant::now();
+        let mut latest_measurement = measurer.measure(deadline).await;
+        latest_measurement.duration = Some(began.elapsed());
+        latest_measurement.measurer = Some(kind.to_owned());
+        latest_measurement.interface = match link::default_route().await {
+            Ok(route) => route.map(|route| route.interface),
+            Err(e) => {
+                debug!("Failed to look up the default route: {:#}", e);
+                None
+            }
+        };
+        latest_measurement.probe = Some(self.name.clone());
+        latest_measurement.downgraded = metered.map(|reason| format!("metered link: {}", reason));
+        latest_measurement.deferred = deferred;
+
+        // Mark or reject the new measurements, if they fail validation (unless measured by a
+        // different Measurer, whose measurements are not comparable to the recent history)
+        let latest_measurement = match self.validator {
+            Some(ref mut validator) if latest_measurement.downgraded.is_none() => {
+                match validator.validate(latest_measurement) {
+                    Some(measurement) => measurement,
+                    None => return None,
+                }
+            }
+            _ => latest_measurement,
+        };
+
+        // Adapt the period to how the link is doing (unless measured by a different Measurer, or
+        // the measurements are suspect)
+        if latest_measurement.downgraded.is_none() && latest_measurement.suspect.is_none() {
+            self.scheduler.observe(&latest_measurement);
+        }
+
+        self.export(latest_measurement.clone(), deadline).await;
+        Some(latest_measurement)
+    }
+
+    /// Hands the given measurement (or skipped round) over to the Database and all exporters.
+    async fn export(&self, measurement: Measurement, deadline: Instant) {
+        // First, inform (synchronously) the Database (which may optionally include the Plotter)
+        self.store(measurement.clone(), deadline).await;
+
+        // Then, inform (asynchronously) all other exporters
+        debug!(
+            "Number of exporters routed to: {}",
+            self.routes.exporters.len()
+        );
+        for exporter in self.routes.exporters.iter() {
+            match exporter.send(measurement.clone()) {
+                Ok(num_recvr) => trace!("Broadcasted measurement to {} receivers", num_recvr),
+                Err(e) => error!("Failed to broadcast measurement to exporter: {}", e),
+            }
+        }
+    }
+
+    /// Sends the given measurement to the Database (if it is routed to it) and waits for it to be
+    /// stored (and, optionally, plotted), unless the deadline expires first.
+    #[tracing::instrument(skip(self))]
+    async fn store(&self, measurement: Measurement, deadline: Instant) {
+        let db_tx = match self.routes.db_tx {
+            Some(ref db_tx) => db_tx,
+            None => return,
+        };
+        trace!("Sending the newest measurement to Database, synchronously");
+        let (sync_tx, mut sync_rx) = oneshot::channel();
+        if let Err(e) = db_tx
+            .send_timeout(
+                database::SyncMessage::new(measurement, sync_tx),
+                deadline.saturating_duration_since(Instant::now()),
+            )
+            .await
+        {
+            error!("Failed to send measurement to Database: {}", e);
+            sync_rx.close();
+        } else if let Err(e) = sync_rx.await {
+            error!("Failed waiting to sync with Database: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+    use async_trait::async_trait;
+    use serde_json::json;
+
+    use super::*;
+
+    /// A `Measurer` that takes a while to measure, counting its rounds.
+    #[derive(Debug)]
+    struct Slow(Arc<AtomicU64>);
+
+    #[async_trait]
+    impl Measurer for Slow {
+        async fn measure(&mut self, _: Instant) -> Measurement {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            self.0.fetch_add(1, Ordering::SeqCst);
+            (10., 90., 9.).into()
+        }
+
+        fn kind(&self) -> &'static str {
+            "slow"
+        }
+    }
+
+    fn probe(config: serde_json::Value, rounds: Option<u64>) -> Probe {
+        let config: Config = serde_json::from_value(config).unwrap();
+        Probe::new(
+            "test".to_owned(),
+            &config,
+            None,
+            rounds.and_then(NonZeroU64::new),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn count_round() {
+        let mut p = probe(json!({ "period": "1m" }), Some(2));
+        let skipped = Measurement::skipped("blackout".to_owned());
+        assert!(!p.count_round(Some(&skipped)));
+        assert!(!p.count_round(Some(&skipped)));
+        assert!(!p.count_round(None));
+        assert!(p.count_round(Some(&(10., 90., 9.).into())));
+        assert_eq!(p.outcome.completed_rounds, 2);
+        assert_eq!(p.outcome.failed_rounds, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn on_demand() {
+        let mut p = probe(json!({ "period": "1h" }), None);
+        let rounds = Arc::new(AtomicU64::new(0));
+        p.measurer = Box::new(Slow(rounds.clone()));
+        let handle = p.handle();
+        let (quit, rx) = watch::channel(false);
+        let task = tokio::spawn(p.run(rx));
+
+        // Trigger several rounds while the first scheduled round is in progress
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        let mut replies = vec![];
+        for _ in 0..3 {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            handle
+                .trigger
+                .send(Trigger::new(Some(reply_tx)))
+                .await
+                .unwrap();
+            replies.push(reply_rx);
+        }
+        for reply in replies {
+            let measurement = reply.await.unwrap().unwrap();
+            assert_eq!(measurement.probe.as_deref(), Some("test"));
+        }
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(rounds.load(Ordering::SeqCst), 2);
+
+        quit.send(true).unwrap();
+        let outcome = task.await.unwrap();
+        assert_eq!(outcome.completed_rounds, 1);
+    }
+
+    #[tokio::test]
+    async fn busy_skip() {
+        // Keep the loopback interface busy, for as long as the test runs
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = socket.local_addr().unwrap();
+        let flooding = Arc::new(AtomicBool::new(true));
+        let flood = {
+            let flooding = flooding.clone();
+            std::thread::spawn(move || {
+                while flooding.load(Ordering::SeqCst) {
+                    let _ = socket.send_to(&[0; 1024], target);
+                }
+            })
+        };
+
+        let mut p = probe(
+            json!({
+                "period": "1h",
+                "busy": {
+                    "interface": "lo",
+                    "threshold": 0.001,
+                    "sample_window": "100ms",
+                    "retry": "1s",
+                    "max_deferral": "1ms",
+                },
+            }),
+            None,
+        );
+        let rounds = Arc::new(AtomicU64::new(0));
+        p.measurer = Box::new(Slow(rounds.clone()));
+        let (tx, mut exported) = broadcast::channel(1);
+        p.routes.exporters.push(tx);
+        let (quit, rx) = watch::channel(false);
+        let task = tokio::spawn(p.run(rx));
+
+        // The first scheduled round is skipped rather than measured, but still exported
+        let measurement = exported.recv().await.unwrap();
+        let reason = measurement.skipped.unwrap();
+        assert!(reason.starts_with("busy link: 'lo' carries"), "{}", reason);
+        assert_eq!(measurement.probe.as_deref(), Some("test"));
+        assert_eq!(rounds.load(Ordering::SeqCst), 0);
+
+        quit.send(true).unwrap();
+        let outcome = task.await.unwrap();
+        assert_eq!(outcome.completed_rounds, 0);
+        flooding.store(false, Ordering::SeqCst);
+        flood.join().unwrap();
+    }
+}
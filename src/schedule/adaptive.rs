@@ -0,0 +1,279 @@
+use std::{collections::VecDeque, time::Duration};
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use tracing::{debug, info, trace};
+
+use crate::measure::{median, Measurement};
+
+/// Configuration for adapting the period between rounds to the outcome of recent ones.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The shortest period that rounds may be scheduled on.
+    #[serde(with = "humantime_serde")]
+    floor: Duration,
+    /// A round is degraded if its download or upload speed is below this fraction of the
+    /// baseline.
+    speed_ratio: Option<f64>,
+    /// A round is degraded if its ping latency is above this multiple of the baseline.
+    latency_ratio: Option<f64>,
+    /// The factor (in `[0, 1)`) that the period is multiplied by after a degraded or failed round.
+    shrink_factor: Option<f64>,
+    /// The factor (greater than 1) that the period is multiplied by after `recover_after`
+    /// consecutive healthy rounds, until it is back to the configured one.
+    grow_factor: Option<f64>,
+    /// The number of consecutive healthy rounds required to lengthen the period.
+    recover_after: Option<usize>,
+    /// The number of recent healthy rounds that the baseline is the median of.
+    baseline_size: Option<usize>,
+    /// The minimum number of recent healthy rounds required to judge rounds against the baseline;
+    /// failed rounds are always considered degraded.
+    min_baseline: Option<usize>,
+}
+
+/// Shortens the period between rounds down to a floor while results are degraded (or missing),
+/// and lengthens it back to the configured one once they recover.
+#[derive(Debug)]
+pub(crate) struct Adaptive {
+    floor: Duration,
+    configured: Duration,
+    period: Duration,
+    speed_ratio: f64,
+    latency_ratio: f64,
+    shrink_factor: f64,
+    grow_factor: f64,
+    recover_after: usize,
+    baseline_size: usize,
+    min_baseline: usize,
+    /// Ping latency, download and upload speed of recent healthy rounds.
+    baseline: VecDeque<(f64, f64, f64)>,
+    /// The number of consecutive healthy rounds since the period was last changed.
+    healthy_streak: usize,
+}
+
+impl Adaptive {
+    const DEFAULT_SPEED_RATIO: f64 = 0.5;
+    const DEFAULT_LATENCY_RATIO: f64 = 3.;
+    const DEFAULT_SHRINK_FACTOR: f64 = 0.5;
+    const DEFAULT_GROW_FACTOR: f64 = 2.;
+    const DEFAULT_RECOVER_AFTER: usize = 3;
+    const DEFAULT_BASELINE_SIZE: usize = 20;
+    const DEFAULT_MIN_BASELINE: usize = 5;
+
+    #[tracing::instrument]
+    pub(crate) fn new(config: &Config, configured: Duration) -> Result<Self> {
+        trace!("Creating new '{}'", std::any::type_name::<Self>());
+        let ret = Self {
+            floor: config.floor,
+            configured,
+            period: configured,
+            speed_ratio: config.speed_ratio.unwrap_or(Self::DEFAULT_SPEED_RATIO),
+            latency_ratio: config.latency_ratio.unwrap_or(Self::DEFAULT_LATENCY_RATIO),
+            shrink_factor: config.shrink_factor.unwrap_or(Self::DEFAULT_SHRINK_FACTOR),
+            grow_factor: config.grow_factor.unwrap_or(Self::DEFAULT_GROW_FACTOR),
+            recover_after: config.recover_after.unwrap_or(Self::DEFAULT_RECOVER_AFTER),
+            baseline_size: config.baseline_size.unwrap_or(Self::DEFAULT_BASELINE_SIZE),
+            min_baseline: config.min_baseline.unwrap_or(Self::DEFAULT_MIN_BASELINE),
+            baseline: VecDeque::new(),
+            healthy_streak: 0,
+        };
+        if ret.floor == Duration::ZERO || ret.floor > configured {
+            bail!("the adaptive floor must be non-zero and no longer than the period");
+        }
+        if !(ret.speed_ratio > 0. && ret.speed_ratio < 1.) {
+            bail!("the adaptive speed_ratio must be in (0, 1)");
+        }
+        if !(ret.latency_ratio > 1. && ret.latency_ratio.is_finite()) {
+            bail!("the adaptive latency_ratio must be greater than 1");
+        }
+        if !(ret.shrink_factor >= 0. && ret.shrink_factor < 1.) {
+            bail!("the adaptive shrink_factor must be in [0, 1)");
+        }
+        if !(ret.grow_factor > 1. && ret.grow_factor.is_finite()) {
+            bail!("the adaptive grow_factor must be greater than 1");
+        }
+        if ret.recover_after == 0 {
+            bail!("the adaptive recover_after must be at least 1");
+        }
+        if ret.min_baseline == 0 || ret.min_baseline > ret.baseline_size {
+            bail!("the adaptive min_baseline must be in [1, baseline_size]");
+        }
+        Ok(ret)
+    }
+
+    /// The shortest period that rounds may be scheduled on.
+    pub(crate) fn floor(&self) -> Duration {
+        self.floor
+    }
+
+    /// The period that rounds are currently scheduled on.
+    pub(crate) fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Resets the period to the given one, which becomes the longest it may grow back to.
+    pub(crate) fn reconfigure(&mut self, configured: Duration) -> Result<()> {
+        if self.floor > configured {
+            bail!("the period must be no shorter than the adaptive floor");
+        }
+        self.configured = configured;
+        self.period = configured;
+        self.healthy_streak = 0;
+        Ok(())
+    }
+
+    /// Adapts the period to the outcome of the latest round, returning it if it has changed.
+    #[tracing::instrument(skip(self))]
+    pub(crate) fn observe(&mut self, measurement: &Measurement) -> Option<Duration> {
+        let previous = self.period;
+        match self.degradation(measurement) {
+            Some(reason) => {
+                self.healthy_streak = 0;
+                self.period = self.floor.max(self.period.mul_f64(self.shrink_factor));
+                if self.period != previous {
+                    info!(
+                        "Round was degraded ({}); shortening period to {}",
+                        reason,
+                        humantime::format_duration(self.period)
+                    );
+                } else {
+                    debug!("Round was degraded ({}); period remains at floor", reason);
+                }
+            }
+            None => {
+                if self.baseline.len() == self.baseline_size {
+                    self.baseline.pop_front();
+                }
+                self.baseline.push_back((
+                    measurement.ping_latency,
+                    measurement.download_speed,
+                    measurement.upload_speed,
+                ));
+                self.healthy_streak += 1;
+                if self.period < self.configured && self.healthy_streak >= self.recover_after {
+                    self.healthy_streak = 0;
+                    self.period = self.configured.min(self.period.mul_f64(self.grow_factor));
+                    info!(
+                        "Results have recovered; lengthening period to {}",
+                        humantime::format_duration(self.period)
+                    );
+                }
+            }
+        }
+        Some(self.period).filter(|period| *period != previous)
+    }
+
+    /// Returns why the given measurement is considered degraded, if it is.
+    fn degradation(&self, m: &Measurement) -> Option<String> {
+        if m.is_failed() {
+            return Some("measurement failed".to_owned());
+        }
+        if self.baseline.len() < self.min_baseline {
+            return None;
+        }
+        let baseline = |get: fn(&(f64, f64, f64)) -> f64| {
+            let mut values: Vec<f64> = self.baseline.iter().map(get).collect();
+            median(&mut values)
+        };
+
+        let mut reasons = vec![];
+        let download = baseline(|b| b.1);
+        if m.download_speed < download * self.speed_ratio {
+            reasons.push(format!(
+                "download speed {:.3} Mbps is far below baseline {:.3} Mbps",
+                m.download_speed, download
+            ));
+        }
+        let upload = baseline(|b| b.2);
+        if m.upload_speed < upload * self.speed_ratio {
+            reasons.push(format!(
+                "upload speed {:.3} Mbps is far below baseline {:.3} Mbps",
+                m.upload_speed, upload
+            ));
+        }
+        let ping = baseline(|b| b.0);
+        if m.ping_latency > ping * self.latency_ratio {
+            reasons.push(format!(
+                "ping latency {:.3} ms is far above baseline {:.3} ms",
+                m.ping_latency, ping
+            ));
+        }
+        if reasons.is_empty() {
+            None
+        } else {
+            Some(reasons.join("; "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adaptive() -> Adaptive {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "floor": "1m",
+            "recover_after": 2,
+            "min_baseline": 3,
+        }))
+        .unwrap();
+        Adaptive::new(&config, Duration::from_secs(600)).unwrap()
+    }
+
+    #[test]
+    fn shrink_and_grow() {
+        let minutes = |m: u64| Some(Duration::from_secs(60 * m));
+        let mut a = adaptive();
+        for _ in 0..3 {
+            assert_eq!(a.observe(&(10., 90., 9.).into()), None);
+        }
+        assert_eq!(a.observe(&(10., 30., 9.).into()), minutes(5));
+        assert_eq!(
+            a.observe(&(40., 90., 9.).into()),
+            Some(Duration::from_secs(150))
+        );
+        assert_eq!(
+            a.observe(&Default::default()),
+            Some(Duration::from_secs(75))
+        );
+        assert_eq!(a.observe(&Default::default()), minutes(1));
+        assert_eq!(a.observe(&Default::default()), None);
+        assert_eq!(a.period(), a.floor());
+
+        // Degraded rounds are not taken into account in the baseline
+        assert_eq!(a.baseline.len(), 3);
+
+        assert_eq!(a.observe(&(11., 91., 9.).into()), None);
+        assert_eq!(a.observe(&(11., 91., 9.).into()), minutes(2));
+        assert_eq!(a.observe(&(11., 91., 9.).into()), None);
+        assert_eq!(a.observe(&(11., 91., 9.).into()), minutes(4));
+        assert_eq!(a.observe(&(11., 91., 9.).into()), None);
+        assert_eq!(a.observe(&(11., 91., 9.).into()), minutes(8));
+        assert_eq!(a.observe(&(11., 91., 9.).into()), None);
+        assert_eq!(a.observe(&(11., 91., 9.).into()), minutes(10));
+        assert_eq!(a.observe(&(11., 91., 9.).into()), None);
+        assert_eq!(a.observe(&(11., 91., 9.).into()), None);
+    }
+
+    #[test]
+    fn invalid() {
+        let config = |floor: &str| -> Config {
+            serde_json::from_value(serde_json::json!({ "floor": floor })).unwrap()
+        };
+        assert!(Adaptive::new(&config("0s"), Duration::from_secs(600)).is_err());
+        assert!(Adaptive::new(&config("11m"), Duration::from_secs(600)).is_err());
+        assert!(Adaptive::new(&config("10m"), Duration::from_secs(600)).is_ok());
+
+        let new = |config: serde_json::Value| {
+            Adaptive::new(
+                &serde_json::from_value(config).unwrap(),
+                Duration::from_secs(600),
+            )
+        };
+        assert!(new(serde_json::json!({ "floor": "1m", "recover_after": 0 })).is_err());
+        assert!(new(serde_json::json!({ "floor": "1m", "min_baseline": 0 })).is_err());
+        assert!(new(serde_json::json!({ "floor": "1m", "min_baseline": 21 })).is_err());
+        // Healthy streaks are unrelated to the size of the baseline
+        assert!(new(serde_json::json!({ "floor": "1m", "recover_after": 21 })).is_ok());
+    }
+}
@@ -0,0 +1,187 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, NaiveTime, TimeZone, Weekday};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use tracing::trace;
+
+/// Configuration for blackout windows, during which no measurements take place.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The IANA name of the timezone that windows are specified in (e.g., `"Europe/Athens"`);
+    /// the local timezone is used if this is not specified.
+    timezone: Option<String>,
+    #[serde(default)]
+    windows: Vec<WindowConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+struct WindowConfig {
+    /// Time of day (`"HH:MM"` or `"HH:MM:SS"`) that the window begins at.
+    start: String,
+    /// Time of day (`"HH:MM"` or `"HH:MM:SS"`) that the window ends at; if it is not later than
+    /// `start`, the window ends on the following day.
+    end: String,
+    /// The days of the week (e.g., `"Mon"`, `"tuesday"`) that the window begins on; every day if
+    /// empty.
+    #[serde(default)]
+    days: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Window {
+    start: NaiveTime,
+    end: NaiveTime,
+    days: Vec<Weekday>,
+}
+
+/// A set of recurring windows of time during which measurements should not take place.
+#[derive(Debug, Clone)]
+pub(crate) struct Blackout {
+    timezone: Option<Tz>,
+    windows: Vec<Window>,
+}
+
+impl Blackout {
+    #[tracing::instrument]
+    pub(crate) fn new(config: &Config) -> Result<Self> {
+        trace!("Creating new '{}'", std::any::type_name::<Self>());
+        let timezone = config
+            .timezone
+            .as_deref()
+            .map(|tz| Tz::from_str(tz).map_err(|e| anyhow!("invalid blackout timezone: {}", e)))
+            .transpose()?;
+        let windows = config
+            .windows
+            .iter()
+            .map(|w| {
+                Ok(Window {
+                    start: parse_time(&w.start)?,
+                    end: parse_time(&w.end)?,
+                    days: w
+                        .days
+                        .iter()
+                        .map(|day| {
+                            Weekday::from_str(day)
+                                .map_err(|_| anyhow!("invalid day of the week '{}'", day))
+                        })
+                        .collect::<Result<_>>()?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| "invalid blackout window")?;
+        if windows.is_empty() {
+            bail!("at least one blackout window must be configured");
+        }
+        Ok(Self { timezone, windows })
+    }
+
+    /// If `t` falls within any blackout window, returns the end of the window that ends last.
+    pub(crate) fn window_end(&self, t: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self.timezone {
+            Some(ref tz) => self.window_end_in(tz, t),
+            None => self.window_end_in(&Local, t),
+        }
+    }
+
+    fn window_end_in<Z: TimeZone>(&self, tz: &Z, t: DateTime<Local>) -> Option<DateTime<Local>> {
+        let t = t.with_timezone(tz);
+        let today = t.naive_local().date();
+        let mut ret = None;
+        for window in self.windows.iter() {
+            // A window that began yesterday may extend into today
+            for day in today.pred_opt().iter().chain(Some(today).iter()) {
+                if !window.days.is_empty() && !window.days.contains(&day.weekday()) {
+                    continue;
+                }
+                let start = day.and_time(window.start);
+                let mut end = day.and_time(window.end);
+                if window.end <= window.start {
+                    end += chrono::Duration::days(1);
+                }
+                if let (Some(start), Some(end)) = (resolve(tz, start), resolve(tz, end)) {
+                    if start <= t && t < end {
+                        ret = ret.max(Some(end.with_timezone(&Local)));
+                    }
+                }
+            }
+        }
+        ret
+    }
+}
+
+fn parse_time(s: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M:%S"))
+        .with_context(|| format!("invalid time of day '{}'", s))
+}
+
+/// Resolves a local date and time in the given timezone; times skipped over by DST transitions
+/// are pushed an hour later, and ambiguous ones resolve to their earliest occurrence.
+fn resolve<Z: TimeZone>(tz: &Z, naive: NaiveDateTime) -> Option<DateTime<Z>> {
+    tz.from_local_datetime(&naive).earliest().or_else(|| {
+        tz.from_local_datetime(&(naive + chrono::Duration::hours(1)))
+            .earliest()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, Utc};
+
+    use super::*;
+
+    fn at(day: u32, hour: u32, min: u32) -> DateTime<Local> {
+        // 2021-09-20 was a Monday
+        let naive = NaiveDate::from_ymd_opt(2021, 9, day)
+            .and_then(|date| date.and_hms_opt(hour, min, 0))
+            .unwrap();
+        Utc.from_utc_datetime(&naive).with_timezone(&Local)
+    }
+
+    fn window(start: &str, end: &str, days: &[&str]) -> WindowConfig {
+        WindowConfig {
+            start: start.to_owned(),
+            end: end.to_owned(),
+            days: days.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn windows() -> Result<()> {
+        let b = Blackout::new(&Config {
+            timezone: Some("UTC".to_owned()),
+            windows: vec![
+                window("09:00", "17:30", &["Mon", "tuesday"]),
+                window("22:00", "06:00", &["Fri"]),
+            ],
+        })?;
+        assert_eq!(b.window_end(at(20, 8, 59)), None);
+        assert_eq!(b.window_end(at(20, 9, 0)), Some(at(20, 17, 30)));
+        assert_eq!(b.window_end(at(21, 17, 29)), Some(at(21, 17, 30)));
+        assert_eq!(b.window_end(at(21, 17, 30)), None);
+        assert_eq!(b.window_end(at(22, 12, 0)), None);
+        // Windows crossing midnight belong to the day they begin on
+        assert_eq!(b.window_end(at(24, 23, 0)), Some(at(25, 6, 0)));
+        assert_eq!(b.window_end(at(25, 5, 59)), Some(at(25, 6, 0)));
+        assert_eq!(b.window_end(at(25, 23, 0)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        let config = |timezone: &str, w| Config {
+            timezone: Some(timezone.to_owned()),
+            windows: vec![w],
+        };
+        assert!(Blackout::new(&config("Mars/Olympus", window("09:00", "10:00", &[]))).is_err());
+        assert!(Blackout::new(&config("UTC", window("9am", "10:00", &[]))).is_err());
+        assert!(Blackout::new(&config("UTC", window("09:00", "10:00", &["Caturday"]))).is_err());
+        assert!(Blackout::new(&Config {
+            timezone: None,
+            windows: vec![],
+        })
+        .is_err());
+    }
+}
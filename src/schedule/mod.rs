@@ -0,0 +1,716 @@
+pub(super) mod adaptive;
+pub(super) mod blackout;
+
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::time::{self, Instant};
+use tracing::{debug, error, info, trace};
+
+use self::{adaptive::Adaptive, blackout::Blackout};
+use crate::measure::Measurement;
+
+/// Configuration for the `Scheduler`; flattened into the top level of the configuration file.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// A fixed period between the beginnings of consecutive rounds.
+    #[serde(default, with = "humantime_serde", alias = "Period")]
+    period: Option<Duration>,
+    /// Cron expressions, any of which firing begins a new round (alternative to `period`).
+    #[serde(default, alias = "Schedule")]
+    schedule: Vec<String>,
+    /// An explicit limit on the duration of each round.
+    #[serde(default, with = "humantime_serde", alias = "Timeout")]
+    timeout: Option<Duration>,
+    /// An upper bound for the random delay added to the beginning of each round.
+    #[serde(default, with = "humantime_serde", alias = "Splay")]
+    splay: Option<Duration>,
+    /// Whether to delay the first round by an offset in `[0, period)` derived from the hostname.
+    #[serde(default)]
+    hostname_offset: bool,
+    /// Windows of time during which rounds are skipped.
+    #[serde(alias = "Blackout")]
+    blackout: Option<blackout::Config>,
+    /// Rules to shorten the period after degraded or failed rounds, and to lengthen it back.
+    #[serde(alias = "Adaptive")]
+    adaptive: Option<adaptive::Config>,
+}
+
+impl Config {
+    /// Whether rounds are configured to be scheduled at all.
+    pub(crate) fn is_configured(&self) -> bool {
+        self.period.is_some() || !self.schedule.is_empty()
+    }
+}
+
+/// The state of the `Scheduler`, as shared with other actors (e.g., to serve it over HTTP).
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct Status {
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub(crate) period: Option<Duration>,
+    /// The period that rounds are currently scheduled on, which may be shorter than the
+    /// configured one if it is adaptive.
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub(crate) effective_period: Option<Duration>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) schedule: Vec<String>,
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub(crate) timeout: Option<Duration>,
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub(crate) splay: Option<Duration>,
+    /// The hostname-derived offset the first round has been delayed by, if any.
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub(crate) startup_offset: Option<Duration>,
+    /// The random delay chosen for the next round, if splay is configured.
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub(crate) next_splay: Option<Duration>,
+    /// When the next round is due.
+    pub(crate) next_round: Option<DateTime<Local>>,
+    /// When the next round that is not skipped due to a blackout window is due; this is only an
+    /// estimate if splay is configured, as the splay of rounds other than the next one is not
+    /// known in advance.
+    pub(crate) next_measurement: Option<DateTime<Local>>,
+    /// Whether a blackout window is in effect (as of the time `current` was called).
+    pub(crate) in_blackout: bool,
+    /// When the blackout window in effect (as of the time `current` was called) ends.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) blackout_until: Option<DateTime<Local>>,
+    /// Whether scheduled rounds are paused.
+    pub(crate) paused: bool,
+    /// When the latest round (whether scheduled or on-demand) was over.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) last_round: Option<DateTime<Local>>,
+    /// The outcome of the latest round.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) last_outcome: Option<String>,
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    #[serde(skip)]
+    pub(crate) blackout: Option<Blackout>,
+}
+
+impl Status {
+    /// Returns a copy of the status, with the blackout window information brought up to date.
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub(crate) fn current(&self) -> Self {
+        let blackout_until = self
+            .blackout
+            .as_ref()
+            .and_then(|blackout| blackout.window_end(Local::now()));
+        Self {
+            in_blackout: blackout_until.is_some(),
+            blackout_until,
+            ..self.clone()
+        }
+    }
+}
+
+/// Decides when each new round of measuring and exporting should begin, and by when it should
+/// be over.
+///
+/// Rounds are nominally scheduled either on a fixed period (starting right away, unless the
+/// first one is delayed by the hostname-derived offset) or by cron expressions. If a splay is
+/// configured, each round begins after an extra random delay in `[0, splay)`. Such delays are
+/// never accumulated: the nominal schedule is unaffected by them, so on a fixed period `p` with
+/// splay `s` (which must be shorter than `p`), the interval between the beginnings of any two
+/// consecutive rounds is always within `(p - s, p + s)`.
+///
+/// If the period is adaptive, it is shortened (down to a floor, which must be longer than any
+/// splay) after degraded or failed rounds, and lengthened back once results recover.
+#[derive(Debug)]
+pub(crate) struct Scheduler {
+    kind: Kind,
+    /// An explicit limit on the duration of each round; if absent, each round may last until the
+    /// next one is scheduled to begin.
+    timeout: Option<Duration>,
+    splay: Option<Duration>,
+    blackout: Option<Blackout>,
+    adaptive: Option<Adaptive>,
+    /// Whether scheduled rounds are paused; the `Scheduler` keeps ticking regardless.
+    paused: bool,
+    /// When the latest round was nominally scheduled to begin.
+    last: Instant,
+    /// When the next round is scheduled to begin.
+    next: Fire,
+    status: Arc<Mutex<Status>>,
+}
+
+#[derive(Debug)]
+enum Kind {
+    /// Rounds begin on a fixed period.
+    Period(Duration),
+    /// Rounds begin whenever any of the cron schedules fires, in the local timezone.
+    Cron(Vec<cron::Schedule>),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fire {
+    /// When the round is nominally scheduled to begin.
+    nominal: Instant,
+    /// When the round actually begins, i.e., after the random splay.
+    at: Instant,
+}
+
+impl Scheduler {
+    /// How many consecutive rounds to look ahead for one outside any blackout window.
+    const BLACKOUT_LOOKAHEAD: usize = 1024;
+    /// The minimum duration of on-demand rounds, unless an explicit timeout is configured.
+    const MIN_ON_DEMAND_DURATION: Duration = Duration::from_secs(120);
+
+    #[tracing::instrument]
+    pub(crate) fn new(config: &Config) -> Result<Self> {
+        trace!("Creating new '{}'", std::any::type_name::<Self>());
+        let kind = match (config.period, config.schedule.is_empty()) {
+            (Some(period), true) => {
+                if period == Duration::ZERO {
+                    bail!("the configured period must be non-zero");
+                }
+                Kind::Period(period)
+            }
+            (None, false) => Kind::Cron(
+                config
+                    .schedule
+                    .iter()
+                    .map(|expr| {
+                        cron::Schedule::from_str(expr)
+                            .with_context(|| format!("invalid cron expression '{}'", expr))
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            (Some(_), false) => bail!("only one of 'period' and 'schedule' may be configured"),
+            (None, true) => bail!("either a 'period' or a 'schedule' must be configured"),
+        };
+        if config.timeout == Some(Duration::ZERO) {
+            bail!("the configured timeout must be non-zero");
+        }
+        let splay = config.splay.filter(|splay| *splay > Duration::ZERO);
+        if let (Kind::Period(period), Some(splay)) = (&kind, splay) {
+            if splay >= *period {
+                bail!("the configured splay must be shorter than the period");
+            }
+        }
+
+        let adaptive = match (&kind, config.adaptive.as_ref()) {
+            (_, None) => None,
+            (Kind::Period(period), Some(ac)) => Some(Adaptive::new(ac, *period)?),
+            (Kind::Cron(_), Some(_)) => bail!("an adaptive period requires a 'period'"),
+        };
+        if let (Some(adaptive), Some(splay)) = (&adaptive, splay) {
+            if splay >= adaptive.floor() {
+                bail!("the configured splay must be shorter than the adaptive floor");
+            }
+        }
+
+        let startup_offset = match (&kind, config.hostname_offset) {
+            (_, false) => None,
+            (Kind::Period(period), true) => Some(hostname_offset(*period)?),
+            (Kind::Cron(_), true) => bail!("'hostname_offset' requires a 'period'"),
+        };
+        let blackout = config.blackout.as_ref().map(Blackout::new).transpose()?;
+        let nominal = match kind {
+            Kind::Period(_) => Instant::now() + startup_offset.unwrap_or_default(),
+            Kind::Cron(ref schedules) => Self::next_cron_fire(schedules, Local::now()),
+        };
+        info!(
+            "Scheduling with startup offset {} and splay {}",
+            humantime::format_duration(startup_offset.unwrap_or_default()),
+            humantime::format_duration(splay.unwrap_or_default()),
+        );
+
+        let status = Arc::new(Mutex::new(Status {
+            period: config.period,
+            effective_period: config.period,
+            schedule: config.schedule.clone(),
+            timeout: config.timeout,
+            splay,
+            startup_offset,
+            blackout: blackout.clone(),
+            ..Default::default()
+        }));
+        let mut ret = Self {
+            kind,
+            timeout: config.timeout,
+            splay,
+            blackout,
+            adaptive,
+            paused: false,
+            last: nominal,
+            next: Fire {
+                nominal,
+                at: nominal,
+            },
+            status,
+        };
+        ret.next = ret.splayed(nominal);
+        Ok(ret)
+    }
+
+    /// A handle to the state of the `Scheduler`, which is kept up to date as rounds go by.
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub(crate) fn status(&self) -> Arc<Mutex<Status>> {
+        self.status.clone()
+    }
+
+    /// Waits until the next round is scheduled to begin, and returns that instant.
+    ///
+    /// If the previous round has overrun the scheduled beginning of this one, this one begins
+    /// right away, but any other scheduled beginnings that have also been missed are skipped.
+    pub(crate) async fn tick(&mut self) -> Instant {
+        time::sleep_until(self.next.at).await;
+        let fire = self.next.at;
+        self.last = self.next.nominal;
+        self.next = self.splayed(self.following(self.last));
+        debug!(
+            "Next round is scheduled in {}",
+            humantime::format_duration(self.next.at.saturating_duration_since(Instant::now()))
+        );
+        fire
+    }
+
+    /// Adapts the period to the outcome of the latest round, if it is adaptive, rescheduling the
+    /// next round accordingly.
+    pub(crate) fn observe(&mut self, measurement: &Measurement) {
+        let period = match self.adaptive {
+            Some(ref mut adaptive) => match adaptive.observe(measurement) {
+                Some(period) => period,
+                None => return,
+            },
+            None => return,
+        };
+        self.next = self.splayed(self.following(self.last));
+        match self.status.lock() {
+            Ok(mut status) => status.effective_period = Some(period),
+            Err(e) => error!("Failed to acquire scheduler status lock: {}", e),
+        }
+    }
+
+    /// Reschedules rounds on the given period, instead of the configured one; if it is adaptive,
+    /// the period is reset to the given one, which then becomes the longest it may grow back to.
+    pub(crate) fn set_period(&mut self, period: Duration) -> Result<()> {
+        if let Kind::Cron(_) = self.kind {
+            bail!("rounds are scheduled through cron expressions, not on a period");
+        }
+        if period == Duration::ZERO {
+            bail!("the period must be non-zero");
+        }
+        if matches!(self.splay, Some(splay) if splay >= period) {
+            bail!("the period must be longer than the configured splay");
+        }
+        if let Some(ref mut adaptive) = self.adaptive {
+            adaptive.reconfigure(period)?;
+        }
+        self.kind = Kind::Period(period);
+        info!(
+            "Rescheduling on a period of {}",
+            humantime::format_duration(period)
+        );
+        self.next = self.splayed(self.following(self.last));
+        match self.status.lock() {
+            Ok(mut status) => {
+                status.period = Some(period);
+                status.effective_period = Some(period);
+            }
+            Err(e) => error!("Failed to acquire scheduler status lock: {}", e),
+        }
+        Ok(())
+    }
+
+    /// Applies a new configuration; rounds on a period keep their phase, and whether they are
+    /// paused, along with the outcome of the latest one, is retained.
+    pub(crate) fn reconfigure(&mut self, config: &Config) -> Result<()> {
+        let mut new = Self::new(config)?;
+        if let (Kind::Period(_), Kind::Period(_)) = (&self.kind, &new.kind) {
+            new.last = self.last;
+            new.next = new.splayed(new.following(new.last));
+        }
+        new.paused = self.paused;
+        let status = new.status.lock().map(|status| status.clone());
+        match (self.status.lock(), status) {
+            (Ok(mut old), Ok(status)) => {
+                *old = Status {
+                    paused: old.paused,
+                    last_round: old.last_round,
+                    last_outcome: old.last_outcome.take(),
+                    ..status
+                }
+            }
+            (Err(e), _) => error!("Failed to acquire scheduler status lock: {}", e),
+            (_, Err(e)) => error!("Failed to acquire scheduler status lock: {}", e),
+        }
+        new.status = self.status.clone();
+        *self = new;
+        Ok(())
+    }
+
+    /// Whether scheduled rounds are paused.
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses or resumes scheduled rounds.
+    pub(crate) fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        match self.status.lock() {
+            Ok(mut status) => status.paused = paused,
+            Err(e) => error!("Failed to acquire scheduler status lock: {}", e),
+        }
+    }
+
+    /// Publishes the outcome of the latest round (whether scheduled or on-demand).
+    pub(crate) fn record(&self, outcome: String) {
+        match self.status.lock() {
+            Ok(mut status) => {
+                status.last_round = Some(Local::now());
+                status.last_outcome = Some(outcome);
+            }
+            Err(e) => error!("Failed to acquire scheduler status lock: {}", e),
+        }
+    }
+
+    /// If a blackout window is currently in effect, returns when it ends.
+    pub(crate) fn blackout_until(&self) -> Option<DateTime<Local>> {
+        self.blackout
+            .as_ref()
+            .and_then(|blackout| blackout.window_end(Local::now()))
+    }
+
+    /// The instant by which the round that began at `start` should be over.
+    pub(crate) fn deadline(&self, start: Instant) -> Instant {
+        match self.timeout {
+            Some(timeout) => start + timeout,
+            None => self.next.at,
+        }
+    }
+
+    /// The instant by which the on-demand round that began at `start` should be over; unless an
+    /// explicit timeout is configured, it may last until the next round is scheduled to begin,
+    /// but no less than `MIN_ON_DEMAND_DURATION`.
+    pub(crate) fn on_demand_deadline(&self, start: Instant) -> Instant {
+        match self.timeout {
+            Some(timeout) => start + timeout,
+            None => self.next.at.max(start + Self::MIN_ON_DEMAND_DURATION),
+        }
+    }
+
+    /// Returns the first nominal beginning of a round after `nominal` that is not in the past.
+    fn following(&self, nominal: Instant) -> Instant {
+        let now = Instant::now();
+        match self.kind {
+            Kind::Period(period) => {
+                let period = self.adaptive.as_ref().map_or(period, Adaptive::period);
+                let mut next = nominal + period;
+                while next < now {
+                    next += period;
+                }
+                next
+            }
+            Kind::Cron(ref schedules) => {
+                let after = Local::now() + chrono_duration(nominal.saturating_duration_since(now));
+                Self::next_cron_fire(schedules, after)
+            }
+        }
+    }
+
+    /// Draws a random splay for the round nominally beginning at `nominal`, and publishes it.
+    fn splayed(&self, nominal: Instant) -> Fire {
+        let splay = self
+            .splay
+            .map(|splay| rand::thread_rng().gen_range(Duration::ZERO..splay));
+        let at = nominal + splay.unwrap_or_default();
+        if let Some(splay) = splay {
+            debug!(
+                "Next round is splayed by {}",
+                humantime::format_duration(splay)
+            );
+        }
+        let next_round = wall_clock(at);
+        let next_measurement = self.next_measurement(wall_clock(nominal), next_round);
+        match self.status.lock() {
+            Ok(mut status) => {
+                status.next_splay = splay;
+                status.next_round = Some(next_round);
+                status.next_measurement = next_measurement;
+            }
+            Err(e) => error!("Failed to acquire scheduler status lock: {}", e),
+        }
+        Fire { nominal, at }
+    }
+
+    /// Looks ahead for the first round (beginning with the one nominally scheduled at `nominal`
+    /// and actually beginning `at`) that does not fall within any blackout window.
+    fn next_measurement(
+        &self,
+        nominal: DateTime<Local>,
+        at: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        let blackout = match self.blackout {
+            Some(ref blackout) => blackout,
+            None => return Some(at),
+        };
+        let mut at = at;
+        for _ in 0..Self::BLACKOUT_LOOKAHEAD {
+            let end = match blackout.window_end(at) {
+                Some(end) => end,
+                None => return Some(at),
+            };
+            at = match self.kind {
+                Kind::Period(period) => {
+                    let period = self.adaptive.as_ref().map_or(period, Adaptive::period);
+                    // The first nominal beginning of a round at or after the end of the window
+                    let gap = (end - nominal).to_std().unwrap_or_default();
+                    let periods = gap.as_nanos() / period.as_nanos();
+                    let next = nominal
+                        + chrono_duration(Duration::from_nanos(
+                            (periods * period.as_nanos()) as u64,
+                        ));
+                    if next < end {
+                        next + chrono_duration(period)
+                    } else {
+                        next
+                    }
+                }
+                Kind::Cron(ref schedules) => {
+                    Self::cron_after(schedules, end - chrono::Duration::nanoseconds(1))
+                }
+            };
+        }
+        None
+    }
+
+    fn next_cron_fire(schedules: &[cron::Schedule], after: DateTime<Local>) -> Instant {
+        let next = Self::cron_after(schedules, after);
+        Instant::now() + (next - Local::now()).to_std().unwrap_or_default()
+    }
+
+    fn cron_after(schedules: &[cron::Schedule], after: DateTime<Local>) -> DateTime<Local> {
+        schedules
+            .iter()
+            .filter_map(|schedule| schedule.after(&after).next())
+            .min()
+            // NOTE: All cron expressions may be exhausted (e.g., ones bound to past years).
+            .unwrap_or_else(|| Local::now() + chrono::Duration::weeks(52 * 100))
+    }
+}
+
+/// Derives an offset in `[0, period)` from the hostname, so that a fleet of hosts sharing the
+/// same configuration spread their rounds over the period, rather than all of them measuring at
+/// once. The same hostname always results in the same offset.
+fn hostname_offset(period: Duration) -> Result<Duration> {
+    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .with_context(|| "failed to retrieve the hostname")?;
+    let hostname = hostname.trim();
+    let offset = Duration::from_nanos(fnv1a(hostname.as_bytes()) % period.as_nanos() as u64);
+    info!(
+        "Derived startup offset {} from hostname '{}'",
+        humantime::format_duration(offset),
+        hostname
+    );
+    Ok(offset)
+}
+
+/// 64-bit FNV-1a, which (unlike `std`'s `DefaultHasher`) is stable across releases and platforms.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Approximates the wall-clock time corresponding to the given `Instant`.
+fn wall_clock(at: Instant) -> DateTime<Local> {
+    let now = Instant::now();
+    if at >= now {
+        Local::now() + chrono_duration(at - now)
+    } else {
+        Local::now() - chrono_duration(now - at)
+    }
+}
+
+fn chrono_duration(d: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(d).unwrap_or_else(|_| chrono::Duration::zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn config(period: Option<u64>, schedule: &[&str], splay: Option<u64>) -> Config {
+        Config {
+            period: period.map(Duration::from_secs),
+            schedule: schedule.iter().map(|s| s.to_string()).collect(),
+            timeout: None,
+            splay: splay.map(Duration::from_secs),
+            hostname_offset: false,
+            blackout: None,
+            adaptive: None,
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Scheduler::new(&config(None, &[], None)).is_err());
+        assert!(Scheduler::new(&config(Some(0), &[], None)).is_err());
+        assert!(Scheduler::new(&config(Some(60), &["0 * * * * *"], None)).is_err());
+        assert!(Scheduler::new(&config(None, &["every minute"], None)).is_err());
+        assert!(Scheduler::new(&config(Some(60), &[], Some(60))).is_err());
+    }
+
+    #[test]
+    fn fnv() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn period() -> Result<()> {
+        let period = Duration::from_secs(600);
+        let mut s = Scheduler::new(&config(Some(600), &[], None))?;
+
+        let first = s.tick().await;
+        assert_eq!(s.deadline(first), first + period);
+        let second = s.tick().await;
+        assert_eq!(second, first + period);
+
+        // A round overrunning the next one delays it, but skips any other missed ones
+        time::sleep(period * 2 + Duration::from_secs(1)).await;
+        let third = s.tick().await;
+        assert_eq!(third, second + period);
+        assert_eq!(s.next.at, second + period * 3);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn set_period() -> Result<()> {
+        let mut s = Scheduler::new(&config(Some(600), &[], Some(60)))?;
+        let first = s.tick().await;
+        assert!(s.set_period(Duration::from_secs(60)).is_err());
+        s.set_period(Duration::from_secs(120))?;
+        assert_eq!(s.next.nominal, s.last + Duration::from_secs(120));
+        let second = s.tick().await;
+        assert!(second - first > Duration::from_secs(120 - 60));
+        assert_eq!(
+            s.status().lock().unwrap().period,
+            Some(Duration::from_secs(120))
+        );
+
+        let mut s = Scheduler::new(&config(None, &["0 0 * * * *"], None))?;
+        assert!(s.set_period(Duration::from_secs(120)).is_err());
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconfigure() -> Result<()> {
+        let mut s = Scheduler::new(&config(Some(600), &[], None))?;
+        let status = s.status();
+        let first = s.tick().await;
+        s.set_paused(true);
+        assert!(s.reconfigure(&config(Some(60), &[], Some(60))).is_err());
+        s.reconfigure(&config(Some(300), &[], None))?;
+        assert_eq!(s.next.at, first + Duration::from_secs(300));
+        assert!(s.is_paused());
+        assert!(Arc::ptr_eq(&status, &s.status()));
+        assert_eq!(
+            status.lock().unwrap().period,
+            Some(Duration::from_secs(300))
+        );
+        assert!(status.lock().unwrap().paused);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn splay() -> Result<()> {
+        let (period, splay) = (Duration::from_secs(600), Duration::from_secs(60));
+        let origin = Instant::now();
+        let mut s = Scheduler::new(&config(Some(600), &[], Some(60)))?;
+
+        let mut prev = s.tick().await;
+        for round in 1..100 {
+            let fire = s.tick().await;
+            assert!(fire - prev > period - splay && fire - prev < period + splay);
+            // Splay never accumulates
+            let nominal = origin + period * round;
+            assert!(fire >= nominal && fire < nominal + splay);
+            prev = fire;
+        }
+        let status = s.status();
+        let status = status.lock().unwrap();
+        assert_eq!(status.splay, Some(splay));
+        assert!(status.next_splay.unwrap() < splay);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cron() -> Result<()> {
+        let mut c = config(None, &["0 */15 9-17 * * Mon-Fri", "0 0 * * * *"], None);
+        c.timeout = Some(Duration::from_secs(30));
+        let mut s = Scheduler::new(&c)?;
+        assert_eq!(s.status().lock().unwrap().period, None);
+
+        let before = Instant::now();
+        let next = s.next.at;
+        assert!(next > before && next <= before + Duration::from_secs(3600));
+        let start = s.tick().await;
+        assert_eq!(start, next);
+        assert_eq!(s.deadline(start), start + Duration::from_secs(30));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn adaptive() -> Result<()> {
+        let mut c = config(Some(600), &[], None);
+        c.adaptive = Some(serde_json::from_value(
+            serde_json::json!({ "floor": "1m", "recover_after": 1 }),
+        )?);
+        let mut s = Scheduler::new(&c)?;
+
+        let first = s.tick().await;
+        assert_eq!(s.next.at, first + Duration::from_secs(600));
+        s.observe(&Default::default());
+        assert_eq!(s.next.at, first + Duration::from_secs(300));
+        let second = s.tick().await;
+        s.observe(&(10., 90., 9.).into());
+        assert_eq!(s.next.at, second + Duration::from_secs(600));
+        assert_eq!(
+            s.status().lock().unwrap().effective_period,
+            Some(Duration::from_secs(600))
+        );
+
+        c.splay = Some(Duration::from_secs(60));
+        assert!(Scheduler::new(&c).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn blackout() -> Result<()> {
+        let mut c = config(Some(1800), &[], None);
+        c.blackout = Some(serde_json::from_value(serde_json::json!({
+            "timezone": "UTC",
+            "windows": [
+                { "start": "00:00", "end": "06:00" },
+                { "start": "05:30", "end": "07:00", "days": ["Mon"] },
+            ],
+        }))?);
+        let s = Scheduler::new(&c)?;
+        let at = |day, hour, min| {
+            let naive = chrono::NaiveDate::from_ymd_opt(2021, 9, day)
+                .and_then(|date| date.and_hms_opt(hour, min, 0))
+                .unwrap();
+            chrono::Utc.from_utc_datetime(&naive).with_timezone(&Local)
+        };
+
+        let start = at(19, 4, 10);
+        assert_eq!(s.next_measurement(start, start), Some(at(19, 6, 10)));
+        // Overlapping windows are chained
+        let start = at(20, 4, 10);
+        assert_eq!(s.next_measurement(start, start), Some(at(20, 7, 10)));
+        assert_eq!(s.next_measurement(start, at(20, 7, 1)), Some(at(20, 7, 1)));
+        Ok(())
+    }
+}
@@ -0,0 +1,553 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    task::{JoinError, JoinHandle},
+    time::{self, Instant},
+};
+use tracing::{debug, error, info, trace, warn};
+
+#[cfg(feature = "plot")]
+use crate::exporters::database::plotter::PLOT_FILE_NAME;
+use crate::{
+    config::Config,
+    exporters::{
+        self,
+        database::{self, Database},
+        Instance,
+    },
+    measure::Measurement,
+    probe,
+};
+
+/// The health of all supervised actors, by name, kept up to date as they die and are restarted.
+pub(crate) type Registry = Arc<Mutex<BTreeMap<String, Health>>>;
+
+/// The health of a supervised actor (i.e., the Database or an exporter).
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Health {
+    pub(crate) state: State,
+    /// When the actor entered its current state.
+    pub(crate) since: DateTime<Local>,
+    /// How many times the actor has been restarted after dying.
+    pub(crate) restarts: u64,
+    /// Why the actor died (or failed to be restarted) most recently, if it ever did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) last_failure: Option<String>,
+    /// When the actor is to be restarted, if it is dead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) next_restart: Option<DateTime<Local>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum State {
+    Running,
+    /// Dead, waiting to be restarted.
+    Restarting,
+}
+
+/// Something that the `Supervisor` has to act upon.
+#[derive(Debug)]
+pub(crate) enum Event {
+    /// The named actor died, with the given result.
+    Died(String, Result<(), JoinError>),
+    /// Dead actors are due to be restarted.
+    RestartDue,
+}
+
+/// Exponentially growing delays between consecutive restarts of an actor that keeps dying.
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    next: Duration,
+}
+
+impl Backoff {
+    const INITIAL: Duration = Duration::from_secs(1);
+    const MAX: Duration = Duration::from_secs(5 * 60);
+
+    /// Returns the delay before the next restart, given how long the actor had been running for
+    /// before dying; having run for long enough, it starts over from the initial delay.
+    fn delay(&mut self, uptime: Duration) -> Duration {
+        if uptime >= Self::MAX {
+            self.next = Self::INITIAL;
+        }
+        let delay = self.next;
+        self.next = (self.next * 2).min(Self::MAX);
+        delay
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            next: Self::INITIAL,
+        }
+    }
+}
+
+/// A spawned actor (i.e., the Database or an exporter).
+struct Actor {
+    /// Sending end of the `watch` (spmc) channel to signal the actor to gracefully terminate.
+    quit: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+    started: Instant,
+}
+
+impl Actor {
+    fn spawn<F>(quit: watch::Sender<bool>, actor: F) -> Self
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        Self {
+            quit,
+            handle: tokio::spawn(actor),
+            started: Instant::now(),
+        }
+    }
+
+    /// Signals the actor to gracefully terminate, and waits for it to.
+    async fn stop(self) {
+        // The actor may already be over, hence not listening anymore
+        let _ = self.quit.send(true);
+        if let Err(e) = self.handle.await {
+            error!("Actor task failed: {}", e);
+        }
+    }
+}
+
+/// Spawns the Database and the exporters, as configured, and restarts (with backoff, out of the
+/// same configuration) any of them that dies (i.e., panics or returns) without being signalled to
+/// terminate.
+pub(crate) struct Supervisor {
+    /// The configuration of the Database, if any.
+    db_config: Option<database::Config>,
+    /// The configured exporters, by name.
+    instances: BTreeMap<String, Instance>,
+    /// The Database and exporter actors that are running, by name.
+    actors: BTreeMap<String, Actor>,
+    /// The dead actors, along with when they are to be restarted.
+    dead: BTreeMap<String, Instant>,
+    backoffs: BTreeMap<String, Backoff>,
+    health: Registry,
+    /// The `Handle`s of all running probes, for exporters to control them (e.g., over HTTP).
+    probes: probe::Registry,
+    /// Sending end of a `mpsc` channel to send measurements to the Database, if it is configured;
+    /// it is replaced whenever the Database is restarted.
+    db_tx: Option<mpsc::Sender<database::SyncMessage>>,
+    /// Sending ends of the channels to broadcast measurements to the exporters, which outlive
+    /// them, so that restarted exporters resubscribe to the same channels; they are also kept
+    /// alive so that exporters do not find them closed once all probes are over, before being
+    /// signalled to quit.
+    exporters: BTreeMap<String, broadcast::Sender<Measurement>>,
+}
+
+impl Supervisor {
+    const MEASUREMENTS_CHANNEL_CAPACITY: usize = 1024;
+    /// The name of the Database, which exporters may not be named after.
+    pub(crate) const DATABASE: &'static str = "database";
+
+    /// Spawns the Database and all exporters that are configured; those that fail to start (e.g.,
+    /// an exporter that fails to connect to its server) are retried with backoff.
+    #[tracing::instrument(skip(config, probes))]
+    pub(crate) async fn new(config: &Config, probes: probe::Registry) -> Result<Self> {
+        trace!("Creating new '{}'", std::any::type_name::<Self>());
+        let mut ret = Self {
+            db_config: config.db_config.clone(),
+            instances: config.exporters()?,
+            actors: BTreeMap::new(),
+            dead: BTreeMap::new(),
+            backoffs: BTreeMap::new(),
+            health: Registry::default(),
+            probes,
+            db_tx: None,
+            exporters: BTreeMap::new(),
+        };
+        for name in ret.names() {
+            if let Err(e) = ret.start(&name).await {
+                error!("Failed to start the '{}' actor: {:#}", name, e);
+                ret.schedule_restart(&name, Duration::ZERO, format!("{:#}", e));
+            }
+        }
+        Ok(ret)
+    }
+
+    /// The sending ends of the channels to the Database and the exporters that are configured.
+    pub(crate) fn senders(
+        &self,
+    ) -> (
+        Option<&mpsc::Sender<database::SyncMessage>>,
+        &BTreeMap<String, broadcast::Sender<Measurement>>,
+    ) {
+        (self.db_tx.as_ref(), &self.exporters)
+    }
+
+    /// The names of all actors that are configured, starting with the Database.
+    fn names(&self) -> Vec<String> {
+        let database = self.db_config.as_ref().map(|_| Self::DATABASE.to_owned());
+        database
+            .into_iter()
+            .chain(self.instances.keys().cloned())
+            .collect()
+    }
+
+    /// Starts the named actor, if it is configured, and marks it as healthy.
+    async fn start(&mut self, name: &str) -> Result<()> {
+        let actor = match name {
+            Self::DATABASE => match self.db_config {
+                Some(ref dc) => {
+                    let (db_tx, actor) = Self::spawn_database(dc)?;
+                    self.db_tx = Some(db_tx);
+                    actor
+                }
+                None => return Ok(()),
+            },
+            name => match self.instances.get(name) {
+                Some(instance) => {
+                    let rx = self
+                        .exporters
+                        .entry(name.to_owned())
+                        .or_insert_with(|| {
+                            broadcast::channel(Self::MEASUREMENTS_CHANNEL_CAPACITY).0
+                        })
+                        .subscribe();
+                    self.spawn_exporter(name, instance, rx).await?
+                }
+                None => return Ok(()),
+            },
+        };
+        self.actors.insert(name.to_owned(), actor);
+        self.dead.remove(name);
+        self.update(name, |health| {
+            health.state = State::Running;
+            health.since = Local::now();
+            health.next_restart = None;
+        });
+        Ok(())
+    }
+
+    /// Stops the named actor, if it is running, and forgets about it.
+    async fn stop(&mut self, name: &str) {
+        if let Some(actor) = self.actors.remove(name) {
+            info!("Stopping the '{}' actor...", name);
+            actor.stop().await;
+        }
+        self.dead.remove(name);
+        self.backoffs.remove(name);
+        match self.health.lock() {
+            Ok(mut health) => {
+                health.remove(name);
+            }
+            Err(e) => error!("Failed to acquire lock for actor health: {}", e),
+        }
+        if name == Self::DATABASE {
+            self.db_tx = None;
+        } else {
+            self.exporters.remove(name);
+        }
+    }
+
+    fn update<F: FnOnce(&mut Health)>(&self, name: &str, f: F) {
+        match self.health.lock() {
+            Ok(mut health) => f(health.entry(name.to_owned()).or_insert_with(|| Health {
+                state: State::Running,
+                since: Local::now(),
+                restarts: 0,
+                last_failure: None,
+                next_restart: None,
+            })),
+            Err(e) => error!("Failed to acquire lock for actor health: {}", e),
+        }
+    }
+
+    /// Applies a reloaded configuration, restarting only the actors whose configuration changed,
+    /// along with any that are dead; those that fail to be restarted are retried with backoff.
+    /// It fails, without applying anything, if the exporters are misconfigured.
+    #[tracing::instrument(skip(self, config))]
+    pub(crate) async fn reload(&mut self, config: &Config) -> Result<()> {
+        let instances = config.exporters()?;
+        let old_plot_path = self.plot_path();
+        let old_db_config = std::mem::replace(&mut self.db_config, config.db_config.clone());
+        let old_instances = std::mem::replace(&mut self.instances, instances);
+        let mut names = self.names();
+        names.extend(old_instances.keys().cloned());
+        if old_db_config.is_some() {
+            names.push(Self::DATABASE.to_owned());
+        }
+        names.sort();
+        names.dedup();
+        // Exporters are restarted if the plot moved, as they are handed its path upon creation
+        let plot_moved = old_plot_path != self.plot_path();
+        for name in names {
+            let running = self.actors.contains_key(&name);
+            let (reconfigured, configured) = match name.as_str() {
+                Self::DATABASE => (old_db_config != self.db_config, self.db_config.is_some()),
+                name => (
+                    old_instances.get(name) != self.instances.get(name)
+                        || (plot_moved && self.instances.contains_key(name)),
+                    self.instances.contains_key(name),
+                ),
+            };
+            if !reconfigured && running == configured {
+                continue;
+            }
+            // The broadcast channel outlives the exporter, unless the latter is removed
+            let tx = self.exporters.get(&name).cloned();
+            self.stop(&name).await;
+            if let Some(tx) = tx.filter(|_| configured) {
+                self.exporters.insert(name.clone(), tx);
+            }
+            match self.start(&name).await {
+                Ok(()) if self.actors.contains_key(&name) => info!("Started the '{}' actor", name),
+                Ok(()) => {}
+                Err(e) => {
+                    error!("Failed to restart the '{}' actor: {:#}", name, e);
+                    self.schedule_restart(&name, Duration::ZERO, format!("{:#}", e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until a supervised actor dies, or until a dead one is due to be restarted. It never
+    /// returns while no actor is running or dead.
+    ///
+    /// It only waits, without acting upon anything, so that it may be cancelled (e.g., in favour of
+    /// another branch of a `select!`) and called anew at any time; restarts are due at fixed
+    /// instants, so waiting anew does not postpone them.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn wait(&mut self) -> Event {
+        let next_restart = self.dead.values().min().copied();
+        let names: Vec<_> = self.actors.keys().cloned().collect();
+        let handles = self.actors.values_mut().map(|actor| &mut actor.handle);
+        let died = async {
+            match names.is_empty() {
+                true => futures::future::pending().await,
+                false => futures::future::select_all(handles).await,
+            }
+        };
+        let restart = async {
+            match next_restart {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => futures::future::pending().await,
+            }
+        };
+        tokio::select! {
+            (result, index, _) = died => Event::Died(names[index].clone(), result),
+            _ = restart => Event::RestartDue,
+        }
+    }
+
+    /// Acts upon the given event: an actor that died is scheduled to be restarted, whereas dead
+    /// actors that are due are restarted out of their current configuration. Returns whether the
+    /// Database has been restarted, in which case the probes need to be routed to it anew.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn supervise(&mut self, event: Event) -> bool {
+        match event {
+            Event::Died(name, result) => {
+                self.bury(&name, result);
+                false
+            }
+            Event::RestartDue => {
+                let now = Instant::now();
+                let due: Vec<_> = self
+                    .dead
+                    .iter()
+                    .filter(|(_, at)| **at <= now)
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                let mut restarted_database = false;
+                for name in due {
+                    match self.start(&name).await {
+                        Ok(()) => {
+                            info!("Restarted the '{}' actor", name);
+                            self.update(&name, |health| health.restarts += 1);
+                            restarted_database |= name == Self::DATABASE;
+                        }
+                        Err(e) => {
+                            error!("Failed to restart the '{}' actor: {:#}", name, e);
+                            self.schedule_restart(&name, Duration::ZERO, format!("{:#}", e));
+                        }
+                    }
+                }
+                restarted_database
+            }
+        }
+    }
+
+    /// Records that the named actor died, and schedules it to be restarted.
+    fn bury(&mut self, name: &str, result: Result<(), JoinError>) {
+        let actor = self
+            .actors
+            .remove(name)
+            .expect("a supervised actor died without being running");
+        let reason = match result {
+            Ok(()) => "exited unexpectedly".to_owned(),
+            Err(e) => format!("failed: {}", e),
+        };
+        warn!("The '{}' actor {}", name, reason);
+        self.schedule_restart(name, actor.started.elapsed(), reason);
+    }
+
+    fn schedule_restart(&mut self, name: &str, uptime: Duration, reason: String) {
+        let delay = self
+            .backoffs
+            .entry(name.to_owned())
+            .or_default()
+            .delay(uptime);
+        info!(
+            "Restarting the '{}' actor in {}",
+            name,
+            humantime::format_duration(delay)
+        );
+        self.dead.insert(name.to_owned(), Instant::now() + delay);
+        self.update(name, |health| {
+            health.state = State::Restarting;
+            health.since = Local::now();
+            health.last_failure = Some(reason);
+            health.next_restart = chrono::Duration::from_std(delay)
+                .ok()
+                .map(|delay| Local::now() + delay);
+        });
+    }
+
+    /// Signals all actors to gracefully terminate, and waits for them to until the given deadline,
+    /// after which any of them that are still running are aborted.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn shutdown(mut self, deadline: Instant) {
+        debug!("Signalling all {} actors to quit...", self.actors.len());
+        for actor in self.actors.values() {
+            // The actor may already be over, hence not listening anymore
+            let _ = actor.quit.send(true);
+        }
+        let handles = self.actors.values_mut().map(|actor| &mut actor.handle);
+        match time::timeout_at(deadline, futures::future::join_all(handles)).await {
+            Ok(results) => {
+                for e in results.into_iter().filter_map(Result::err) {
+                    error!("Actor task failed: {}", e);
+                }
+            }
+            Err(_) => {
+                warn!("Aborting actors that are still running, as the grace period is over");
+                for actor in self.actors.values() {
+                    actor.handle.abort();
+                }
+            }
+        }
+    }
+
+    /// Where the latest plot is stored, if a Database is configured.
+    fn plot_path(&self) -> Option<PathBuf> {
+        self.db_config.as_ref().map(|_c| {
+            #[cfg(feature = "plot")]
+            {
+                PathBuf::from(_c.path()).join(PLOT_FILE_NAME)
+            }
+            #[cfg(not(feature = "plot"))]
+            {
+                PathBuf::new()
+            }
+        })
+    }
+
+    // NOTE: Now that the Database works synchronously with respect to the Monitor, it does not
+    // *have* to be modeled as an actor. FIXME?
+    #[tracing::instrument]
+    fn spawn_database(
+        dc: &database::Config,
+    ) -> Result<(mpsc::Sender<database::SyncMessage>, Actor)> {
+        debug!("Initializing Database exporter...");
+        // A mpsc channel to send measurements to the Database.
+        let (db_tx, db_rx) = mpsc::channel(1);
+        let (quit_tx, quit) = watch::channel(false);
+        let db = Database::new(dc.clone(), db_rx, quit)
+            .with_context(|| "failed to initialize Database exporter")?;
+        Ok((db_tx, Actor::spawn(quit_tx, async move { db.run().await })))
+    }
+
+    #[tracing::instrument(skip(self, rx))]
+    async fn spawn_exporter(
+        &self,
+        name: &str,
+        instance: &Instance,
+        rx: broadcast::Receiver<Measurement>,
+    ) -> Result<Actor> {
+        debug!("Initializing '{}' exporter...", name);
+        let ctx = exporters::Context {
+            name: name.to_owned(),
+            plot_path: self.plot_path(),
+            probes: self.probes.clone(),
+            health: self.health.clone(),
+        };
+        let mut exporter = exporters::new_exporter(instance, &ctx)?;
+        exporter
+            .init()
+            .await
+            .with_context(|| format!("failed to initialize '{}' exporter", name))?;
+        let (quit_tx, quit) = watch::channel(false);
+        let name = name.to_owned();
+        Ok(Actor::spawn(quit_tx, async move {
+            exporters::run(name, exporter, rx, quit).await
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff::default();
+        let delays: Vec<_> = (0..11)
+            .map(|_| backoff.delay(Duration::from_secs(1)).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 64, 128, 256, 300, 300]);
+        // An actor that has been running for long enough starts over
+        assert_eq!(backoff.delay(Backoff::MAX), Backoff::INITIAL);
+        assert_eq!(backoff.delay(Duration::ZERO), Backoff::INITIAL * 2);
+    }
+
+    #[cfg(feature = "http")]
+    #[tokio::test(start_paused = true)]
+    async fn start_failure() {
+        // The HTTP exporter fails to initialize while its address is taken
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "period": "1h",
+            "database": { "kind": "mem", "path": std::env::temp_dir() },
+            "http": { "bind_addr": taken.local_addr().unwrap().to_string() },
+        }))
+        .unwrap();
+        let mut supervisor = Supervisor::new(&config, probe::Registry::default())
+            .await
+            .unwrap();
+        assert!(!supervisor.actors.contains_key("http"));
+        assert!(supervisor.dead.contains_key("http"));
+        assert!(supervisor.senders().1.contains_key("http"));
+        let health = supervisor.health.lock().unwrap()["http"].clone();
+        assert_eq!(health.state, State::Restarting);
+        let failure = health.last_failure.unwrap();
+        assert!(failure.contains("failed to bind"), "{}", failure);
+
+        // ...and is restarted once its address is available
+        drop(taken);
+        let event = supervisor.wait().await;
+        assert!(matches!(event, Event::RestartDue));
+        assert!(!supervisor.supervise(event).await);
+        assert!(supervisor.actors.contains_key("http"));
+        assert!(supervisor.dead.is_empty());
+        let health = supervisor.health.lock().unwrap()["http"].clone();
+        assert_eq!((health.state, health.restarts), (State::Running, 1));
+
+        supervisor.shutdown(Instant::now()).await;
+    }
+}